@@ -0,0 +1,153 @@
+use crate::httpdate::format_clf_date;
+use crate::response::HttpStatusCode;
+use crate::{HttpMethod, Request};
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// What [Server::on_request](crate::Server::on_request) hooks get to know
+/// about an answered request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLog {
+    pub method: HttpMethod,
+    /// The path without the query, see [Request::path_as_str].
+    pub path: String,
+    pub status: HttpStatusCode,
+    /// The time from having read the request to having written the response.
+    pub duration: Duration,
+}
+
+/// The format of the lines written by the access log, see
+/// [Server::enable_access_log](crate::Server::enable_access_log).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The Common Log Format, e.g.
+    /// `127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] "GET / HTTP/1.1" 200 42`.
+    Common,
+    /// The Common Log Format followed by the quoted `referer` and
+    /// `user-agent` of the request, like Apache's combined format.
+    Combined,
+}
+
+/// Writes one line per response to its writer.
+pub struct AccessLog {
+    format: LogFormat,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new<W: Write + Send + 'static>(format: LogFormat, writer: W) -> Self {
+        AccessLog {
+            format,
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Logs a response. `request` is None if the request couldn't be parsed,
+    /// `body_length` is None if the length isn't known up front. Failing to
+    /// write the line doesn't fail the response.
+    pub fn log(
+        &self,
+        peer: Option<IpAddr>,
+        request: Option<&Request>,
+        status: u16,
+        body_length: Option<u64>,
+    ) {
+        let line = format_entry(
+            self.format,
+            peer,
+            request,
+            status,
+            body_length,
+            SystemTime::now(),
+        );
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+        }
+    }
+}
+
+/// Formats a log line. Missing values are logged as `-`, like a body of
+/// zero bytes.
+fn format_entry(
+    format: LogFormat,
+    peer: Option<IpAddr>,
+    request: Option<&Request>,
+    status: u16,
+    body_length: Option<u64>,
+    time: SystemTime,
+) -> String {
+    let peer = peer.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+    let request_line = request
+        .and_then(|req| req.raw_content().lines().next())
+        .map_or_else(|| "-".to_string(), escape);
+    let bytes = match body_length {
+        Some(0) | None => "-".to_string(),
+        Some(length) => length.to_string(),
+    };
+    let mut line = format!(
+        "{} - - [{}] \"{}\" {} {}",
+        peer,
+        format_clf_date(time),
+        request_line,
+        status,
+        bytes
+    );
+    if format == LogFormat::Combined {
+        let header = |name| {
+            request
+                .and_then(|req| req.header(name))
+                .map_or_else(|| "-".to_string(), escape)
+        };
+        line.push_str(&format!(
+            " \"{}\" \"{}\"",
+            header("referer"),
+            header("user-agent")
+        ));
+    }
+    line
+}
+
+/// Escapes quotes, backslashes and control characters, so that values sent
+/// by clients can't break the format.
+fn escape(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c if c.is_control() => format!("\\x{:02x}", c as u32).chars().collect(),
+            c => vec![c],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::utils::parse_request_from_bytes;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_format_entry() {
+        let request = parse_request_from_bytes(
+            b"GET /index.html?page=2 HTTP/1.1\r\nReferer: http://example.com/\r\nUser-Agent: curl/8.0 \"x\"\r\n\r\n"
+                .to_vec(),
+        )
+        .unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        let peer = Some(IpAddr::from([192, 168, 0, 2]));
+        assert_eq!(
+            format_entry(LogFormat::Common, peer, Some(&request), 200, Some(1234), time),
+            "192.168.0.2 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html?page=2 HTTP/1.1\" 200 1234"
+        );
+        assert_eq!(
+            format_entry(LogFormat::Combined, peer, Some(&request), 304, Some(0), time),
+            "192.168.0.2 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html?page=2 HTTP/1.1\" 304 - \"http://example.com/\" \"curl/8.0 \\\"x\\\"\""
+        );
+        assert_eq!(
+            format_entry(LogFormat::Combined, None, None, 400, None, time),
+            "- - - [06/Nov/1994:08:49:37 +0000] \"-\" 400 - \"-\" \"-\""
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::response::{HttpHeaderName, HttpStatusCode, Response};
+use crate::Request;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+type ErasedParser = dyn Fn(&Request) -> Result<Box<dyn Any>, String> + Send + Sync;
+
+/// The parsers registered through
+/// [Server::register_body_parser](crate::Server::register_body_parser), by
+/// media type and the type they produce.
+#[derive(Clone, Default)]
+pub struct BodyParsers {
+    parsers: HashMap<(String, TypeId), Arc<ErasedParser>>,
+}
+
+impl BodyParsers {
+    pub fn insert<T, E, F>(&mut self, media_type: &str, parser: F)
+    where
+        T: 'static,
+        E: Display,
+        F: Fn(&Request) -> Result<T, E> + Send + Sync + 'static,
+    {
+        let erased = move |req: &Request| {
+            parser(req)
+                .map(|value| Box::new(value) as Box<dyn Any>)
+                .map_err(|err| err.to_string())
+        };
+        self.parsers.insert(
+            (media_type.trim().to_ascii_lowercase(), TypeId::of::<T>()),
+            Arc::new(erased),
+        );
+    }
+
+    /// Parses the body of `req` with the parser registered for its media type
+    /// (lowercased, without parameters) and `T`.
+    pub fn parse<T: 'static>(
+        &self,
+        media_type: Option<String>,
+        req: &Request,
+    ) -> Result<T, BodyError> {
+        let parser = media_type
+            .as_ref()
+            .and_then(|media_type| self.parsers.get(&(media_type.clone(), TypeId::of::<T>())))
+            .ok_or(BodyError::UnsupportedMediaType(media_type.clone()))?;
+        let value = parser(req).map_err(BodyError::Invalid)?;
+        // the parser was registered for T, so the downcast succeeds
+        value
+            .downcast::<T>()
+            .map(|value| *value)
+            .map_err(|_| BodyError::UnsupportedMediaType(media_type))
+    }
+}
+
+impl Debug for BodyParsers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.parsers.keys().map(|(media_type, _)| media_type))
+            .finish()
+    }
+}
+
+/// The error for a body which can't be parsed, see [Request::parse_body].
+/// Converts into a `415 Unsupported Media Type` or `400 Bad Request`
+/// response.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BodyError {
+    /// No parser is registered for the media type of the request (None if
+    /// there is no `content-type`) and the requested type.
+    UnsupportedMediaType(Option<String>),
+    /// The `content-type` declares a charset other than UTF-8 (or its subset
+    /// US-ASCII), which the body would be mis-decoded with.
+    UnsupportedCharset(String),
+    /// The parser rejected the body, with its error message.
+    Invalid(String),
+}
+
+impl From<BodyError> for Response {
+    fn from(err: BodyError) -> Self {
+        let status = match err {
+            BodyError::UnsupportedMediaType(_) | BodyError::UnsupportedCharset(_) => {
+                HttpStatusCode::UnsupportedMediaType
+            }
+            BodyError::Invalid(_) => HttpStatusCode::BadRequest,
+        };
+        // the message quotes the request, so it isn't sent as HTML
+        Response::with_status(status)
+            .with_header(HttpHeaderName::ContentType, "text/plain; charset=utf-8")
+            .with_body(err)
+    }
+}
+
+impl Display for BodyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyError::UnsupportedMediaType(Some(media_type)) => {
+                write!(f, "unsupported media type {}", media_type)
+            }
+            BodyError::UnsupportedMediaType(None) => f.write_str("missing content-type"),
+            BodyError::UnsupportedCharset(charset) => write!(f, "unsupported charset {}", charset),
+            BodyError::Invalid(reason) => write!(f, "invalid body: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+/// The error for a body which can't be parsed as JSON, see [Request::json].
+/// Converts into a `400 Bad Request` response, or `415 Unsupported Media
+/// Type` for a charset other than UTF-8.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    /// The `content-type` isn't `application/json` (None if there is none).
+    NotJson(Option<String>),
+    /// The `content-type` declares a charset other than UTF-8 (or its subset
+    /// US-ASCII).
+    UnsupportedCharset(String),
+    /// The parser registered for `application/json` rejected the body, with
+    /// its error message.
+    Invalid(String),
+    /// No parser for `application/json` and the requested type is registered,
+    /// which is a mistake of the server rather than the client.
+    NoParser,
+}
+
+impl From<JsonError> for Response {
+    fn from(err: JsonError) -> Self {
+        let status = match err {
+            JsonError::NotJson(_) | JsonError::Invalid(_) => HttpStatusCode::BadRequest,
+            JsonError::UnsupportedCharset(_) => HttpStatusCode::UnsupportedMediaType,
+            JsonError::NoParser => HttpStatusCode::InternalServerError,
+        };
+        Response::with_status(status)
+            .with_header(HttpHeaderName::ContentType, "text/plain; charset=utf-8")
+            .with_body(err)
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::NotJson(Some(media_type)) => {
+                write!(f, "expected application/json, got {}", media_type)
+            }
+            JsonError::NotJson(None) => f.write_str("missing content-type"),
+            JsonError::UnsupportedCharset(charset) => write!(f, "unsupported charset {}", charset),
+            JsonError::Invalid(reason) => write!(f, "invalid JSON: {}", reason),
+            JsonError::NoParser => f.write_str("no JSON parser is registered"),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
@@ -0,0 +1,149 @@
+use crate::response::{HttpStatusCode, Response, StoredResponse};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// The maximum number of responses kept in a [ResponseCache]. Once it is
+/// reached, the oldest entry makes room for a new one, so requests with
+/// ever-changing queries can't grow the cache without bound.
+pub const MAX_CACHED_RESPONSES: usize = 256;
+
+/// The in-memory cache of the responses of routes registered with
+/// [RouteBuilder::cached](crate::RouteBuilder::cached).
+///
+/// Entries are keyed by the request path and the query, and at most
+/// [MAX_CACHED_RESPONSES] are kept. A handle obtained through
+/// [Server::response_cache](crate::Server::response_cache) shares the entries
+/// with the server, so handlers can invalidate them.
+#[derive(Default, Clone)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<Entries>>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// The responses by path, then by query.
+    by_path: HashMap<String, HashMap<Option<String>, StoredResponse>>,
+    /// The keys in the order they were inserted, oldest first.
+    order: VecDeque<(String, Option<String>)>,
+}
+
+impl ResponseCache {
+    /// Removes the cached responses for `path`, whatever their query, so the
+    /// next requests to it run the handler again. Returns false if nothing
+    /// was cached for `path`.
+    pub fn invalidate(&self, path: &str) -> bool {
+        let mut entries = self.lock();
+        entries.order.retain(|(p, _)| p != path);
+        entries.by_path.remove(path).is_some()
+    }
+
+    /// Removes all cached responses.
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.by_path.clear();
+        entries.order.clear();
+    }
+
+    pub(crate) fn get(&self, path: &str, query: Option<&str>) -> Option<Response> {
+        let entries = self.lock();
+        let stored = entries.by_path.get(path)?.get(&query.map(str::to_string))?;
+        Some(Response::from(stored.clone()))
+    }
+
+    /// Stores `response` for `path` and `query` if it is a `200 OK` without a
+    /// streamed body, evicting the oldest entry if the cache is full.
+    pub(crate) fn insert(&self, path: &str, query: Option<&str>, response: &Response) {
+        if response.status_code() != HttpStatusCode::OK {
+            return;
+        }
+        let Some(stored) = response.to_stored() else {
+            return;
+        };
+        let mut entries = self.lock();
+        let query = query.map(str::to_string);
+        let previous = entries
+            .by_path
+            .entry(path.to_string())
+            .or_default()
+            .insert(query.clone(), stored);
+        if previous.is_some() {
+            return;
+        }
+        entries.order.push_back((path.to_string(), query));
+        while entries.order.len() > MAX_CACHED_RESPONSES {
+            let Some((path, query)) = entries.order.pop_front() else {
+                break;
+            };
+            if let Some(queries) = entries.by_path.get_mut(&path) {
+                queries.remove(&query);
+                if queries.is_empty() {
+                    entries.by_path.remove(&path);
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::write_response;
+
+    #[test]
+    fn test_response_cache() {
+        let cache = ResponseCache::default();
+        cache.insert("/a", None, &"a".into());
+        cache.insert("/b", None, &Response::with_status(HttpStatusCode::NotFound));
+        cache.insert("/c", None, &Response::from_reader("text/plain", &b"c"[..]));
+        cache.insert("/d", None, &Response::from_static("text/plain", b"d"));
+        assert!(cache.get("/b", None).is_none());
+        assert!(cache.get("/c", None).is_none());
+
+        let mut http = Vec::new();
+        write_response(cache.get("/a", None).unwrap(), &mut http).unwrap();
+        assert!(http.ends_with(b"\r\n\r\na"));
+        let mut http = Vec::new();
+        write_response(cache.get("/d", None).unwrap(), &mut http).unwrap();
+        assert!(http.ends_with(b"\r\n\r\nd"));
+
+        assert!(cache.clone().invalidate("/a"));
+        assert!(!cache.invalidate("/a"));
+        cache.clear();
+        assert!(cache.get("/d", None).is_none());
+    }
+
+    #[test]
+    fn test_invalidate_covers_all_queries() {
+        let cache = ResponseCache::default();
+        cache.insert("/a", None, &"plain".into());
+        cache.insert("/a", Some("x=1"), &"x".into());
+        cache.insert("/ab", None, &"other".into());
+        assert!(cache.get("/a", Some("x=2")).is_none());
+        assert!(cache.get("/a", Some("x=1")).is_some());
+
+        assert!(cache.invalidate("/a"));
+        assert!(cache.get("/a", None).is_none());
+        assert!(cache.get("/a", Some("x=1")).is_none());
+        assert!(cache.get("/ab", None).is_some());
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let cache = ResponseCache::default();
+        for i in 0..MAX_CACHED_RESPONSES + 10 {
+            cache.insert("/cached", Some(&format!("bust={}", i)), &"x".into());
+        }
+        let entries = cache.lock();
+        assert_eq!(entries.order.len(), MAX_CACHED_RESPONSES);
+        assert_eq!(entries.by_path["/cached"].len(), MAX_CACHED_RESPONSES);
+        drop(entries);
+        // the oldest entries were evicted
+        assert!(cache.get("/cached", Some("bust=0")).is_none());
+        let newest = format!("bust={}", MAX_CACHED_RESPONSES + 9);
+        assert!(cache.get("/cached", Some(&newest)).is_some());
+    }
+}
@@ -0,0 +1,73 @@
+use crate::gzip::gzip;
+use crate::response::{HttpHeaderName, HttpStatusCode, Response};
+use crate::Request;
+
+/// Compresses the body of `response` with gzip for
+/// [Server::enable_compression](crate::Server::enable_compression), if the
+/// client accepts it and the body is a text in memory of at least
+/// `min_length` bytes. Streamed bodies and ones which already have a
+/// `content-encoding` are left as they are.
+pub fn compress(request: &Request, response: &mut Response, min_length: usize) {
+    let compressible = response
+        .header(HttpHeaderName::ContentType)
+        .is_some_and(is_compressible_type);
+    if !compressible
+        || response.header(HttpHeaderName::ContentEncoding).is_some()
+        || response.status_code() == HttpStatusCode::PartialContent
+    {
+        return;
+    }
+    let length = response.buffered_body().map_or(0, <[u8]>::len);
+    if length < min_length {
+        return;
+    }
+    // caches have to keep the variants apart
+    match response.header(HttpHeaderName::Vary) {
+        None => response.set_header(HttpHeaderName::Vary, "accept-encoding"),
+        Some(vary) if !vary.to_ascii_lowercase().contains("accept-encoding") => {
+            let vary = format!("{}, accept-encoding", vary);
+            response.set_header(HttpHeaderName::Vary, vary);
+        }
+        Some(_) => {}
+    }
+    // on a tie, the smaller body wins
+    let quality = request.encoding_quality("gzip");
+    if quality <= 0.0 || quality < request.encoding_quality("identity") {
+        return;
+    }
+    let compressed = gzip(response.buffered_body().unwrap_or_default());
+    if compressed.len() < length {
+        response.set_body_bytes(&compressed);
+        response.set_header(HttpHeaderName::ContentEncoding, "gzip");
+        if response.header(HttpHeaderName::ContentLength).is_some() {
+            response.set_header(HttpHeaderName::ContentLength, compressed.len());
+        }
+        // the bytes differ from the uncompressed body, so a strong tag would
+        // claim both are identical, e.g. for if-range; weak comparison, as
+        // for if-none-match, still matches
+        if let Some(etag) = response.header(HttpHeaderName::ETag) {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{}", etag);
+                response.set_header(HttpHeaderName::ETag, weak);
+            }
+        }
+    }
+}
+
+/// Returns true for textual media types. Others, like images, videos or
+/// archives, are usually compressed already.
+fn is_compressible_type(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
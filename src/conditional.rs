@@ -0,0 +1,58 @@
+use crate::httpdate::parse_http_date;
+use crate::response::{HttpHeaderName, HttpStatusCode, Response};
+use crate::{HttpMethod, Request};
+
+/// Answers a conditional GET or HEAD request with `304 Not Modified` if the
+/// validators of the response show that the client's copy is still fresh.
+///
+/// `if-none-match` takes precedence over `if-modified-since`. Only successful
+/// responses are considered.
+pub fn evaluate(request: &Request, mut response: Response) -> Response {
+    let method = request.method();
+    if !matches!(method, HttpMethod::GET | HttpMethod::HEAD)
+        || response.status_code() != HttpStatusCode::OK
+    {
+        return response;
+    }
+    let not_modified = if let Some(if_none_match) = request.header("if-none-match") {
+        response
+            .header(HttpHeaderName::ETag)
+            .is_some_and(|etag| etag_list_matches(if_none_match, etag))
+    } else if let Some(if_modified_since) = request.header("if-modified-since") {
+        let since = parse_http_date(if_modified_since);
+        let last_modified = response
+            .header(HttpHeaderName::LastModified)
+            .and_then(parse_http_date);
+        matches!((since, last_modified), (Some(since), Some(modified)) if modified <= since)
+    } else {
+        false
+    };
+    if not_modified {
+        response.set_not_modified();
+    }
+    response
+}
+
+/// Checks whether a list of entity tags like `"a", W/"b"` contains `etag`,
+/// using the weak comparison (`W/` prefixes are ignored).
+pub fn etag_list_matches(list: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    list.split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_etag_list_matches() {
+        assert!(etag_list_matches("\"abc\"", "\"abc\""));
+        assert!(etag_list_matches("\"x\", W/\"abc\"", "\"abc\""));
+        assert!(etag_list_matches("\"abc\"", "W/\"abc\""));
+        assert!(etag_list_matches("*", "\"abc\""));
+        assert!(!etag_list_matches("\"abcd\"", "\"abc\""));
+        assert!(!etag_list_matches("", "\"abc\""));
+    }
+}
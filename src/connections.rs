@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Keeps track of the number of active connections per peer IP address.
+#[derive(Default, Clone)]
+pub struct ConnectionLimiter {
+    max_per_ip: Option<usize>,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimiter {
+    pub fn set_max_per_ip(&mut self, max: usize) {
+        self.max_per_ip = Some(max);
+    }
+
+    /// Registers a new connection from `ip`. Returns None if the IP already
+    /// has the maximum number of connections open. The connection counts as
+    /// active until the returned guard is dropped.
+    pub fn try_acquire(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let count = active.entry(ip).or_insert(0);
+        if self.max_per_ip.is_some_and(|max| *count >= max) {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            ip,
+            active: Arc::clone(&self.active),
+        })
+    }
+
+    /// Returns the number of active connections from `ip`.
+    #[cfg(test)]
+    pub fn active_connections(&self, ip: IpAddr) -> usize {
+        let active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        active.get(&ip).copied().unwrap_or(0)
+    }
+}
+
+/// Marks a connection as active while it is alive. Dropping it, which also
+/// happens when unwinding from a panic, frees the slot again.
+pub struct ConnectionGuard {
+    ip: IpAddr,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn test_limit_per_ip() {
+        let mut limiter = ConnectionLimiter::default();
+        limiter.set_max_per_ip(2);
+        let first = limiter.try_acquire(LOOPBACK).unwrap();
+        let _second = limiter.try_acquire(LOOPBACK).unwrap();
+        assert!(limiter.try_acquire(LOOPBACK).is_none());
+        assert_eq!(limiter.active_connections(LOOPBACK), 2);
+
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(limiter.try_acquire(other_ip).is_some());
+
+        drop(first);
+        assert_eq!(limiter.active_connections(LOOPBACK), 1);
+        assert!(limiter.try_acquire(LOOPBACK).is_some());
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let limiter = ConnectionLimiter::default();
+        let guards: Vec<_> = (0..100)
+            .filter_map(|_| limiter.try_acquire(LOOPBACK))
+            .collect();
+        assert_eq!(guards.len(), 100);
+        drop(guards);
+        assert_eq!(limiter.active_connections(LOOPBACK), 0);
+    }
+
+    #[test]
+    fn test_guard_released_on_panic() {
+        let mut limiter = ConnectionLimiter::default();
+        limiter.set_max_per_ip(1);
+        let cloned = limiter.clone();
+        let result = std::panic::catch_unwind(move || {
+            let _guard = cloned.try_acquire(LOOPBACK).unwrap();
+            panic!("handler failed");
+        });
+        assert!(result.is_err());
+        assert_eq!(limiter.active_connections(LOOPBACK), 0);
+        assert!(limiter.try_acquire(LOOPBACK).is_some());
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+/// The attributes of a cookie set with [Response::set_cookie](crate::Response::set_cookie).
+/// Fields left at their default are omitted, so by default the cookie lasts
+/// for the browser session and is sent for the path it was set on.
+/// ```
+/// use raspi_file_server::*;
+/// use std::time::Duration;
+///
+/// let options = CookieOptions {
+///     max_age: Some(Duration::from_secs(3600)),
+///     path: Some("/".to_string()),
+///     http_only: true,
+///     same_site: Some(SameSite::Lax),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CookieOptions {
+    /// How long the cookie is kept, in whole seconds. Zero deletes it.
+    pub max_age: Option<Duration>,
+    /// The path prefix the cookie is sent for.
+    pub path: Option<String>,
+    /// Hides the cookie from JavaScript.
+    pub http_only: bool,
+    /// Only sends the cookie over HTTPS.
+    pub secure: bool,
+    /// Restricts sending the cookie along with cross-site requests.
+    pub same_site: Option<SameSite>,
+}
+
+/// The values of the `SameSite` cookie attribute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Sends the cookie with all requests, which browsers only accept for
+    /// [secure](CookieOptions::secure) cookies.
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/// Formats the value of a `set-cookie` header. Characters which aren't
+/// allowed in the name, value or path are percent-encoded, so none of them
+/// can end the cookie and add attributes of its own.
+pub fn format_set_cookie(name: &str, value: &str, options: &CookieOptions) -> String {
+    let mut cookie = format!("{}={}", encode(name, true), encode(value, false));
+    if let Some(max_age) = options.max_age {
+        cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+    }
+    if let Some(path) = &options.path {
+        cookie.push_str(&format!("; Path={}", encode(path, false)));
+    }
+    if options.http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if options.secure {
+        cookie.push_str("; Secure");
+    }
+    if let Some(same_site) = options.same_site {
+        cookie.push_str(&format!("; SameSite={}", same_site));
+    }
+    cookie
+}
+
+/// Percent-encodes control characters, whitespace, non-ASCII bytes and
+/// `"`, `,`, `;`, `\` as well as `%` itself, plus `=` in a name.
+fn encode(raw: &str, is_name: bool) -> String {
+    raw.bytes()
+        .map(|b| match b {
+            b'"' | b',' | b';' | b'\\' | b'%' => format!("%{:02X}", b),
+            b'=' if is_name => format!("%{:02X}", b),
+            b'!'..=b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_set_cookie() {
+        assert_eq!(
+            format_set_cookie("a", "1", &CookieOptions::default()),
+            "a=1"
+        );
+        let options = CookieOptions {
+            max_age: Some(Duration::from_secs(60)),
+            path: Some("/app".to_string()),
+            http_only: true,
+            secure: true,
+            same_site: Some(SameSite::Strict),
+        };
+        assert_eq!(
+            format_set_cookie("session", "abc", &options),
+            "session=abc; Max-Age=60; Path=/app; HttpOnly; Secure; SameSite=Strict"
+        );
+    }
+
+    #[test]
+    fn test_format_set_cookie_encodes_separators() {
+        assert_eq!(
+            format_set_cookie("theme", "x; Domain=evil.com", &CookieOptions::default()),
+            "theme=x%3B%20Domain=evil.com"
+        );
+        let options = CookieOptions {
+            path: Some("/a;Secure,b c".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_set_cookie("a=b;c", "1,2\r\n\"%\u{e9}", &options),
+            "a%3Db%3Bc=1%2C2%0D%0A%22%25%C3%A9; Path=/a%3BSecure%2Cb%20c"
+        );
+        // token characters and '=' in values stay as they are
+        assert_eq!(
+            format_set_cookie("token", "ab=cd/+_.~!", &CookieOptions::default()),
+            "token=ab=cd/+_.~!"
+        );
+    }
+}
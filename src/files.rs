@@ -0,0 +1,259 @@
+use crate::conditional;
+use crate::httpdate::parse_http_date;
+use crate::mime::mime_from_path;
+use crate::request::utils::percent_decode;
+use crate::response::{HttpHeaderName, HttpStatusCode, Response};
+use crate::zip::{ZipEntry, ZipStream};
+use crate::{HttpMethod, Request};
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sent with all files served from a directory: clients may store them, but
+/// have to revalidate them (cheaply, through the ETag) before each use.
+const CACHE_CONTROL: &str = "no-cache";
+
+/// Precompressed companions of a file, e.g. `app.js.br` for `app.js`, as
+/// `(extension, content coding)` in order of preference.
+const PRECOMPRESSED_VARIANTS: [(&str, &str); 2] = [("br", "br"), ("gz", "gzip")];
+
+/// Maps a percent-encoded URL path relative to `root` onto the file system.
+/// Returns None if the path doesn't exist, has a `..` segment or one which
+/// decodes to a separator or NUL, or resolves to a location outside of
+/// `root`, e.g. by following a symlink.
+pub fn resolve_path(root: &Path, relative: &str) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let mut path = root.clone();
+    for segment in relative.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode(segment, false);
+        if segment == ".." || segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        path.push(segment);
+    }
+    let path = path.canonicalize().ok()?;
+    path.starts_with(&root).then_some(path)
+}
+
+/// Returns the part of the request path after the `mount` prefix. Returns
+/// None if the request isn't below `mount`.
+pub fn path_below_mount<'a>(req: &'a Request, mount: &str) -> Option<&'a str> {
+    let rest = req.path_as_str().strip_prefix(mount)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// Handler for [Server::serve_dir](crate::Server::serve_dir).
+pub fn serve_file(req: &Request, mount: &str, fs_root: &Path) -> Response {
+    let path = path_below_mount(req, mount)
+        .and_then(|rest| resolve_path(fs_root, rest))
+        .map(|path| {
+            if path.is_dir() {
+                path.join("index.html")
+            } else {
+                path
+            }
+        })
+        .filter(|path| path.is_file());
+    match path {
+        Some(path) => negotiated_file_response(req, fs_root, &path)
+            .map(|response| response.with_header(HttpHeaderName::CacheControl, CACHE_CONTROL))
+            .unwrap_or_else(|_| Response::with_status(HttpStatusCode::InternalServerError)),
+        None => Response::not_found(),
+    }
+}
+
+/// Serves the precompressed companion of `path` which the client accepts
+/// with the highest quality, preferring brotli on a tie, or `path` itself
+/// if there is none.
+fn negotiated_file_response(req: &Request, fs_root: &Path, path: &Path) -> io::Result<Response> {
+    let mut companions = PRECOMPRESSED_VARIANTS
+        .iter()
+        .filter_map(|(extension, coding)| {
+            let mut name = path.file_name()?.to_os_string();
+            name.push(format!(".{}", extension));
+            let companion = path.with_file_name(name).canonicalize().ok()?;
+            let inside_root = fs_root
+                .canonicalize()
+                .is_ok_and(|root| companion.starts_with(root));
+            (inside_root && companion.is_file()).then_some((companion, *coding))
+        })
+        .peekable();
+    if companions.peek().is_none() {
+        return pipe_file(path, req);
+    }
+
+    let mut best: Option<(PathBuf, &str, f32)> = None;
+    let identity = req.encoding_quality("identity");
+    for (companion, coding) in companions {
+        let quality = req.encoding_quality(coding);
+        if quality > 0.0
+            && quality >= identity
+            && best.as_ref().is_none_or(|(_, _, q)| quality > *q)
+        {
+            best = Some((companion, coding, quality));
+        }
+    }
+    let mut response = match best {
+        Some((companion, coding, _)) => {
+            let mut response = pipe_file(&companion, req)?;
+            response.set_header(HttpHeaderName::ContentType, mime_from_path(path));
+            response.set_header(HttpHeaderName::ContentEncoding, coding);
+            response
+        }
+        None => pipe_file(path, req)?,
+    };
+    // caches have to keep the variants apart
+    response.set_header(HttpHeaderName::Vary, "accept-encoding");
+    Ok(response)
+}
+
+/// Handler side of [Response::pipe_from_file]: streams the file at `path`,
+/// or the byte range of it requested by `req`, with a `content-type` guessed
+/// from its extension and validators derived from its metadata.
+pub fn pipe_file(path: &Path, req: &Request) -> io::Result<Response> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let length = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = weak_etag(length, modified.unwrap_or(UNIX_EPOCH));
+    let mut response = Response::default().with_validators(&etag, modified);
+    response.set_header(HttpHeaderName::ContentType, mime_from_path(path));
+    response.set_header(HttpHeaderName::AcceptRanges, "bytes");
+
+    // a fresh copy is answered with 304 before a range is considered
+    let mut response = conditional::evaluate(req, response);
+    if response.status_code() == HttpStatusCode::NotModified {
+        return Ok(response);
+    }
+    match requested_range(req, length, modified) {
+        None => response.set_sized_stream(file, length),
+        Some(Some((start, end))) => {
+            file.seek(SeekFrom::Start(start))?;
+            response.set_status_code(HttpStatusCode::PartialContent);
+            response.set_header(
+                HttpHeaderName::ContentRange,
+                format!("bytes {}-{}/{}", start, end, length),
+            );
+            response.set_sized_stream(file, end - start + 1);
+        }
+        Some(None) => {
+            response.set_status_code(HttpStatusCode::RangeNotSatisfiable);
+            response.set_header(HttpHeaderName::ContentRange, format!("bytes */{}", length));
+        }
+    }
+    Ok(response)
+}
+
+/// Returns the inclusive byte range requested through the `range` header of
+/// a GET request for a file of `length` bytes, `Some(None)` if the range
+/// can't be satisfied, or None if the whole file is to be sent.
+///
+/// Only a single range is supported; requests for several ranges, malformed
+/// ones and ones whose `if-range` doesn't match get the whole file. Weak
+/// ETags never match `if-range`, so only a date there can.
+fn requested_range(
+    req: &Request,
+    length: u64,
+    modified: Option<SystemTime>,
+) -> Option<Option<(u64, u64)>> {
+    if !matches!(req.method(), HttpMethod::GET | HttpMethod::HEAD) {
+        return None;
+    }
+    let range = req.header("range")?.trim().strip_prefix("bytes=")?;
+    if let Some(if_range) = req.header("if-range") {
+        let since = parse_http_date(if_range)?;
+        let modified = modified?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        if since.duration_since(UNIX_EPOCH).ok()?.as_secs() != modified {
+            return None;
+        }
+    }
+    if range.contains(',') {
+        return None;
+    }
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        // the last `end` bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || length == 0 {
+            return Some(None);
+        }
+        return Some(Some((length.saturating_sub(suffix), length - 1)));
+    }
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => u64::MAX,
+        end => end.parse().ok()?,
+    };
+    if start > end {
+        return None;
+    }
+    if start >= length {
+        return Some(None);
+    }
+    Some(Some((start, end.min(length - 1))))
+}
+
+/// Computes a weak ETag from size and modification time, which is cheap as
+/// the content doesn't need to be hashed.
+pub fn weak_etag(size: u64, modified: std::time::SystemTime) -> String {
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "W/\"{:x}-{:x}{:08x}\"",
+        size,
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+}
+
+/// Handler for [Server::serve_dir_as_zip](crate::Server::serve_dir_as_zip).
+pub fn serve_zip(req: &Request, mount: &str, fs_root: &Path) -> Response {
+    let wants_zip = matches!(req.queries().get("download"), Some(Some(v)) if v == "zip");
+    let dir = path_below_mount(req, mount)
+        .and_then(|rest| resolve_path(fs_root, rest))
+        .filter(|path| path.is_dir());
+    match dir {
+        Some(dir) if wants_zip => zip_directory(&dir)
+            .unwrap_or_else(|_| Response::with_status(HttpStatusCode::InternalServerError)),
+        _ => Response::not_found(),
+    }
+}
+
+/// Creates a response streaming a ZIP archive of `dir`. Only the directory
+/// listing is read up front, file contents are read while streaming.
+pub fn zip_directory(dir: &Path) -> io::Result<Response> {
+    let mut entries = Vec::new();
+    collect_zip_entries(dir, "", &mut entries)?;
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().replace('"', ""))
+        .unwrap_or_else(|| "archive".to_string());
+
+    let mut response = Response::default();
+    response.set_header(HttpHeaderName::ContentType, "application/zip");
+    response.set_header(
+        HttpHeaderName::ContentDisposition,
+        format!("attachment; filename=\"{}.zip\"", name),
+    );
+    response.set_stream(ZipStream::new(entries));
+    Ok(response)
+}
+
+fn collect_zip_entries(dir: &Path, prefix: &str, entries: &mut Vec<ZipEntry>) -> io::Result<()> {
+    let mut children = std::fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|entry| entry.file_name());
+    for child in children {
+        let name = format!("{}{}", prefix, child.file_name().to_string_lossy());
+        let file_type = child.file_type()?;
+        if file_type.is_dir() {
+            collect_zip_entries(&child.path(), &format!("{}/", name), entries)?;
+        } else if file_type.is_file() {
+            entries.push(ZipEntry {
+                name,
+                path: child.path(),
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use crate::zip::Crc32;
+
+/// How far back LZ77 matches may reach, the maximum of DEFLATE.
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash are tried for a match,
+/// trading compression for speed.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const END_OF_BLOCK: u16 = 256;
+
+/// Compresses `data` into the gzip format.
+///
+/// The data is compressed into a single DEFLATE block with the fixed Huffman
+/// codes, which needs no code tables and is fast, but compresses a little
+/// worse than the dynamic codes of a full implementation.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // magic, method deflate, no flags, no mtime, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    let mut crc = Crc32::new();
+    crc.update(data);
+    out.extend(crc.finish().to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // final block, fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut matcher = Matcher::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = matcher.longest_match(data, pos);
+        if length >= MIN_MATCH {
+            writer.write_match(length, distance);
+            for p in pos..pos + length {
+                matcher.insert(data, p);
+            }
+            pos += length;
+        } else {
+            writer.write_symbol(data[pos] as u16);
+            matcher.insert(data, pos);
+            pos += 1;
+        }
+    }
+    writer.write_symbol(END_OF_BLOCK);
+    writer.finish()
+}
+
+/// Finds earlier occurrences of the upcoming bytes through hash chains: for
+/// each hash of three bytes the latest position, and for each position the
+/// previous one with the same hash.
+struct Matcher {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl Matcher {
+    fn new() -> Self {
+        Self {
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            prev: vec![usize::MAX; WINDOW_SIZE],
+        }
+    }
+
+    fn hash(data: &[u8], pos: usize) -> Option<usize> {
+        let bytes = data.get(pos..pos + MIN_MATCH)?;
+        let hash = (bytes[0] as usize) << 10 ^ (bytes[1] as usize) << 5 ^ bytes[2] as usize;
+        Some(hash & ((1 << HASH_BITS) - 1))
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if let Some(hash) = Self::hash(data, pos) {
+            self.prev[pos % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = pos;
+        }
+    }
+
+    /// Returns the length and distance of the longest match for the bytes at
+    /// `pos`, or a length of 0 if there is none.
+    fn longest_match(&self, data: &[u8], pos: usize) -> (usize, usize) {
+        let Some(hash) = Self::hash(data, pos) else {
+            return (0, 0);
+        };
+        let max_length = MAX_MATCH.min(data.len() - pos);
+        let mut best = (0, 0);
+        let mut candidate = self.head[hash];
+        for _ in 0..MAX_CHAIN {
+            // older positions may have been overwritten in `prev`
+            if candidate == usize::MAX || pos - candidate >= WINDOW_SIZE {
+                break;
+            }
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[pos..pos + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, pos - candidate);
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate % WINDOW_SIZE];
+        }
+        best
+    }
+}
+
+/// Packs bits into bytes, starting with the least significant bit.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which starts with its most significant bit.
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Writes a literal byte, the end of the block or a length code with
+    /// the fixed Huffman codes.
+    fn write_symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|base| *base as usize <= length)
+            .unwrap_or_default();
+        self.write_symbol(257 + code as u16);
+        self.write_bits(
+            (length - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA_BITS[code] as u32,
+        );
+        let code = DISTANCE_BASE
+            .iter()
+            .rposition(|base| *base as usize <= distance)
+            .unwrap_or_default();
+        self.write_code(code as u32, 5);
+        self.write_bits(
+            (distance - DISTANCE_BASE[code] as usize) as u32,
+            DISTANCE_EXTRA_BITS[code] as u32,
+        );
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bit(&mut self) -> u32 {
+            let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+            self.pos += 1;
+            bit as u32
+        }
+
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, i| value | self.bit() << i)
+        }
+
+        /// Reads a symbol encoded with the fixed Huffman codes.
+        fn symbol(&mut self) -> u16 {
+            let code = (0..7).fold(0, |code, _| code << 1 | self.bit());
+            if code <= 0x17 {
+                return 256 + code as u16;
+            }
+            let code = code << 1 | self.bit();
+            match code {
+                0x30..=0xbf => (code - 0x30) as u16,
+                0xc0..=0xc7 => (280 + code - 0xc0) as u16,
+                _ => (144 + (code << 1 | self.bit()) - 0x190) as u16,
+            }
+        }
+    }
+
+    /// Decompresses the output of [gzip], checking its header and trailer.
+    pub(crate) fn gunzip(compressed: &[u8]) -> Vec<u8> {
+        assert_eq!(compressed[..4], [0x1f, 0x8b, 8, 0]);
+        let mut reader = BitReader {
+            data: &compressed[10..],
+            pos: 0,
+        };
+        assert_eq!(reader.bits(3), 0b011, "a final block with fixed codes");
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let symbol = reader.symbol();
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                END_OF_BLOCK => break,
+                _ => {
+                    let code = (symbol - 257) as usize;
+                    let length = LENGTH_BASE[code] as usize
+                        + reader.bits(LENGTH_EXTRA_BITS[code] as u32) as usize;
+                    let code = (0..5).fold(0, |code, _| code << 1 | reader.bit()) as usize;
+                    let distance = DISTANCE_BASE[code] as usize
+                        + reader.bits(DISTANCE_EXTRA_BITS[code] as u32) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+        let trailer = &compressed[compressed.len() - 8..];
+        let mut crc = Crc32::new();
+        crc.update(&out);
+        assert_eq!(trailer[..4], crc.finish().to_le_bytes());
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let text = "<li>hello world</li>\n".repeat(2000);
+        let compressed = gzip(text.as_bytes());
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(gunzip(&compressed), text.as_bytes());
+
+        // long runs, far matches and bytes with 9-bit codes
+        let mut data = vec![0; 1000];
+        data.extend((0..40_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        data.extend_from_within(20_000..30_000);
+        assert_eq!(gunzip(&gzip(&data)), data);
+
+        assert_eq!(gunzip(&gzip(b"")), b"");
+        assert_eq!(gunzip(&gzip(b"ab")), b"ab");
+    }
+}
@@ -0,0 +1,260 @@
+use crate::response::{HttpHeaderName, Response};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON array which is generated while it is being read, so large
+/// collections don't have to be serialized into memory up front.
+///
+/// Each item of the iterator has to be a serialized JSON value; they are
+/// written separated by commas between `[` and `]`. Converting the stream
+/// into a [Response] sends it with `content-type: application/json` and
+/// `transfer-encoding: chunked`.
+/// ```
+/// use raspi_file_server::*;
+///
+/// fn numbers_route(_: &Request) -> Response {
+///     let items = (0..10_000).map(|i| format!("{{\"n\":{}}}", i));
+///     JsonArrayStream::new(items).into()
+/// }
+/// ```
+pub struct JsonArrayStream<I> {
+    items: I,
+    pending: Vec<u8>,
+    pending_pos: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<I, T> JsonArrayStream<I>
+where
+    I: Iterator<Item = T>,
+    T: ToString,
+{
+    pub fn new<C: IntoIterator<IntoIter = I>>(items: C) -> Self {
+        Self {
+            items: items.into_iter(),
+            pending: Vec::new(),
+            pending_pos: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Fills `pending` with the next piece of the array. Returns false once
+    /// the closing bracket was produced.
+    fn advance(&mut self) -> bool {
+        self.pending.clear();
+        self.pending_pos = 0;
+        if self.finished {
+            return false;
+        }
+        match self.items.next() {
+            Some(item) => {
+                self.pending.push(if self.started { b',' } else { b'[' });
+                self.pending.extend_from_slice(item.to_string().as_bytes());
+            }
+            None => {
+                if !self.started {
+                    self.pending.push(b'[');
+                }
+                self.pending.push(b']');
+                self.finished = true;
+            }
+        }
+        self.started = true;
+        true
+    }
+}
+
+impl<I, T> Read for JsonArrayStream<I>
+where
+    I: Iterator<Item = T>,
+    T: ToString,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending_pos >= self.pending.len() {
+            if !self.advance() {
+                return Ok(0);
+            }
+        }
+        let available = &self.pending[self.pending_pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pending_pos += len;
+        Ok(len)
+    }
+}
+
+impl<I, T> From<JsonArrayStream<I>> for Response
+where
+    I: Iterator<Item = T> + 'static,
+    T: ToString,
+{
+    fn from(stream: JsonArrayStream<I>) -> Self {
+        let mut response = Response::default();
+        response.set_header(HttpHeaderName::ContentType, "application/json");
+        response.set_stream(stream);
+        response
+    }
+}
+
+/// Parses a JSON object whose values are all strings, numbers, booleans or
+/// null into its fields. Strings are unescaped, numbers and booleans kept as
+/// written and null fields left out. Returns None for anything else,
+/// including objects with nested objects or arrays.
+pub(crate) fn parse_flat_object(text: &str) -> Option<HashMap<String, String>> {
+    let mut chars = text.chars().peekable();
+    let mut fields = HashMap::new();
+    skip_whitespace(&mut chars);
+    expect(&mut chars, '{')?;
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            expect(&mut chars, '"')?;
+            let key = parse_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            expect(&mut chars, ':')?;
+            skip_whitespace(&mut chars);
+            if let Some(value) = parse_scalar(&mut chars)? {
+                fields.insert(key, value);
+            }
+            skip_whitespace(&mut chars);
+            match chars.next()? {
+                ',' => continue,
+                '}' => break,
+                _ => return None,
+            }
+        }
+    }
+    skip_whitespace(&mut chars);
+    chars.next().is_none().then_some(fields)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Option<()> {
+    chars.next_if_eq(&expected).map(|_| ())
+}
+
+/// Parses a string, number, boolean or null, the latter yielding
+/// `Some(None)`.
+fn parse_scalar(chars: &mut Peekable<Chars>) -> Option<Option<String>> {
+    if expect(chars, '"').is_some() {
+        return parse_string(chars).map(Some);
+    }
+    let mut word = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || "+-.".contains(*c)) {
+        word.push(c);
+    }
+    match word.as_str() {
+        "null" => Some(None),
+        "true" | "false" => Some(Some(word)),
+        _ if word.parse::<f64>().is_ok() && !word.starts_with('+') => Some(Some(word)),
+        _ => None,
+    }
+}
+
+/// Parses the rest of a string after its opening quote.
+fn parse_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => match chars.next()? {
+                'n' => string.push('\n'),
+                't' => string.push('\t'),
+                'r' => string.push('\r'),
+                'b' => string.push('\u{8}'),
+                'f' => string.push('\u{c}'),
+                'u' => {
+                    let high = parse_hex4(chars)?;
+                    let code = if (0xd800..0xdc00).contains(&high) {
+                        expect(chars, '\\')?;
+                        expect(chars, 'u')?;
+                        let low = parse_hex4(chars)?;
+                        0x10000 + ((high - 0xd800) << 10) + low.checked_sub(0xdc00)?
+                    } else {
+                        high
+                    };
+                    string.push(char::from_u32(code)?);
+                }
+                c @ ('"' | '\\' | '/') => string.push(c),
+                _ => return None,
+            },
+            c if c < ' ' => return None,
+            c => string.push(c),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let hex: String = (0..4).map_while(|_| chars.next()).collect();
+    if hex.len() != 4 {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::tests::decode_chunked;
+    use crate::response::write_response;
+
+    #[test]
+    fn test_stream_json_array() {
+        let items = (0..1000).map(|i| format!("{{\"id\":{}}}", i));
+        let mut http = Vec::new();
+        write_response(JsonArrayStream::new(items).into(), &mut http).unwrap();
+        let head_end = http.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&http[..head_end]).to_string();
+        assert!(head.contains("content-type: application/json\r\n"));
+        assert!(head.contains("transfer-encoding: chunked"));
+
+        let body = String::from_utf8(decode_chunked(&http[head_end + 4..])).unwrap();
+        let expected: Vec<String> = (0..1000).map(|i| format!("{{\"id\":{}}}", i)).collect();
+        assert_eq!(body, format!("[{}]", expected.join(",")));
+    }
+
+    #[test]
+    fn test_parse_flat_object() {
+        let fields = parse_flat_object(
+            r#" {"name": "K\u00f6ln \"x\"", "n": -1.5e3, "ok": true, "none": null, "e": "\ud83d\ude00"} "#,
+        )
+        .unwrap();
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields["name"], "Köln \"x\"");
+        assert_eq!(fields["n"], "-1.5e3");
+        assert_eq!(fields["ok"], "true");
+        assert_eq!(fields["e"], "😀");
+        assert_eq!(parse_flat_object("{}"), Some(HashMap::new()));
+
+        for invalid in [
+            r#"{"a": {"b": 1}}"#,
+            r#"{"a": [1]}"#,
+            r#"{"a": 1,}"#,
+            r#"{"a": nope}"#,
+            r#"{"a": 1} x"#,
+            r#"["a"]"#,
+            "",
+        ] {
+            assert_eq!(parse_flat_object(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_stream_empty_json_array() {
+        let mut body = String::new();
+        JsonArrayStream::new(Vec::<String>::new())
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "[]");
+    }
+}
@@ -1,19 +1,159 @@
+mod access_log;
+mod body_parser;
+mod cache;
+mod compression;
+mod conditional;
+mod connections;
+mod cookie;
+mod files;
+mod gzip;
+mod httpdate;
+mod json;
+mod middleware;
+pub mod mime;
+mod proxy;
+mod reader;
 mod request;
 mod response;
+mod shutdown;
+mod sse;
+mod stats;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+mod zip;
 
-pub use request::{HttpMethod, Request};
-use response::response_into_http_response_string;
-pub use response::{HttpHeaderName, HttpStatusCode, Response};
-use std::io::prelude::*;
+pub use access_log::{LogFormat, RequestLog};
+pub use body_parser::{BodyError, JsonError};
+pub use cache::{ResponseCache, MAX_CACHED_RESPONSES};
+pub use cookie::{CookieOptions, SameSite};
+pub use json::JsonArrayStream;
+pub use middleware::Next;
+pub use request::{HttpMethod, QueryError, Request, RequestParseError};
+use response::write_response;
+pub use response::{HttpHeaderName, HttpStatusCode, Response, ResponseError, UnknownStatus};
+pub use shutdown::ServerHandle;
+pub use stats::ServerStats;
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
 use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-type Routes = Vec<(HttpMethod, String, Box<dyn Fn(&Request) -> Response>)>;
+struct Route {
+    method: HttpMethod,
+    path: String,
+    host: Option<String>,
+    cached: bool,
+    handler: Handler,
+}
+
+impl Route {
+    fn matches(
+        &self,
+        method: &HttpMethod,
+        host: Option<&str>,
+        path: &str,
+        options: request::utils::MatchOptions,
+    ) -> bool {
+        let host_matches = match (&self.host, host) {
+            (None, _) => true,
+            (Some(expected), Some(host)) => {
+                let host = host.rsplit_once(':').map_or(host, |(name, _)| name);
+                expected.eq_ignore_ascii_case(host)
+            }
+            (Some(_), None) => false,
+        };
+        self.method == *method
+            && host_matches
+            && request::utils::path_matches_route_with(path, &self.path, options)
+    }
+}
+
+type Routes = Vec<Route>;
+
+type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+type ConnectionHook = Box<dyn Fn(&TcpStream) + Send + Sync>;
+
+type RequestHook = Box<dyn Fn(&RequestLog) + Send + Sync>;
 
 #[derive(Default)]
 pub struct Server {
     routes: Routes,
+    connections: connections::ConnectionLimiter,
+    format_extensions: Vec<String>,
+    max_body_size: Option<usize>,
+    development: bool,
+    cache: ResponseCache,
+    manual_head: bool,
+    manual_options: bool,
+    not_found_handler: Option<Handler>,
+    connection_hook: Option<ConnectionHook>,
+    request_hook: Option<RequestHook>,
+    stats: stats::StatsRecorder,
+    access_log: Option<access_log::AccessLog>,
+    cors_origin: Option<String>,
+    /// Path patterns of named routes, see [Server::add_named_route].
+    route_names: HashMap<String, String>,
+    handle: ServerHandle,
+    /// Paths still served in maintenance mode, see
+    /// [Server::allow_during_maintenance].
+    maintenance_allowlist: Vec<String>,
+    reject_get_body: bool,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    body_parsers: Arc<body_parser::BodyParsers>,
+    middleware: Vec<middleware::Middleware>,
+    /// The minimum body length to compress, if compression is enabled.
+    compression: Option<usize>,
+    /// See [Server::enable_case_insensitive_paths] and
+    /// [Server::set_strict_trailing_slash].
+    path_matching: request::utils::MatchOptions,
+    /// The prefix stripped from paths before routing, see [Server::set_base_path].
+    base_path: Option<String>,
+    keep_alive_timeout: Option<Duration>,
+    /// Whether connections are handled on a thread pool, which enables
+    /// keep-alive unless a timeout is set, see [Server::set_keep_alive_timeout].
+    concurrent: bool,
+    max_idle_connections: Option<usize>,
+    /// The number of kept-alive connections waiting for their next request.
+    idle_connections: AtomicUsize,
+    /// The number of handlers currently running, see [Server::in_flight].
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Counts a running handler for [Server::in_flight] while it is alive, so the
+/// count also drops if the handler panics.
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InFlightGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
+/// The default for [Server::set_max_body_size], 8 MiB.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+
+/// The minimum body length compressed by [Server::enable_compression], 1 KiB.
+pub const DEFAULT_MIN_COMPRESSED_LENGTH: usize = 1024;
+
+/// The default for [Server::set_keep_alive_timeout] with
+/// [Server::bind_and_run_with_threads], 5 seconds.
+pub const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl Server {
     /// Creates a new server, which can be configured further and then started
     /// by calling [Server::bind_and_run].
@@ -34,6 +174,12 @@ impl Server {
     /// Adds an endpoint to the server. A handler takes a [Request] and returns a
     /// [Response]. Response implements [From<&str>], which makes it easy to send
     /// text back to the client.
+    ///
+    /// A `{name}` segment of the path matches any single segment, a trailing
+    /// `{*name}` segment the rest of the path (possibly empty); their values
+    /// are available through [Request::params]. If several routes match, the
+    /// most specific one wins: a literal segment beats a `{name}`, which
+    /// beats a wildcard.
     /// ```
     /// use raspi_file_server::*;
     ///
@@ -50,59 +196,3356 @@ impl Server {
     /// ```
     pub fn add_route<F>(&mut self, method: HttpMethod, path: &str, handler: F) -> &mut Self
     where
-        F: Fn(&Request) -> Response + 'static,
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
     {
-        self.routes
-            .push((method, path.to_string(), Box::new(handler)));
+        self.route().method(method).path(path).handler(handler)
+    }
+
+    /// Like [Server::add_route], but also registers the path under `name`,
+    /// so links to the route can be generated with [Server::url_for] instead
+    /// of repeating the path.
+    pub fn add_named_route<F>(
+        &mut self,
+        name: &str,
+        method: HttpMethod,
+        path: &str,
+        handler: F,
+    ) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route_names.insert(name.to_string(), path.to_string());
+        self.add_route(method, path, handler)
+    }
+
+    /// Builds the path of the route registered as `name`, substituting the
+    /// `{param}` segments with the (percent-encoded) values of `params`,
+    /// below the [base path](Server::set_base_path) if there is one. The value
+    /// of a `{*name}` segment may span several segments, its `/` are kept.
+    /// Returns None if there is no such route or a parameter is missing.
+    /// ```
+    /// use raspi_file_server::*;
+    /// use std::collections::HashMap;
+    ///
+    /// let mut server = Server::new();
+    /// server.add_named_route("user", HttpMethod::GET, "/user/{id}", |_| Response::empty());
+    ///
+    /// let params = HashMap::from([("id", "42")]);
+    /// assert_eq!(server.url_for("user", &params), Some("/user/42".to_string()));
+    /// assert_eq!(server.url_for("user", &HashMap::new()), None);
+    /// ```
+    pub fn url_for(&self, name: &str, params: &HashMap<&str, &str>) -> Option<String> {
+        let pattern = self.route_names.get(name)?;
+        pattern
+            .split('/')
+            .map(|segment| {
+                if let Some(param) = request::utils::splat_name(segment) {
+                    return params.get(param).map(|value| {
+                        value
+                            .split('/')
+                            .map(request::utils::percent_encode)
+                            .collect::<Vec<_>>()
+                            .join("/")
+                    });
+                }
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.strip_suffix('}'))
+                {
+                    Some(param) => params
+                        .get(param)
+                        .map(|value| request::utils::percent_encode(value)),
+                    None => Some(segment.to_string()),
+                }
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|segments| {
+                format!(
+                    "{}{}",
+                    self.base_path.as_deref().unwrap_or_default(),
+                    segments.join("/")
+                )
+            })
+    }
+
+    /// Adds an endpoint for several methods which share one handler. The
+    /// handler can tell them apart through [Request::method].
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route_multi(&[HttpMethod::GET, HttpMethod::POST], "/guestbook", |req| {
+    ///             match req.method() {
+    ///                 HttpMethod::POST => Response::with_status(HttpStatusCode::Created),
+    ///                 _ => "<h1>Guestbook</h1>".into(),
+    ///             }
+    ///         })
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn add_route_multi<F>(
+        &mut self,
+        methods: &[HttpMethod],
+        path: &str,
+        handler: F,
+    ) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        for method in methods {
+            let handler = Arc::clone(&handler);
+            self.add_route(method.clone(), path, move |req| handler(req));
+        }
         self
     }
 
-    /// Starts the server, bound to the specified address. The address can be passed
-    /// in different formats, which implement [ToSocketAddrs].
-    pub fn bind_and_run<A: ToSocketAddrs>(&mut self, address: A) -> std::io::Result<()> {
-        let listener = TcpListener::bind(address)?;
-        for stream in listener.incoming().filter_map(Result::ok) {
-            self.handle_request(stream)?;
+    /// Starts registering a route with a [RouteBuilder], which allows
+    /// combining multiple constraints like the method, the path and the host.
+    /// Without further constraints, the route matches `GET /` on any host.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .route()
+    ///         .host("api.local")
+    ///         .method(HttpMethod::PUT)
+    ///         .path("/users/{id}")
+    ///         .handler(|_| Response::default())
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn route(&mut self) -> RouteBuilder<'_> {
+        RouteBuilder {
+            server: self,
+            method: HttpMethod::GET,
+            path: "/".to_string(),
+            host: None,
+            cached: false,
         }
-        Ok(())
     }
 
-    fn handle_request(&self, mut stream: TcpStream) -> std::io::Result<()> {
-        let mut request = {
-            let mut buffer = [0; 5120];
-            let _ = stream.read(&mut buffer)?;
-            let content = String::from_utf8_lossy(&buffer).to_string();
-            let request_result = request::utils::parse_request_from_http_request_body(content);
-            if let Err(err) = request_result {
-                stream.write_all(response_into_http_response_string(err.into()).as_bytes())?;
-                return Ok(());
-            }
-            request_result.unwrap()
-        };
+    /// Serves the files below `fs_root` under the path `url`.
+    ///
+    /// A GET request to `url/css/site.css` is answered with the file
+    /// `fs_root/css/site.css`, with a `content-type` guessed from the extension
+    /// (see [mime::mime_from_extension]). For a directory, its `index.html` is
+    /// served. Paths escaping `fs_root` (e.g. through `..` or a symlink) and
+    /// missing files are answered by the not-found handler, see
+    /// [Server::set_not_found_handler].
+    ///
+    /// If a file has precompressed companions like `app.js.br` or `app.js.gz`,
+    /// the one the client accepts with the highest quality (according to its
+    /// `accept-encoding` header) is served instead, with the matching
+    /// `content-encoding`. Brotli wins a tie.
+    ///
+    /// Responses carry a weak `etag` computed from size and modification time,
+    /// `last-modified` and `cache-control: no-cache`, so clients revalidate
+    /// their copy and get a `304 Not Modified` as long as the file is unchanged.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .serve_dir("/static", "/home/pi/www")
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn serve_dir<P: Into<PathBuf>>(&mut self, url: &str, fs_root: P) -> &mut Self {
+        let mount = url.trim_end_matches('/').to_string();
+        let fs_root = fs_root.into();
+        self.add_mounted_routes(url.trim_end_matches('/'), move |req: &Request| {
+            files::serve_file(req, &mount, &fs_root)
+        })
+    }
 
-        if let Some((_, route, handler)) = self.routes.iter().find(|(method, route, _)| {
-            (*method == request.method()) && request::utils::request_matches_route(&request, route)
-        }) {
-            request::utils::set_request_params_according_to_match(&mut request, route);
-            let response = handler(&request);
-            stream.write_all(response_into_http_response_string(response).as_bytes())?;
-        }
-        Ok(())
+    /// Serves the directories below `fs_root` as ZIP archives.
+    ///
+    /// A GET request to `url` or a path below it, which carries the query
+    /// `?download=zip` and points to a directory, is answered with a ZIP archive
+    /// of that directory (including subdirectories). The archive is streamed to
+    /// the client while it is being generated. Paths escaping `fs_root` as well
+    /// as all other requests are answered with `404 Not Found`.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     // GET /files/photos?download=zip downloads /home/pi/shared/photos
+    ///     Server::new()
+    ///         .serve_dir_as_zip("/files", "/home/pi/shared")
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn serve_dir_as_zip<P: Into<PathBuf>>(&mut self, url: &str, fs_root: P) -> &mut Self {
+        let mount = url.trim_end_matches('/').to_string();
+        let fs_root = fs_root.into();
+        self.add_mounted_routes(url.trim_end_matches('/'), move |req: &Request| {
+            files::serve_zip(req, &mount, &fs_root)
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Forwards GET requests to `url` and the paths below it to the server at
+    /// `upstream`, e.g. `"127.0.0.1:3000"`, and answers with its response.
+    ///
+    /// The path is forwarded unchanged; hop-by-hop headers like `connection`,
+    /// `upgrade` or those named in `connection` are dropped. Of the upstream
+    /// response, the status, the body and all headers with a [HttpHeaderName]
+    /// are passed on; repeated headers like `set-cookie` keep all of their
+    /// values. The body is streamed to the client as it arrives, so large
+    /// downloads aren't held in memory. If the upstream can't be reached or
+    /// stalls for more than 30 seconds, the client gets `502 Bad Gateway`.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .proxy("/api", "127.0.0.1:3000")
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn proxy(&mut self, url: &str, upstream: &str) -> &mut Self {
+        let upstream = upstream.to_string();
+        self.add_mounted_routes(url.trim_end_matches('/'), move |req: &Request| {
+            proxy::forward(req, &upstream)
+        })
+    }
 
-    #[test]
-    fn test_add_route() {
-        let mut server = Server::new();
-        assert_eq!(server.routes.len(), 0);
-        server.add_route(HttpMethod::GET, "/", |_| Response::default());
-        assert_eq!(server.routes.len(), 1);
-        let (m, p, _) = server.routes.get(0).unwrap();
-        assert_eq!(*m, HttpMethod::GET);
-        assert_eq!(*p, "/");
+    /// Registers `handler` for GET requests to `mount` and everything below it.
+    fn add_mounted_routes<F>(&mut self, mount: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Clone + Send + Sync + 'static,
+    {
+        let route = if mount.is_empty() { "/" } else { mount };
+        let wildcard_route = format!("{}/*", mount);
+        self.add_route(HttpMethod::GET, route, handler.clone())
+            .add_route(HttpMethod::GET, &wildcard_route, handler)
+    }
+
+    /// Limits the number of simultaneous connections a single client IP may
+    /// have open. Further connections from that IP are answered with
+    /// `503 Service Unavailable` and closed, the limit counts kept-alive
+    /// connections too. By default there is no limit.
+    pub fn set_max_connections_per_ip(&mut self, max: usize) -> &mut Self {
+        self.connections.set_max_per_ip(max);
+        self
+    }
+
+    /// Sets the maximum size of a request body in bytes. Requests declaring a
+    /// larger `content-length`, or whose chunked body grows beyond it, are
+    /// rejected with `413 Content Too Large` before the body is read
+    /// completely.
+    /// Defaults to [DEFAULT_MAX_BODY_SIZE].
+    pub fn set_max_body_size(&mut self, bytes: usize) -> &mut Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Switches between production mode (the default) and development mode.
+    ///
+    /// If a handler, middleware or the not found handler panics, the client
+    /// gets `500 Internal Server Error` and the connection stays usable. In
+    /// production mode, such responses carry a generic page, which also
+    /// replaces the body of any 500 response returned by a handler, so that no
+    /// internals leak. In development mode, the page of a panic names the route
+    /// and the panic message, and 500 responses of handlers are sent unchanged.
+    pub fn set_production(&mut self, production: bool) -> &mut Self {
+        self.development = !production;
+        self
+    }
+
+    /// Runs `hook` for each accepted connection before anything is read from
+    /// it, e.g. for counting connections per peer or setting socket options.
+    /// A panicking hook doesn't affect the connection.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .on_connection(|stream| {
+    ///             let _ = stream.set_nodelay(true);
+    ///             if let Ok(peer) = stream.peer_addr() {
+    ///                 println!("connection from {}", peer);
+    ///             }
+    ///         })
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn on_connection<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&TcpStream) + Send + Sync + 'static,
+    {
+        self.connection_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs `hook` after each response has been written, with the method and
+    /// path of the request, the status and how long answering took. This is
+    /// meant for logging or recording metrics in any way. Requests which
+    /// couldn't be parsed aren't reported. A panicking hook doesn't affect the
+    /// connection.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .on_request(|log| {
+    ///             let code = usize::from(log.status);
+    ///             println!("{} {} {} in {:?}", log.method, log.path, code, log.duration);
+    ///         })
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn on_request<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&RequestLog) + Send + Sync + 'static,
+    {
+        self.request_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the handler for requests which no route matches, e.g. for a
+    /// custom error page. By default, they get `404 Not Found` with a small
+    /// HTML page.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "<h1>Index</h1>".into())
+    ///         .set_not_found_handler(|_| {
+    ///             Response::with_status(HttpStatusCode::NotFound)
+    ///                 .with_html("<h1>Nothing here</h1><a href=\"/\">Back to the index</a>")
+    ///         })
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_not_found_handler<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.not_found_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Writes a line per response to stdout, in the Common Log Format or the
+    /// combined format which adds referer and user agent.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "<h1>Index</h1>".into())
+    ///         .enable_access_log(LogFormat::Combined)
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn enable_access_log(&mut self, format: LogFormat) -> &mut Self {
+        self.enable_access_log_to(format, std::io::stdout())
+    }
+
+    /// Like [Server::enable_access_log], but writes the lines to `writer`,
+    /// e.g. a log file.
+    pub fn enable_access_log_to<W>(&mut self, format: LogFormat, writer: W) -> &mut Self
+    where
+        W: Write + Send + 'static,
+    {
+        self.access_log = Some(access_log::AccessLog::new(format, writer));
+        self
+    }
+
+    /// Allows cross-origin requests from `origin`, e.g. `https://example.com`
+    /// or `*`: all responses get an `access-control-allow-origin` header,
+    /// unless the handler set one, and CORS preflight requests are answered
+    /// with the methods of the routes registered for the path and the
+    /// requested headers.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::PUT, "/api/notes/{id}", |_| Response::empty())
+    ///         .enable_cors("https://notes.example.com")
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn enable_cors<S: ToString>(&mut self, origin: S) -> &mut Self {
+        self.cors_origin = Some(origin.to_string());
+        self
+    }
+
+    /// Returns how many connections and requests the server has handled so
+    /// far. The same summary is returned when the server stops running.
+    pub fn stats(&self) -> ServerStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns how many route handlers are running right now. After a
+    /// [shutdown](ServerHandle::shutdown),
+    /// [Server::bind_and_run_with_threads] only returns once this dropped to
+    /// zero, so the requests in flight are finished.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Enables or disables answering HEAD requests with the GET route of the
+    /// path, if there is no HEAD route for it. The handler runs as for a GET
+    /// request, but only the head of its response is sent, including the
+    /// `content-length` of the body. Enabled by default; when disabled, such
+    /// requests get `405 Method Not Allowed`.
+    pub fn set_auto_head(&mut self, enabled: bool) -> &mut Self {
+        self.manual_head = !enabled;
+        self
+    }
+
+    /// Enables or disables answering OPTIONS requests to paths without an
+    /// OPTIONS route with `204 No Content` and an `allow` header, which lists
+    /// the methods of the routes matching the path. Enabled by default; when
+    /// disabled, such requests get `405 Method Not Allowed`.
+    pub fn set_auto_options(&mut self, enabled: bool) -> &mut Self {
+        self.manual_options = !enabled;
+        self
+    }
+
+    /// Limits how long reading a request and writing a response may stall,
+    /// per read and write on the connection. A client which doesn't finish
+    /// its request in time gets `408 Request Timeout` and is disconnected,
+    /// so it can't block the server. A zero duration disables the timeout,
+    /// which is the default.
+    /// ```
+    /// use raspi_file_server::*;
+    /// use std::time::Duration;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .set_timeouts(Duration::from_secs(10), Duration::from_secs(30))
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_timeouts(&mut self, read: Duration, write: Duration) -> &mut Self {
+        self.read_timeout = (!read.is_zero()).then_some(read);
+        self.write_timeout = (!write.is_zero()).then_some(write);
+        self
+    }
+
+    /// Compresses responses with gzip if the client accepts it, saving
+    /// bandwidth on a slow uplink. Only textual bodies (HTML, JSON, CSS, ...)
+    /// of at least [DEFAULT_MIN_COMPRESSED_LENGTH] held in memory are
+    /// compressed; streamed bodies like files, and types like images which
+    /// are usually compressed already, are sent as they are. A strong `etag`
+    /// of a compressed response is weakened, as its bytes differ from the
+    /// uncompressed ones.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "<h1>Hello</h1>".repeat(100).into())
+    ///         .enable_compression()
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn enable_compression(&mut self) -> &mut Self {
+        self.enable_compression_with_min_size(DEFAULT_MIN_COMPRESSED_LENGTH)
+    }
+
+    /// Like [Server::enable_compression], but compresses bodies of at least
+    /// `min_size` bytes. Shorter ones are sent as they are, whatever the
+    /// client accepts.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "<h1>Hello</h1>".repeat(100).into())
+    ///         .enable_compression_with_min_size(4096)
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn enable_compression_with_min_size(&mut self, min_size: usize) -> &mut Self {
+        self.compression = Some(min_size);
+        self
+    }
+
+    /// Matches the literal segments of route paths ignoring ASCII case, so
+    /// that `/About` is routed to `/about`. Params keep the case they were
+    /// sent in.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/about", |_| "<h1>About</h1>".into())
+    ///         .enable_case_insensitive_paths()
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn enable_case_insensitive_paths(&mut self) -> &mut Self {
+        self.path_matching.case_insensitive = true;
+        self
+    }
+
+    /// Sets whether a trailing slash is significant when matching routes. By
+    /// default it is not and `/x/` matches the route `/x`; if `strict` is
+    /// true, `/x/` only matches `/x/` and `/x` only `/x`. Routes ending with
+    /// a wildcard match either way.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/docs/", |_| "<h1>Docs</h1>".into())
+    ///         .set_strict_trailing_slash(true)
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_strict_trailing_slash(&mut self, strict: bool) -> &mut Self {
+        self.path_matching.strict_trailing_slash = strict;
+        self
+    }
+
+    /// Sets how long a connection is kept open after a response, waiting for
+    /// the next request of the client. HTTP/1.1 clients keep connections open
+    /// unless they send `connection: close`. A zero duration disables
+    /// keep-alive, so each connection serves a single request.
+    ///
+    /// If not set, [Server::bind_and_run_with_threads] uses
+    /// [DEFAULT_KEEP_ALIVE_TIMEOUT], while [Server::bind_and_run] disables
+    /// keep-alive: it can't accept other connections while it waits for the
+    /// next request on an idle one, so a single client could hold it.
+    pub fn set_keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Limits how many connections may be kept open at once while waiting
+    /// for their next request. Once the limit is reached, connections are
+    /// closed after their response instead, answered with `connection: close`.
+    /// Not limited by default.
+    /// ```
+    /// use raspi_file_server::*;
+    /// use std::time::Duration;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .set_keep_alive_timeout(Duration::from_secs(2))
+    ///         .set_max_idle_connections(16)
+    ///         .bind_and_run_with_threads("127.0.0.1:8080", 4)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_max_idle_connections(&mut self, max: usize) -> &mut Self {
+        self.max_idle_connections = Some(max);
+        self
+    }
+
+    /// Registers a parser turning request bodies of `media_type` (e.g.
+    /// `application/json`) into a `T`, which handlers use through
+    /// [Request::parse_body]. Parsers for several media types can produce
+    /// the same type, so one handler accepts all of these formats.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// struct Note {
+    ///     title: String,
+    /// }
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .register_body_parser("application/x-www-form-urlencoded", |req| {
+    ///             match req.form().get("title") {
+    ///                 Some(title) => Ok(Note { title: title.clone() }),
+    ///                 None => Err("missing title"),
+    ///             }
+    ///         })
+    ///         .add_route(HttpMethod::POST, "/notes", |req| {
+    ///             req.parse_body::<Note>()
+    ///                 .map(|note| Response::from(format!("<h1>{}</h1>", note.title)))
+    ///                 .into()
+    ///         })
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn register_body_parser<T, E, F>(&mut self, media_type: &str, parser: F) -> &mut Self
+    where
+        T: 'static,
+        E: std::fmt::Display,
+        F: Fn(&Request) -> Result<T, E> + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.body_parsers).insert(media_type, parser);
+        self
+    }
+
+    /// Adds middleware, which runs around the handlers of all routes, e.g. for
+    /// checking authentication or adding headers. It gets the request (with
+    /// the params of the matched route) and the rest of the chain as [Next].
+    /// Middleware runs in the order it was added; the first one is the
+    /// outermost. It also runs for requests without a matching route.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_middleware(|req, next| {
+    ///             next.run(req)
+    ///                 .with_header(HttpHeaderName::Server, "raspi-file-server")
+    ///         })
+    ///         .add_middleware(|req, next| match req.header("authorization") {
+    ///             Some(_) => next.run(req),
+    ///             None => Response::with_status(HttpStatusCode::Unauthorized),
+    ///         })
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn add_middleware<F>(&mut self, middleware: F) -> &mut Self
+    where
+        F: Fn(&Request, Next) -> Response + Send + Sync + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Like [Server::add_middleware], but the middleware only runs for paths
+    /// below `prefix`, e.g. `/admin` and `/admin/users` for the prefix
+    /// `/admin`, but not `/administrator`. Other requests skip it.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_middleware_for("/admin", |req, next| match req.header("authorization") {
+    ///             Some(_) => next.run(req),
+    ///             None => Response::with_status(HttpStatusCode::Unauthorized),
+    ///         })
+    ///         .add_route(HttpMethod::GET, "/admin/stats", |_| "stats".into())
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn add_middleware_for<F>(&mut self, prefix: &str, middleware: F) -> &mut Self
+    where
+        F: Fn(&Request, Next) -> Response + Send + Sync + 'static,
+    {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        self.add_middleware(move |req, next| {
+            if files::path_below_mount(req, &prefix).is_some() {
+                middleware(req, next)
+            } else {
+                next.run(req)
+            }
+        })
+    }
+
+    /// Makes the server answer GET and HEAD requests which carry a body, i.e.
+    /// a non-zero `content-length` or a `transfer-encoding`, with `400 Bad
+    /// Request` instead of routing them. Such bodies have no meaning and may
+    /// be used to smuggle requests past proxies which disagree about them.
+    /// Disabled by default.
+    pub fn set_reject_get_body(&mut self, reject: bool) -> &mut Self {
+        self.reject_get_body = reject;
+        self
+    }
+
+    /// Returns a handle to the cache of responses of routes registered with
+    /// [RouteBuilder::cached], e.g. for invalidating entries after a change or
+    /// on a `PURGE` request.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut server = Server::new();
+    ///     let cache = server.response_cache();
+    ///     server
+    ///         .route()
+    ///         .path("/*")
+    ///         .cached()
+    ///         .handler(|_| "expensive".into())
+    ///         .route()
+    ///         .method(HttpMethod::Other("PURGE".to_string()))
+    ///         .path("/*")
+    ///         .handler(move |req| {
+    ///             cache.invalidate(req.path_as_str());
+    ///             Response::empty()
+    ///         })
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn response_cache(&self) -> ResponseCache {
+        self.cache.clone()
+    }
+
+    /// Enables format suffixes on the last path segment, Rails-style.
+    ///
+    /// If the last segment of a request path ends in one of the given
+    /// extensions and the path without it matches a route, the extension is
+    /// stripped and available through [Request::format]. Otherwise the path is
+    /// matched as is.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .set_format_extensions(&["json", "html"])
+    ///         .add_route(HttpMethod::GET, "/users/{id}", user_route)
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    ///
+    /// // GET /users/1.json responds with JSON, GET /users/1 with HTML
+    /// fn user_route(req: &Request) -> Response {
+    ///     let id = req.params().get("id").unwrap();
+    ///     let mut response = Response::default();
+    ///     match req.format() {
+    ///         Some("json") => response.set_json(format!("{{\"id\":\"{}\"}}", id)),
+    ///         _ => response.set_html(format!("<h1>User {}</h1>", id)),
+    ///     }
+    ///     response
+    /// }
+    /// ```
+    pub fn set_format_extensions(&mut self, extensions: &[&str]) -> &mut Self {
+        self.format_extensions = extensions.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// Serves the application below `base`, e.g. when a reverse proxy
+    /// forwards everything under `/app` to it. The prefix is stripped from
+    /// the path before routing, so routes are registered without it and
+    /// handlers see the path below it. Requests outside of the base path get
+    /// `404 Not Found`.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     // GET /app/home is handled by the route /home
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/home", |_| "<h1>Home</h1>".into())
+    ///         .set_base_path("/app")
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_base_path(&mut self, base: &str) -> &mut Self {
+        let base = base.trim_end_matches('/');
+        self.base_path = (!base.is_empty()).then(|| base.to_string());
+        self
+    }
+
+    /// Returns a handle for stopping the server from another thread once it
+    /// runs.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut server = Server::new();
+    ///     server.add_route(HttpMethod::GET, "/", |_| "hello".into());
+    ///     let handle = server.handle();
+    ///     let running = std::thread::spawn(move || server.bind_and_run("127.0.0.1:8080"));
+    ///
+    ///     std::thread::sleep(std::time::Duration::from_secs(60));
+    ///     handle.shutdown();
+    ///     let stats = running.join().unwrap()?;
+    ///     println!("served {} requests", stats.requests);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Switches maintenance mode on or off. While it is on, every request is
+    /// answered with `503 Service Unavailable` and a `Retry-After` header of
+    /// `retry_after` in whole seconds, except for the paths added with
+    /// [Server::allow_during_maintenance]. A running server is switched
+    /// through [ServerHandle::set_maintenance_mode].
+    /// ```
+    /// use raspi_file_server::*;
+    /// use std::time::Duration;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .add_route(HttpMethod::GET, "/healthz", |_| "ok".into())
+    ///         .allow_during_maintenance("/healthz")
+    ///         .set_maintenance_mode(true, Duration::from_secs(600))
+    ///         .bind_and_run("127.0.0.1:8080")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_maintenance_mode(&mut self, enabled: bool, retry_after: Duration) -> &mut Self {
+        self.handle.set_maintenance_mode(enabled, retry_after);
+        self
+    }
+
+    /// Keeps serving requests for paths matching `path` in maintenance mode,
+    /// e.g. a health check. The path may contain parameters like a route.
+    pub fn allow_during_maintenance(&mut self, path: &str) -> &mut Self {
+        self.maintenance_allowlist.push(path.to_string());
+        self
+    }
+
+    /// Starts the server, bound to the specified address. The address can be passed
+    /// in different formats, which implement [ToSocketAddrs].
+    ///
+    /// Connections are handled one after another, see
+    /// [Server::bind_and_run_with_threads] for handling them concurrently.
+    /// Therefore connections aren't kept alive, unless enabled through
+    /// [Server::set_keep_alive_timeout].
+    /// Errors while handling a connection only end that connection. The
+    /// server runs until it is stopped through its [ServerHandle]. Then a
+    /// summary of the handled connections and requests is returned, see
+    /// [Server::stats].
+    pub fn bind_and_run<A: ToSocketAddrs>(&mut self, address: A) -> std::io::Result<ServerStats> {
+        self.concurrent = false;
+        let listener = self.bind(address)?;
+        for stream in listener.incoming().filter_map(Result::ok) {
+            if self.handle.is_stopping() {
+                break;
+            }
+            if let Some((stream, _guard)) = self.admit(stream) {
+                self.handle_isolated(stream);
+            }
+        }
+        self.handle.set_local_addr(None);
+        Ok(self.stats())
+    }
+
+    /// Starts the server like [Server::bind_and_run], but handles connections
+    /// concurrently on a pool of `threads` worker threads (at least one), so a
+    /// slow handler doesn't block other clients.
+    ///
+    /// Accepted connections wait in a queue until a worker is free. Errors and
+    /// panics while handling a connection only end that connection, the
+    /// worker goes on with the next one. The workers are named
+    /// `rfs-worker-N`, counting from 0, which shows up in panic messages and
+    /// debuggers.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn start_server() -> Result<(), Box<dyn std::error::Error>> {
+    ///     Server::new()
+    ///         .add_route(HttpMethod::GET, "/", |_| "hello".into())
+    ///         .bind_and_run_with_threads("127.0.0.1:8080", 4)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn bind_and_run_with_threads<A: ToSocketAddrs>(
+        &mut self,
+        address: A,
+        threads: usize,
+    ) -> std::io::Result<ServerStats> {
+        self.concurrent = true;
+        let listener = self.bind(address)?;
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Mutex::new(receiver);
+        let server = &*self;
+        thread::scope(|scope| {
+            for index in 0..threads.max(1) {
+                thread::Builder::new()
+                    .name(format!("rfs-worker-{}", index))
+                    .spawn_scoped(scope, || loop {
+                        // the lock is released at the end of the statement,
+                        // before the connection is handled
+                        let next = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                        match next {
+                            Ok((stream, _guard)) => server.handle_isolated(stream),
+                            Err(_) => break,
+                        }
+                    })?;
+            }
+            for stream in listener.incoming().filter_map(Result::ok) {
+                if server.handle.is_stopping() {
+                    break;
+                }
+                if let Some(connection) = server.admit(stream) {
+                    let _ = sender.send(connection);
+                }
+            }
+            // the workers stop once the queue is empty and the sender dropped
+            drop(sender);
+            server.handle.set_local_addr(None);
+            Ok(server.stats())
+        })
+    }
+
+    /// Binds the listener and tells the handle where the server listens.
+    fn bind<A: ToSocketAddrs>(&self, address: A) -> std::io::Result<TcpListener> {
+        let listener = TcpListener::bind(address)?;
+        self.handle.set_local_addr(Some(listener.local_addr()?));
+        // a shutdown before the address was known couldn't wake up accept
+        if self.handle.is_stopping() {
+            self.handle.shutdown();
+        }
+        Ok(listener)
+    }
+
+    /// Checks the connection against the per-IP limit. Refused connections are
+    /// answered with `503 Service Unavailable` and None is returned.
+    ///
+    /// The peer address is only unknown if the client has disconnected
+    /// already, so such connections are closed without an answer.
+    fn admit(&self, mut stream: TcpStream) -> Option<(TcpStream, connections::ConnectionGuard)> {
+        let Ok(peer) = stream.peer_addr() else {
+            return None;
+        };
+        let guard = self.connections.try_acquire(peer.ip());
+        match guard {
+            Some(guard) => Some((stream, guard)),
+            None => {
+                let response = Response::with_status(HttpStatusCode::ServiceUnavailable);
+                let _ = write_response(response, &mut stream);
+                None
+            }
+        }
+    }
+
+    /// Serves the requests of a connection until the client closes it, asks
+    /// for closing it, or stays idle for longer than the keep-alive timeout.
+    fn handle_request(&self, stream: TcpStream) -> std::io::Result<()> {
+        self.stats.record_connection();
+        if let Some(hook) = &self.connection_hook {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&stream)));
+        }
+        stream.set_write_timeout(self.write_timeout)?;
+        let mut reader = reader::RequestReader::new(&stream);
+        stream.set_read_timeout(self.read_timeout)?;
+        while self.serve_request(&mut reader, &stream)? {
+            // the connection holds an idle slot until the next request starts
+            let next = stream
+                .set_read_timeout(Some(self.keep_alive_timeout()))
+                .and_then(|_| reader.wait_for_request());
+            self.idle_connections.fetch_sub(1, Ordering::SeqCst);
+            if !matches!(next, Ok(true)) {
+                break;
+            }
+            stream.set_read_timeout(self.read_timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Reads and answers the next request on the connection. Returns true if
+    /// the connection stays open for another request, in which case it got
+    /// one of the idle slots limited by [Server::set_max_idle_connections].
+    fn serve_request(
+        &self,
+        reader: &mut reader::RequestReader<&TcpStream>,
+        stream: &TcpStream,
+    ) -> std::io::Result<bool> {
+        let max_body_size = self.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE);
+        // after a malformed request the next one can't be found reliably, so
+        // the connection is closed
+        let content = match reader.read_request(max_body_size) {
+            Ok(content) => content,
+            Err(reader::ReadError::Io(err)) => return Err(err),
+            Err(err) => {
+                let response = Response::from(err).with_header(HttpHeaderName::Connection, "close");
+                self.respond(None, response, stream)?;
+                return Ok(false);
+            }
+        };
+        let mut request = match request::utils::parse_request_from_bytes(content) {
+            Ok(request) => request,
+            Err(err) => {
+                let response = Response::from(err).with_header(HttpHeaderName::Connection, "close");
+                self.respond(None, response, stream)?;
+                return Ok(false);
+            }
+        };
+        if let Ok(connection) = stream.try_clone() {
+            request::utils::set_request_connection(&mut request, connection);
+        }
+        request::utils::set_request_body_parsers(&mut request, Arc::clone(&self.body_parsers));
+
+        let started = Instant::now();
+        let mut response = self.answer(&mut request);
+        if let Err(err) = response.validate() {
+            response = self.invalid_response(&err);
+        }
+        let keep_alive = request.is_keep_alive() && self.reserve_idle_slot();
+        let token = if !keep_alive {
+            Some("close")
+        } else if request
+            .connection_tokens()
+            .iter()
+            .any(|t| t == "keep-alive")
+        {
+            // HTTP/1.0 clients only keep the connection if told so
+            Some("keep-alive")
+        } else {
+            None
+        };
+        if let Some(token) = token {
+            // keep tokens set by the handler, e.g. `upgrade`
+            let value = match response.header(HttpHeaderName::Connection) {
+                Some(existing) => format!("{}, {}", existing, token),
+                None => token.to_string(),
+            };
+            response.set_header(HttpHeaderName::Connection, value);
+        }
+        let status = response.status_code();
+        let result = self.respond(Some(&request), response, stream);
+        if let Some(hook) = &self.request_hook {
+            let log = RequestLog {
+                method: request.method(),
+                path: request.path_as_str().to_string(),
+                status,
+                duration: started.elapsed(),
+            };
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&log)));
+        }
+        match result {
+            Ok(()) => Ok(keep_alive),
+            Err(err) => {
+                if keep_alive {
+                    self.idle_connections.fetch_sub(1, Ordering::SeqCst);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Creates the response to a parsed request.
+    fn answer(&self, request: &mut Request) -> Response {
+        let method = request.method();
+        if self.reject_get_body && matches!(method, HttpMethod::GET | HttpMethod::HEAD) {
+            let has_body = request.header("transfer-encoding").is_some()
+                || request
+                    .header("content-length")
+                    .is_some_and(|length| length.trim() != "0");
+            if has_body {
+                let mut response = Response::with_status(HttpStatusCode::BadRequest)
+                    .with_html("<h1>Bad Request</h1>");
+                if method == HttpMethod::HEAD {
+                    response.set_head_only();
+                }
+                return response;
+            }
+        }
+        let below_base = match &self.base_path {
+            Some(base) => request::utils::strip_base_path(request, base),
+            None => true,
+        };
+        let maintenance = self.handle.maintenance().filter(|_| {
+            !self.maintenance_allowlist.iter().any(|path| {
+                request::utils::path_matches_route_with(
+                    request.path_as_str(),
+                    path,
+                    self.path_matching,
+                )
+            })
+        });
+        let mut response = match (self.cors_preflight(request), maintenance) {
+            _ if !below_base => self.not_found(request),
+            (_, Some(retry_after)) => maintenance_response(retry_after),
+            (Some(response), None) => response,
+            (None, None) => self.dispatch(&method, request),
+        };
+        if let Some(origin) = &self.cors_origin {
+            if response
+                .header(HttpHeaderName::AccessControlAllowOrigin)
+                .is_none()
+            {
+                response.set_header(HttpHeaderName::AccessControlAllowOrigin, origin);
+            }
+        }
+        if let Some(min_length) = self.compression {
+            compression::compress(request, &mut response, min_length);
+        }
+        if method == HttpMethod::HEAD {
+            response.set_head_only();
+        }
+        response
+    }
+
+    fn keep_alive_timeout(&self) -> Duration {
+        let default = match self.concurrent {
+            true => DEFAULT_KEEP_ALIVE_TIMEOUT,
+            false => Duration::ZERO,
+        };
+        self.keep_alive_timeout.unwrap_or(default)
+    }
+
+    /// Takes one of the idle slots for keeping a connection open, unless
+    /// keep-alive is disabled, the server is shutting down or all slots are
+    /// taken.
+    fn reserve_idle_slot(&self) -> bool {
+        if self.keep_alive_timeout().is_zero() || self.handle.is_stopping() {
+            return false;
+        }
+        let max = self.max_idle_connections.unwrap_or(usize::MAX);
+        self.idle_connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |idle| {
+                (idle < max).then_some(idle + 1)
+            })
+            .is_ok()
+    }
+
+    /// Routes the request to its handler, or answers it with a 404, 405 or
+    /// the automatic OPTIONS response if there is none.
+    fn dispatch(&self, method: &HttpMethod, request: &mut Request) -> Response {
+        let mut matched = self.match_route(method, request);
+        if matched.is_none() && *method == HttpMethod::HEAD && !self.manual_head {
+            matched = self.match_route(&HttpMethod::GET, request);
+        }
+        let route = matched.map(|(route, path)| {
+            request::utils::set_request_params_from_path(request, &path, &route.path);
+            route
+        });
+        let endpoint = |request: &Request| self.endpoint(method, route, request);
+        // panics of handlers are caught in `run_handler`, this catches the
+        // ones of middleware and the not found handler
+        let request = &*request;
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            Next::new(&self.middleware, &endpoint).run(request)
+        }))
+        .unwrap_or_else(|payload| {
+            let origin = format!("Handling {} {}", method, request.path_as_str());
+            self.panic_response(&origin, payload.as_ref())
+        })
+    }
+
+    /// Runs the handler of the matched route, or answers the request itself
+    /// if no route matched.
+    fn endpoint(&self, method: &HttpMethod, route: Option<&Route>, request: &Request) -> Response {
+        match route {
+            Some(route) => {
+                let response = self.run_handler(route, request);
+                if response.is_fall_through() {
+                    self.not_found(request)
+                } else {
+                    conditional::evaluate(request, response)
+                }
+            }
+            None => match self.allowed_methods(request) {
+                None => self.not_found(request),
+                Some(allow) if *method == HttpMethod::OPTIONS && !self.manual_options => {
+                    Response::with_status(HttpStatusCode::NoContent)
+                        .with_header(HttpHeaderName::Allow, allow)
+                }
+                Some(allow) => Response::with_status(HttpStatusCode::MethodNotAllowed)
+                    .with_header(HttpHeaderName::Allow, allow),
+            },
+        }
+    }
+
+    /// Answers a CORS preflight request if CORS is enabled, advertising the
+    /// methods of the routes registered for the path. Returns None for other
+    /// requests and for paths without routes.
+    fn cors_preflight(&self, request: &Request) -> Option<Response> {
+        self.cors_origin.as_ref()?;
+        if request.method() != HttpMethod::OPTIONS
+            || request.header("access-control-request-method").is_none()
+        {
+            return None;
+        }
+        let allow = self.allowed_methods(request)?;
+        let mut response = Response::with_status(HttpStatusCode::NoContent)
+            .with_header(HttpHeaderName::Allow, &allow)
+            .with_header(HttpHeaderName::AccessControlAllowMethods, allow);
+        if let Some(headers) = request.header("access-control-request-headers") {
+            response.set_header(HttpHeaderName::AccessControlAllowHeaders, headers);
+        }
+        Some(response)
+    }
+
+    fn respond(
+        &self,
+        request: Option<&Request>,
+        response: Response,
+        mut stream: &TcpStream,
+    ) -> std::io::Result<()> {
+        self.stats.record_response(response.status_code());
+        if let Some(access_log) = &self.access_log {
+            access_log.log(
+                stream.peer_addr().ok().map(|addr| addr.ip()),
+                request,
+                usize::from(response.status_code()) as u16,
+                response.body_length(),
+            );
+        }
+        // a streamed body may panic while it is read, which only ends the
+        // connection
+        panic::catch_unwind(AssertUnwindSafe(|| write_response(response, &mut stream)))
+            .unwrap_or_else(|_| Err(std::io::Error::other("the response body panicked")))
+    }
+
+    /// Handles a connection like [Server::handle_request], but also survives
+    /// panics, so the calling loop or worker keeps running.
+    fn handle_isolated(&self, stream: TcpStream) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| self.handle_request(stream)));
+    }
+
+    fn not_found(&self, request: &Request) -> Response {
+        match &self.not_found_handler {
+            Some(handler) => handler(request),
+            None => Response::with_status(HttpStatusCode::NotFound).with_html(NOT_FOUND_PAGE),
+        }
+    }
+
+    /// Finds the route for `method` and the path of the request, along with
+    /// the path it matched.
+    fn match_route(&self, method: &HttpMethod, request: &mut Request) -> Option<(&Route, String)> {
+        // a format extension is stripped first, so that `/users/1.json`
+        // doesn't end up with the param `id = "1.json"`
+        let stripped =
+            request::utils::strip_format_extension(request.path_as_str(), &self.format_extensions);
+        if let Some((path, format)) = stripped {
+            if let Some(route) = self.find_route(method, request, &path) {
+                request::utils::set_request_format(request, format);
+                return Some((route, path));
+            }
+        }
+        self.find_route(method, request, request.path_as_str())
+            .map(|route| (route, request.path_as_str().to_string()))
+    }
+
+    /// Returns the value of the `allow` header for the path of the request,
+    /// listing the methods of all routes matching it, or None if no route
+    /// matches the path.
+    fn allowed_methods(&self, request: &Request) -> Option<String> {
+        let stripped =
+            request::utils::strip_format_extension(request.path_as_str(), &self.format_extensions)
+                .map(|(path, _)| path);
+        let paths = [Some(request.path_as_str()), stripped.as_deref()];
+        let mut methods: Vec<HttpMethod> = Vec::new();
+        for route in &self.routes {
+            let path_matches = paths.iter().flatten().any(|path| {
+                route.matches(
+                    &route.method,
+                    request.header("host"),
+                    path,
+                    self.path_matching,
+                )
+            });
+            if path_matches && !methods.contains(&route.method) {
+                methods.push(route.method.clone());
+            }
+        }
+        if methods.is_empty() {
+            return None;
+        }
+        if !self.manual_head
+            && methods.contains(&HttpMethod::GET)
+            && !methods.contains(&HttpMethod::HEAD)
+        {
+            methods.push(HttpMethod::HEAD);
+        }
+        if !self.manual_options && !methods.contains(&HttpMethod::OPTIONS) {
+            methods.push(HttpMethod::OPTIONS);
+        }
+        Some(
+            methods
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+
+    /// Runs the handler of `route`, or answers from the response cache if the
+    /// route is cached.
+    fn run_handler(&self, route: &Route, request: &Request) -> Response {
+        let cached = route.cached && request.method() == HttpMethod::GET;
+        let (path, query) = (request.path_as_str(), request.raw_query());
+        if let Some(response) = cached.then(|| self.cache.get(path, query)).flatten() {
+            return response;
+        }
+        let guard = InFlightGuard::new(&self.in_flight);
+        let response = match panic::catch_unwind(AssertUnwindSafe(|| (route.handler)(request))) {
+            Ok(response) => self.hide_internal_errors(response),
+            Err(payload) => {
+                let origin = format!("The handler of {} {}", route.method, route.path);
+                self.panic_response(&origin, payload.as_ref())
+            }
+        };
+        drop(guard);
+        if cached {
+            self.cache.insert(path, query, &response);
+        }
+        response
+    }
+
+    /// In production mode, replaces a `500 Internal Server Error` response
+    /// with the generic one.
+    fn hide_internal_errors(&self, response: Response) -> Response {
+        if self.development || response.status_code() != HttpStatusCode::InternalServerError {
+            return response;
+        }
+        internal_server_error(GENERIC_ERROR_PAGE)
+    }
+
+    /// Replaces a response which failed [Response::validate]. Like a panic,
+    /// this shows up as a 500 in the access log.
+    fn invalid_response(&self, err: &ResponseError) -> Response {
+        if !self.development {
+            return internal_server_error(GENERIC_ERROR_PAGE);
+        }
+        internal_server_error(&format!(
+            "<h1>Internal Server Error</h1><p>The response couldn't be sent: {}</p>",
+            err
+        ))
+    }
+
+    /// Answers a caught panic. `origin` names what panicked in the page shown
+    /// in development mode.
+    fn panic_response(&self, origin: &str, payload: &(dyn Any + Send)) -> Response {
+        if !self.development {
+            return internal_server_error(GENERIC_ERROR_PAGE);
+        }
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic payload");
+        internal_server_error(&format!(
+            "<h1>Internal Server Error</h1><p>{} panicked:</p><pre>{}</pre>",
+            origin, message
+        ))
+    }
+
+    /// Finds the most specific route matching the request, see
+    /// [request::utils::route_specificity]. Of equally specific routes, the
+    /// first one registered wins.
+    fn find_route(&self, method: &HttpMethod, request: &Request, path: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.matches(method, request.header("host"), path, self.path_matching))
+            .fold(None, |best: Option<(&Route, Vec<u8>)>, route| {
+                let specificity = request::utils::route_specificity(&route.path);
+                match best {
+                    Some((_, ref best_specificity)) if *best_specificity >= specificity => best,
+                    _ => Some((route, specificity)),
+                }
+            })
+            .map(|(route, _)| route)
+    }
+}
+
+/// The body of `404 Not Found` responses if there is no not-found handler.
+const NOT_FOUND_PAGE: &str = "<h1>Not Found</h1>";
+
+/// The body of `500 Internal Server Error` responses in production mode.
+const GENERIC_ERROR_PAGE: &str = "<h1>Internal Server Error</h1>";
+
+fn internal_server_error(html: &str) -> Response {
+    let mut response = Response::with_status(HttpStatusCode::InternalServerError);
+    response.set_html(html);
+    response
+}
+
+/// The answer to requests in maintenance mode, see [Server::set_maintenance_mode].
+fn maintenance_response(retry_after: Duration) -> Response {
+    Response::with_status(HttpStatusCode::ServiceUnavailable)
+        .with_header(HttpHeaderName::RetryAfter, retry_after.as_secs())
+        .with_html("<h1>Service Unavailable</h1>")
+}
+
+/// Collects the constraints of a route, see [Server::route].
+pub struct RouteBuilder<'a> {
+    server: &'a mut Server,
+    method: HttpMethod,
+    path: String,
+    host: Option<String>,
+    cached: bool,
+}
+
+impl<'a> RouteBuilder<'a> {
+    /// Only match requests with this [HttpMethod]. Defaults to `GET`.
+    pub fn method(mut self, method: HttpMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Only match requests to this path, see [Server::add_route] for the
+    /// syntax. Defaults to `/`.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Only match requests whose `host` header (ignoring the port) equals
+    /// `host`, compared case-insensitively. By default any host matches.
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    /// Caches the `200 OK` responses of the route to GET requests in memory,
+    /// keyed by the path and the query. Cached responses are sent without
+    /// running the handler again until they are invalidated through the
+    /// [ResponseCache] of [Server::response_cache], which drops the responses
+    /// for all queries of a path. Streamed responses aren't cached, and at
+    /// most [MAX_CACHED_RESPONSES] are kept.
+    pub fn cached(mut self) -> Self {
+        self.cached = true;
+        self
+    }
+
+    /// Registers the route with its handler and returns the server.
+    pub fn handler<F>(self, handler: F) -> &'a mut Server
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.server.routes.push(Route {
+            method: self.method,
+            path: self.path,
+            host: self.host,
+            cached: self.cached,
+            handler: Box::new(handler),
+        });
+        self.server
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::prelude::*;
+    use std::net::Shutdown;
+
+    /// Sends a raw request to the server and returns everything it writes back.
+    fn send_request(server: &Server, request: &[u8]) -> Vec<u8> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request).unwrap();
+        // no further requests, so the server closes the connection
+        client.shutdown(Shutdown::Write).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.handle_request(stream).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    /// Splits a raw response into its head and body.
+    fn split_response(response: &[u8]) -> (String, &[u8]) {
+        let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..head_end].to_vec()).unwrap();
+        (head, &response[head_end + 4..])
+    }
+
+    #[test]
+    fn test_add_route() {
+        let mut server = Server::new();
+        assert_eq!(server.routes.len(), 0);
+        server.add_route(HttpMethod::GET, "/", |_| Response::default());
+        assert_eq!(server.routes.len(), 1);
+        let route = server.routes.first().unwrap();
+        assert_eq!(route.method, HttpMethod::GET);
+        assert_eq!(route.path, "/");
+        assert_eq!(route.host, None);
+    }
+
+    #[test]
+    fn test_add_route_multi() {
+        let mut server = Server::new();
+        server
+            .add_route_multi(&[HttpMethod::GET, HttpMethod::POST], "/x", |req| {
+                req.method().to_string().into()
+            })
+            .add_route(HttpMethod::DELETE, "/x", |_| "single".into());
+        assert_eq!(server.routes.len(), 3);
+
+        let response = send_request(&server, b"GET /x HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"GET");
+        let response = send_request(&server, b"POST /x HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"POST");
+        let response = send_request(&server, b"DELETE /x HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"single");
+        let response = send_request(&server, b"PUT /x HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 405 "));
+    }
+
+    #[test]
+    fn test_post_route() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/submit", |_| "get".into())
+            .add_route(HttpMethod::POST, "/submit", |req| {
+                req.form().get("a").unwrap().as_str().into()
+            });
+        let response = send_request(
+            &server,
+            b"POST /submit HTTP/1.1\r\ncontent-length: 5\r\n\r\na=bcd",
+        );
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(body, b"bcd");
+        let response = send_request(&server, b"GET /submit HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"get");
+    }
+
+    #[test]
+    fn test_large_request_body() {
+        let mut server = Server::new();
+        server.add_route(HttpMethod::POST, "/upload", |req| {
+            assert!(!req.raw_content().contains('\0'));
+            req.body().into()
+        });
+        let body: String = (0..20 * 1024)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        let request = format!(
+            "POST /upload HTTP/1.1\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let response = send_request(&server, request.as_bytes());
+        let (head, received) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(received, body.as_bytes());
+    }
+
+    #[test]
+    fn test_panicking_handler() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/panic", |_| panic!("secret detail"))
+            .add_route(HttpMethod::GET, "/error", |_| {
+                let mut response: Response = "db password wrong".into();
+                response.set_status_code(HttpStatusCode::InternalServerError);
+                response
+            });
+
+        let response = send_request(&server, b"GET /panic HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 500 "));
+        assert_eq!(body, GENERIC_ERROR_PAGE.as_bytes());
+        let response = send_request(&server, b"GET /error HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, GENERIC_ERROR_PAGE.as_bytes());
+
+        server.set_production(false);
+        let response = send_request(&server, b"GET /panic HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 500 "));
+        let body = String::from_utf8_lossy(body);
+        assert!(body.contains("secret detail"));
+        assert!(body.contains("GET /panic"));
+        let response = send_request(&server, b"GET /error HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"db password wrong");
+    }
+
+    #[test]
+    fn test_panicking_middleware_and_not_found_handler() {
+        let mut server = Server::new();
+        server
+            .set_keep_alive_timeout(DEFAULT_KEEP_ALIVE_TIMEOUT)
+            .add_middleware(|req, next| {
+                if req.header("x-crash").is_some() {
+                    panic!("middleware detail");
+                }
+                next.run(req)
+            })
+            .add_route(HttpMethod::GET, "/ok", |_| "ok".into())
+            .set_not_found_handler(|_| panic!("not found detail"));
+
+        let response = send_request(&server, b"GET /ok HTTP/1.1\r\nX-Crash: 1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 500 "));
+        assert_eq!(body, GENERIC_ERROR_PAGE.as_bytes());
+
+        // the connection is kept for the next request
+        let response = send_request(
+            &server,
+            b"GET /missing HTTP/1.1\r\n\r\nGET /ok HTTP/1.1\r\n\r\n",
+        );
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 500 "));
+        assert!(response.ends_with("\r\n\r\nok"));
+
+        server.set_production(false);
+        let response = send_request(&server, b"GET /missing HTTP/1.1\r\n\r\n");
+        let body = String::from_utf8_lossy(split_response(&response).1).to_string();
+        assert!(body.contains("Handling GET /missing panicked"));
+        assert!(body.contains("not found detail"));
+    }
+
+    #[test]
+    fn test_thread_pool() {
+        use std::sync::Condvar;
+        use std::time::Duration;
+
+        // "/all" answers "together" once all four workers run it at once
+        let waiting = Arc::new((Mutex::new(0), Condvar::new()));
+        let address = test_support::free_loopback_addr();
+        thread::spawn(move || {
+            Server::new()
+                .add_route(HttpMethod::GET, "/all", move |_| {
+                    let (count, all_arrived) = &*waiting;
+                    let mut count = count.lock().unwrap();
+                    *count += 1;
+                    all_arrived.notify_all();
+                    let (count, _) = all_arrived
+                        .wait_timeout_while(count, Duration::from_secs(10), |count| *count < 4)
+                        .unwrap();
+                    if *count >= 4 { "together" } else { "alone" }.into()
+                })
+                .bind_and_run_with_threads(address, 4)
+        });
+        let connect = || loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => return stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        drop(connect());
+
+        let clients: Vec<_> = (0..4)
+            .map(|_| {
+                let mut client = connect();
+                thread::spawn(move || {
+                    client
+                        .write_all(b"GET /all HTTP/1.1\r\nConnection: close\r\n\r\n")
+                        .unwrap();
+                    let mut response = Vec::new();
+                    client.read_to_end(&mut response).unwrap();
+                    response
+                })
+            })
+            .collect();
+        for client in clients {
+            assert_eq!(split_response(&client.join().unwrap()).1, b"together");
+        }
+    }
+
+    #[test]
+    fn test_thread_pool_survives_panics() {
+        use std::sync::Condvar;
+        use std::time::Duration;
+
+        struct PanickingBody;
+        impl Read for PanickingBody {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                panic!("broken body")
+            }
+        }
+
+        // "/pair" answers "together" once both workers run it at once
+        let waiting = Arc::new((Mutex::new(0), Condvar::new()));
+        let address = test_support::free_loopback_addr();
+        thread::spawn(move || {
+            Server::new()
+                .add_route(HttpMethod::GET, "/boom", |_| {
+                    Response::from_reader("text/plain", PanickingBody)
+                })
+                .add_route(HttpMethod::GET, "/name", |_| {
+                    thread::current().name().unwrap_or_default().into()
+                })
+                .add_route(HttpMethod::GET, "/pair", move |_| {
+                    let (count, both_arrived) = &*waiting;
+                    let mut count = count.lock().unwrap();
+                    *count += 1;
+                    both_arrived.notify_all();
+                    let (count, _) = both_arrived
+                        .wait_timeout_while(count, Duration::from_secs(2), |count| *count < 2)
+                        .unwrap();
+                    if *count >= 2 { "together" } else { "alone" }.into()
+                })
+                .bind_and_run_with_threads(address, 2)
+        });
+        let request = move |path: &str| {
+            let mut client = loop {
+                match TcpStream::connect(address) {
+                    Ok(stream) => break stream,
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            client
+                .write_all(format!("GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", path).as_bytes())
+                .unwrap();
+            let mut response = Vec::new();
+            let _ = client.read_to_end(&mut response);
+            response
+        };
+        for _ in 0..4 {
+            request("/boom");
+        }
+        let name = request("/name");
+        assert!(split_response(&name).1.starts_with(b"rfs-worker-"));
+
+        let pair: Vec<_> = (0..2)
+            .map(|_| thread::spawn(move || request("/pair")))
+            .collect();
+        for response in pair {
+            assert_eq!(split_response(&response.join().unwrap()).1, b"together");
+        }
+    }
+
+    #[test]
+    fn test_purge_cached_route() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = Arc::clone(&calls);
+        let mut server = Server::new();
+        let cache = server.response_cache();
+        server
+            .route()
+            .path("/page")
+            .cached()
+            .handler(move |_| {
+                let call = handler_calls.fetch_add(1, Ordering::SeqCst) + 1;
+                call.to_string().into()
+            })
+            .route()
+            .method(HttpMethod::Other("PURGE".to_string()))
+            .path("/page")
+            .handler(move |req| match cache.invalidate(req.path_as_str()) {
+                true => Response::empty(),
+                false => Response::with_status(HttpStatusCode::NotFound),
+            });
+
+        let get = b"GET /page HTTP/1.1\r\n\r\n";
+        assert_eq!(split_response(&send_request(&server, get)).1, b"1");
+        assert_eq!(split_response(&send_request(&server, get)).1, b"1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let purge = b"PURGE /page HTTP/1.1\r\n\r\n";
+        assert!(split_response(&send_request(&server, purge))
+            .0
+            .starts_with("HTTP/1.1 200 "));
+        assert!(split_response(&send_request(&server, purge))
+            .0
+            .starts_with("HTTP/1.1 404 "));
+        assert_eq!(split_response(&send_request(&server, get)).1, b"2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // purging the path also drops the responses for its queries
+        let get_query = b"GET /page?x=1 HTTP/1.1\r\n\r\n";
+        assert_eq!(split_response(&send_request(&server, get_query)).1, b"3");
+        assert_eq!(split_response(&send_request(&server, get_query)).1, b"3");
+        send_request(&server, purge);
+        assert_eq!(split_response(&send_request(&server, get_query)).1, b"4");
+    }
+
+    #[test]
+    fn test_dedicated_head_route() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/x", |_| "from get".into())
+            .add_route(HttpMethod::HEAD, "/x", |_| {
+                Response::from("from head").with_header(HttpHeaderName::CacheControl, "no-store")
+            });
+
+        let response = send_request(&server, b"HEAD /x HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.lines().any(|line| line == "cache-control: no-store"));
+        assert!(head.lines().any(|line| line == "content-length: 9"));
+        assert_eq!(body, b"");
+        let response = send_request(&server, b"GET /x HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"from get");
+    }
+
+    #[test]
+    fn test_path_matching_options() {
+        let status = |server: &Server, path: &str| {
+            let response =
+                send_request(server, format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes());
+            String::from_utf8_lossy(&response)
+                .split("\r\n")
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/about", |_| "about".into());
+        assert_eq!(status(&server, "/about/"), "HTTP/1.1 200 OK");
+        assert_eq!(status(&server, "/About"), "HTTP/1.1 404 Not Found");
+
+        server.enable_case_insensitive_paths();
+        assert_eq!(status(&server, "/About"), "HTTP/1.1 200 OK");
+        assert_eq!(status(&server, "/ABOUT/"), "HTTP/1.1 200 OK");
+
+        server.set_strict_trailing_slash(true);
+        assert_eq!(status(&server, "/About"), "HTTP/1.1 200 OK");
+        assert_eq!(status(&server, "/about/"), "HTTP/1.1 404 Not Found");
+    }
+
+    #[test]
+    fn test_upgrade_required_keeps_connection_tokens() {
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/ws", |_| {
+            Response::upgrade_required(&["websocket"])
+        });
+
+        let response = send_request(&server, b"GET /ws HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(head.lines().any(|line| line == "upgrade: websocket"));
+        assert!(head
+            .lines()
+            .any(|line| line == "connection: upgrade, close"));
+    }
+
+    #[test]
+    fn test_auto_head_and_options() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/x", |_| "test".into())
+            .add_route(HttpMethod::DELETE, "/x", |_| Response::empty());
+
+        let response = send_request(&server, b"HEAD /x HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\ncontent-length: 4"));
+        assert_eq!(body, b"");
+
+        let response = send_request(&server, b"OPTIONS /x HTTP/1.1\r\n\r\n");
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 204 "));
+        assert!(head.contains("\r\nallow: GET, DELETE, HEAD, OPTIONS"));
+
+        let response = send_request(&server, b"POST /x HTTP/1.1\r\n\r\n");
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 405 "));
+        assert!(head.contains("\r\nallow: GET, DELETE, HEAD, OPTIONS"));
+
+        server.set_auto_head(false);
+        let response = send_request(&server, b"HEAD /x HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 405 "));
+        assert!(head.contains("\r\nallow: GET, DELETE, OPTIONS"));
+        assert_eq!(body, b"");
+
+        server.set_auto_options(false);
+        let response = send_request(&server, b"OPTIONS /x HTTP/1.1\r\n\r\n");
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 405 "));
+        assert!(head.contains("\r\nallow: GET, DELETE\r\n"));
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/x", |_| "test".into())
+            .set_auto_head(false)
+            .set_auto_options(false);
+
+        let response = send_request(&server, b"POST /x HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(head.lines().any(|line| line == "allow: GET"));
+        assert!(body.is_empty());
+
+        // no route for the path at all
+        let response = send_request(&server, b"POST /y HTTP/1.1\r\n\r\n");
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(!head.contains("allow:"));
+    }
+
+    #[test]
+    fn test_connection_alive() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let noticed_disconnect = Arc::new(AtomicBool::new(false));
+        let noticed = Arc::clone(&noticed_disconnect);
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/alive", |req| {
+                req.is_connection_alive().to_string().into()
+            })
+            .add_route(HttpMethod::GET, "/long", move |req| {
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_secs(5) {
+                    if !req.is_connection_alive() {
+                        noticed.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Response::empty()
+            });
+
+        // send_request closes the sending side, which looks like a disconnect
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET /alive HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.handle_request(stream).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(split_response(&response).1, b"true");
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET /long HTTP/1.1\r\n\r\n").unwrap();
+        drop(client);
+        let (stream, _) = listener.accept().unwrap();
+        // writing the response to the closed connection may fail
+        let _ = server.handle_request(stream);
+        assert!(noticed_disconnect.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_not_found() {
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/", |_| "index".into());
+        let response = send_request(&server, b"GET /missing HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 404 "));
+        assert!(head.contains("\r\ncontent-type: text/html"));
+        assert_eq!(body, NOT_FOUND_PAGE.as_bytes());
+
+        server.set_not_found_handler(|req| {
+            Response::with_status(HttpStatusCode::NotFound)
+                .with_body(format!("no {}", req.path_as_str()))
+        });
+        let response = send_request(&server, b"GET /missing HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 404 "));
+        assert_eq!(body, b"no /missing");
+        let response = send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"index");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/", |_| "index".into());
+        send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        send_request(&server, b"GET /missing HTTP/1.1\r\n\r\n");
+        send_request(&server, b"FOO BAR\r\n\r\n");
+        let stats = server.stats();
+        assert_eq!(stats.connections, 4);
+        assert_eq!(stats.requests, 4);
+        assert_eq!(stats.responses_in_class(2), 2);
+        assert_eq!(stats.responses_in_class(4), 2);
+        assert_eq!(stats.responses_in_class(5), 0);
+    }
+
+    #[test]
+    fn test_route_builder() {
+        let mut server = Server::new();
+        server
+            .route()
+            .host("api.local")
+            .method(HttpMethod::PUT)
+            .path("/x")
+            .handler(|_| "api".into());
+        let route = server.routes.first().unwrap();
+        assert_eq!(route.method, HttpMethod::PUT);
+        assert_eq!(route.path, "/x");
+        assert_eq!(route.host.as_deref(), Some("api.local"));
+
+        let response = send_request(&server, b"PUT /x HTTP/1.1\r\nHost: api.local:8080\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"api");
+        let response = send_request(&server, b"PUT /x HTTP/1.1\r\nHost: API.LOCAL\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"api");
+        let response = send_request(&server, b"PUT /x HTTP/1.1\r\nHost: other.local\r\n\r\n");
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 404 "));
+        let response = send_request(&server, b"GET /x HTTP/1.1\r\nHost: api.local\r\n\r\n");
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 405 "));
+        let response = send_request(&server, b"PUT /y HTTP/1.1\r\nHost: api.local\r\n\r\n");
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 404 "));
+        let response = send_request(&server, b"PUT /x HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 404 "));
+    }
+
+    #[test]
+    fn test_format_extensions() {
+        let mut server = Server::new();
+        server
+            .set_format_extensions(&["json"])
+            .add_route(HttpMethod::GET, "/users/{id}", |req| {
+                format!("{}:{:?}", req.params().get("id").unwrap(), req.format()).into()
+            });
+        let response = send_request(&server, b"GET /users/1.json HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"1:Some(\"json\")");
+        let response = send_request(&server, b"GET /users/1 HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"1:None");
+        let response = send_request(&server, b"GET /users/1.xml HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"1.xml:None");
+    }
+
+    #[test]
+    fn test_serve_dir_as_zip() {
+        let root = std::env::temp_dir().join(format!("rfs-serve-zip-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs/nested")).unwrap();
+        std::fs::create_dir_all(root.join("my docs")).unwrap();
+        std::fs::write(root.join("docs/readme.txt"), "read me").unwrap();
+        std::fs::write(root.join("docs/nested/data.bin"), [1u8, 2, 3]).unwrap();
+        std::fs::write(root.join("secret.txt"), "not in the archive").unwrap();
+
+        let mut server = Server::new();
+        server.serve_dir_as_zip("/files", &root);
+        let response = send_request(&server, b"GET /files/docs?download=zip HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("content-type: application/zip"));
+        assert!(head.contains("content-disposition: attachment; filename=\"docs.zip\""));
+        assert!(head.contains("transfer-encoding: chunked"));
+        let archive = response::tests::decode_chunked(body);
+        assert_eq!(
+            zip::read_entries(&archive),
+            vec![
+                ("nested/data.bin".to_string(), vec![1, 2, 3]),
+                ("readme.txt".to_string(), b"read me".to_vec()),
+            ]
+        );
+
+        let response = send_request(
+            &server,
+            b"GET /files/my%20docs?download=zip HTTP/1.1\r\n\r\n",
+        );
+        let (head, _) = split_response(&response);
+        assert!(head.contains("filename=\"my docs.zip\""));
+
+        let response = send_request(&server, b"GET /files/docs HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 404 Not Found"));
+        let response = send_request(&server, b"GET /files/../../?download=zip HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 404 Not Found"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_conditional_requests() {
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/", move |_| {
+            Response::from("content").with_validators("v1", Some(modified))
+        });
+
+        let response = send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("etag: \"v1\""));
+        assert_eq!(body, b"content");
+
+        let response = send_request(&server, b"GET / HTTP/1.1\r\nIf-None-Match: \"v1\"\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 304 Not Modified"));
+        assert!(head.contains("etag: \"v1\""));
+        assert!(body.is_empty());
+
+        let response = send_request(&server, b"GET / HTTP/1.1\r\nIf-None-Match: \"v0\"\r\n\r\n");
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 200 OK"));
+
+        let request = b"GET / HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n";
+        let response = send_request(&server, request);
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 304 Not Modified"));
+
+        let request = b"GET / HTTP/1.1\r\nIf-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT\r\n\r\n";
+        let response = send_request(&server, request);
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 200 OK"));
+
+        // HEAD requests are evaluated like GET requests
+        for request in [
+            &b"HEAD / HTTP/1.1\r\nIf-None-Match: \"v1\"\r\n\r\n"[..],
+            b"HEAD / HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n",
+        ] {
+            let response = send_request(&server, request);
+            let (head, body) = split_response(&response);
+            assert!(head.starts_with("HTTP/1.1 304 Not Modified"));
+            assert!(body.is_empty());
+        }
+        let response = send_request(&server, b"HEAD / HTTP/1.1\r\nIf-None-Match: \"v2\"\r\n\r\n");
+        assert!(split_response(&response).0.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    fn test_chunked_request_body() {
+        let mut server = Server::new();
+        server
+            .set_max_body_size(16)
+            .add_route(HttpMethod::PUT, "/", |req| {
+                req.form().get("a").unwrap().as_str().into()
+            });
+        let request = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\na=h\r\n4\r\nello\r\n0\r\n\r\n";
+        let response = send_request(&server, request);
+        assert_eq!(split_response(&response).1, b"hello");
+
+        let mut request = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        request.extend(std::iter::repeat_n(
+            b'0',
+            reader::MAX_CHUNK_LINE_LENGTH + 10,
+        ));
+        let response = send_request(&server, &request);
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 400 Bad Request"));
+
+        let request = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n11\r\n";
+        let response = send_request(&server, request);
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 413 Content Too Large"));
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        let mut server = Server::new();
+        server
+            .set_max_body_size(16)
+            .add_route(HttpMethod::PUT, "/", |req| req.body().into());
+        let response = send_request(
+            &server,
+            b"PUT / HTTP/1.1\r\nContent-Length: 16\r\n\r\n0123456789abcdef",
+        );
+        assert_eq!(split_response(&response).1, b"0123456789abcdef");
+
+        // the body isn't sent at all, the declared length is enough
+        let response = send_request(
+            &server,
+            b"PUT / HTTP/1.1\r\nContent-Length: 1000000000\r\n\r\n",
+        );
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+        assert!(head.lines().any(|line| line == "connection: close"));
+    }
+
+    #[test]
+    fn test_ambiguous_framing_closes_connection() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::POST, "/", |_| "posted".into())
+            .add_route(HttpMethod::GET, "/admin", |_| "smuggled".into());
+        let response = send_request(
+            &server,
+            b"POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /admin HTTP/1.1\r\n\r\n",
+        );
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(head.lines().any(|line| line == "connection: close"));
+        assert!(!String::from_utf8_lossy(&response).contains("smuggled"));
+    }
+
+    #[test]
+    fn test_serve_dir() {
+        let base = std::env::temp_dir().join(format!("rfs-serve-{}", std::process::id()));
+        let root = base.join("www");
+        std::fs::create_dir_all(root.join("img")).unwrap();
+        std::fs::write(root.join("img/logo.svg"), "<svg/>").unwrap();
+        std::fs::write(root.join("data.json"), "{}").unwrap();
+        std::fs::write(root.join("my photo.jpg"), "jpeg").unwrap();
+        std::fs::write(root.join("über.txt"), "umlaut").unwrap();
+        std::fs::write(base.join("secret.txt"), "secret").unwrap();
+
+        let mut server = Server::new();
+        server
+            .serve_dir("/static", &root)
+            .set_not_found_handler(|_| {
+                Response::with_status(HttpStatusCode::NotFound).with_body("custom 404")
+            });
+        let response = send_request(&server, b"GET /static/img/logo.svg HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("\r\ncontent-type: image/svg+xml\r\n"));
+        assert_eq!(body, b"<svg/>");
+        let response = send_request(&server, b"GET /static/data.json?v=2 HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.contains("\r\ncontent-type: application/json\r\n"));
+        assert_eq!(body, b"{}");
+        let response = send_request(&server, b"GET /static/my%20photo.jpg HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(body, b"jpeg");
+        let response = send_request(&server, b"GET /static/%C3%BCber.txt HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"umlaut");
+
+        for path in [
+            "/static/../secret.txt",
+            "/static/img/../../secret.txt",
+            "/static/%2e%2e/secret.txt",
+            "/static/img%2f..%2f..%2fsecret.txt",
+            "/static/..%5csecret.txt",
+            "/static/data.json%00",
+            "/static/missing.txt",
+        ] {
+            let request = format!("GET {} HTTP/1.1\r\n\r\n", path);
+            let response = send_request(&server, request.as_bytes());
+            let (head, body) = split_response(&response);
+            assert!(head.starts_with("HTTP/1.1 404 "), "{}", path);
+            assert_eq!(body, b"custom 404");
+        }
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_serve_dir_precompressed() {
+        let root = std::env::temp_dir().join(format!("rfs-precompressed-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("app.js"), "plain").unwrap();
+        std::fs::write(root.join("app.js.gz"), "gzip bytes").unwrap();
+        std::fs::write(root.join("app.js.br"), "brotli bytes").unwrap();
+        std::fs::write(root.join("other.js"), "other").unwrap();
+
+        let mut server = Server::new();
+        server.serve_dir("/", &root);
+        let get = |path: &str, accept: &str| {
+            let request = format!(
+                "GET {} HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n",
+                path, accept
+            );
+            let response = send_request(&server, request.as_bytes());
+            let (head, body) = split_response(&response);
+            (head, body.to_vec())
+        };
+
+        let (head, body) = get("/app.js", "gzip, br");
+        assert_eq!(body, b"brotli bytes");
+        assert!(head.contains("\r\ncontent-encoding: br\r\n"));
+        assert!(head.contains("\r\ncontent-type: text/javascript\r\n"));
+        assert!(head.contains("\r\nvary: accept-encoding\r\n"));
+
+        let (head, body) = get("/app.js", "gzip, br;q=0.5");
+        assert_eq!(body, b"gzip bytes");
+        assert!(head.contains("\r\ncontent-encoding: gzip\r\n"));
+
+        let (head, body) = get("/app.js", "identity");
+        assert_eq!(body, b"plain");
+        assert!(!head.contains("content-encoding"));
+        assert!(head.contains("\r\nvary: accept-encoding\r\n"));
+
+        let (head, body) = get("/other.js", "gzip, br");
+        assert_eq!(body, b"other");
+        assert!(!head.contains("content-encoding"));
+        assert!(!head.contains("vary"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_serve_dir_revalidation() {
+        let root = std::env::temp_dir().join(format!("rfs-serve-dir-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("css")).unwrap();
+        std::fs::write(root.join("css/site.css"), "body {}").unwrap();
+        std::fs::write(root.join("index.html"), "<h1>index</h1>").unwrap();
+
+        let mut server = Server::new();
+        server.serve_dir("/static/", &root);
+        let response = send_request(&server, b"GET /static/css/site.css HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("content-type: text/css"));
+        assert!(head.contains("cache-control: no-cache"));
+        assert!(head.contains("content-length: 7"));
+        assert_eq!(body, b"body {}");
+        let etag = head
+            .lines()
+            .find_map(|line| line.strip_prefix("etag: "))
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with("W/\""));
+        let last_modified = head
+            .lines()
+            .find_map(|line| line.strip_prefix("last-modified: "))
+            .unwrap()
+            .to_string();
+
+        let request = format!(
+            "GET /static/css/site.css HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n",
+            etag
+        );
+        let response = send_request(&server, request.as_bytes());
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 304 Not Modified"));
+        assert!(body.is_empty());
+
+        let request = format!(
+            "GET /static/css/site.css HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n",
+            last_modified
+        );
+        let response = send_request(&server, request.as_bytes());
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 304 Not Modified"));
+
+        let response = send_request(&server, b"GET /static HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"<h1>index</h1>");
+        let response = send_request(&server, b"GET /static/missing.css HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 404 Not Found"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_pipe_from_file() {
+        let root = std::env::temp_dir().join(format!("rfs-pipe-file-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("song.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/song", move |req| {
+            Response::pipe_from_file(&path, req).unwrap()
+        });
+        let response = send_request(&server, b"GET /song HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("content-type: text/plain"));
+        assert!(head.contains("accept-ranges: bytes"));
+        assert!(head.contains("content-length: 10"));
+        assert!(head.contains("last-modified: "));
+        assert!(!head.contains("cache-control"));
+        assert_eq!(body, b"0123456789");
+        let etag = head
+            .lines()
+            .find_map(|line| line.strip_prefix("etag: "))
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with("W/\""));
+
+        let response = send_request(&server, b"GET /song HTTP/1.1\r\nRange: bytes=2-5\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content"));
+        assert!(head.contains("content-range: bytes 2-5/10"));
+        assert!(head.contains("content-length: 4"));
+        assert_eq!(body, b"2345");
+
+        let response = send_request(&server, b"GET /song HTTP/1.1\r\nRange: bytes=7-\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"789");
+        let response = send_request(&server, b"GET /song HTTP/1.1\r\nRange: bytes=-3\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.contains("content-range: bytes 7-9/10"));
+        assert_eq!(body, b"789");
+
+        let response = send_request(&server, b"GET /song HTTP/1.1\r\nRange: bytes=10-\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 416 Range Not Satisfiable"));
+        assert!(head.contains("content-range: bytes */10"));
+        assert!(body.is_empty());
+
+        // several ranges and a weak if-range get the whole file
+        let response = send_request(
+            &server,
+            b"GET /song HTTP/1.1\r\nRange: bytes=0-1,4-5\r\n\r\n",
+        );
+        assert_eq!(split_response(&response).1, b"0123456789");
+        let request = format!(
+            "GET /song HTTP/1.1\r\nRange: bytes=0-1\r\nIf-Range: {}\r\n\r\n",
+            etag
+        );
+        let response = send_request(&server, request.as_bytes());
+        assert_eq!(split_response(&response).1, b"0123456789");
+
+        // a fresh copy wins over a range
+        let request = format!(
+            "GET /song HTTP/1.1\r\nRange: bytes=0-1\r\nIf-None-Match: {}\r\n\r\n",
+            etag
+        );
+        let response = send_request(&server, request.as_bytes());
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 304 Not Modified"));
+        assert!(body.is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_access_log() {
+        #[derive(Clone, Default)]
+        struct SharedBuffer(std::sync::Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = SharedBuffer::default();
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/hello", |_| {
+                Response::new().with_body("hello")
+            })
+            .enable_access_log_to(LogFormat::Common, buffer.clone());
+        send_request(&server, b"GET /hello?x=1 HTTP/1.1\r\n\r\n");
+        send_request(&server, b"GET /missing HTTP/1.1\r\n\r\n");
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        // 127.0.0.1 - - [dd/Mon/yyyy:hh:mm:ss +0000] "GET /hello?x=1 HTTP/1.1" 200 5
+        let (prefix, rest) = lines[0].split_once(" [").unwrap();
+        assert_eq!(prefix, "127.0.0.1 - -");
+        let (date, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(date.len(), "06/Nov/1994:08:49:37 +0000".len());
+        assert!(date.ends_with(" +0000"));
+        assert_eq!(date.matches('/').count(), 2);
+        assert_eq!(rest, "\"GET /hello?x=1 HTTP/1.1\" 200 5");
+        assert!(lines[1].ends_with("\"GET /missing HTTP/1.1\" 404 18"));
+    }
+
+    #[test]
+    fn test_cors() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/notes/{id}", |_| "note".into())
+            .add_route(HttpMethod::PUT, "/notes/{id}", |_| Response::empty())
+            .add_route(HttpMethod::GET, "/own", |_| {
+                Response::new().with_header(HttpHeaderName::AccessControlAllowOrigin, "*")
+            })
+            .enable_cors("https://example.com");
+
+        let response = send_request(
+            &server,
+            b"OPTIONS /notes/1 HTTP/1.1\r\nOrigin: https://example.com\r\nAccess-Control-Request-Method: PUT\r\nAccess-Control-Request-Headers: content-type\r\n\r\n",
+        );
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 204 "));
+        assert!(head
+            .lines()
+            .any(|line| line == "access-control-allow-origin: https://example.com"));
+        assert!(head
+            .lines()
+            .any(|line| line == "access-control-allow-methods: GET, PUT, HEAD, OPTIONS"));
+        assert!(head
+            .lines()
+            .any(|line| line == "access-control-allow-headers: content-type"));
+        assert!(body.is_empty());
+
+        let response = send_request(
+            &server,
+            b"GET /notes/1 HTTP/1.1\r\nOrigin: https://example.com\r\n\r\n",
+        );
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head
+            .lines()
+            .any(|line| line == "access-control-allow-origin: https://example.com"));
+        assert!(!head.contains("access-control-allow-methods"));
+        assert_eq!(body, b"note");
+
+        let response = send_request(&server, b"GET /own HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response)
+            .0
+            .lines()
+            .any(|line| line == "access-control-allow-origin: *"));
+
+        // a preflight for a path without routes isn't answered
+        let response = send_request(
+            &server,
+            b"OPTIONS /missing HTTP/1.1\r\nAccess-Control-Request-Method: GET\r\n\r\n",
+        );
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[test]
+    fn test_url_for() {
+        let mut server = Server::new();
+        server
+            .add_named_route("user", HttpMethod::GET, "/user/{id}", |req| {
+                req.params()["id"].as_str().into()
+            })
+            .add_named_route("post", HttpMethod::GET, "/user/{id}/posts/{slug}/", |_| {
+                Response::empty()
+            });
+
+        let params = HashMap::from([("id", "42")]);
+        assert_eq!(
+            server.url_for("user", &params),
+            Some("/user/42".to_string())
+        );
+        let url = server.url_for("user", &params).unwrap();
+        let response = send_request(&server, format!("GET {} HTTP/1.1\r\n\r\n", url).as_bytes());
+        assert_eq!(split_response(&response).1, b"42");
+
+        let params = HashMap::from([("id", "7"), ("slug", "hello world/2")]);
+        assert_eq!(
+            server.url_for("post", &params),
+            Some("/user/7/posts/hello%20world%2F2/".to_string())
+        );
+        assert_eq!(server.url_for("post", &HashMap::from([("id", "7")])), None);
+        assert_eq!(server.url_for("missing", &params), None);
+
+        server.add_named_route("asset", HttpMethod::GET, "/assets/{*rest}", |req| {
+            req.params()["rest"].as_str().into()
+        });
+        let params = HashMap::from([("rest", "css/my site.css")]);
+        let url = server.url_for("asset", &params).unwrap();
+        assert_eq!(url, "/assets/css/my%20site.css");
+        let response = send_request(&server, format!("GET {} HTTP/1.1\r\n\r\n", url).as_bytes());
+        assert_eq!(split_response(&response).1, b"css/my site.css");
+        assert_eq!(server.url_for("asset", &HashMap::new()), None);
+    }
+
+    #[test]
+    fn test_in_flight() {
+        use std::sync::atomic::AtomicBool;
+        static RELEASE: AtomicBool = AtomicBool::new(false);
+
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/slow", |_| {
+                while !RELEASE.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Response::empty()
+            })
+            .add_route(HttpMethod::GET, "/panic", |_| panic!("oops"));
+        assert_eq!(server.in_flight(), 0);
+        thread::scope(|scope| {
+            let request = scope.spawn(|| send_request(&server, b"GET /slow HTTP/1.1\r\n\r\n"));
+            while server.in_flight() == 0 {
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(server.in_flight(), 1);
+            RELEASE.store(true, Ordering::SeqCst);
+            request.join().unwrap();
+        });
+        assert_eq!(server.in_flight(), 0);
+
+        send_request(&server, b"GET /panic HTTP/1.1\r\n\r\n");
+        assert_eq!(server.in_flight(), 0);
+    }
+
+    /// Reads a single response with a `content-length` from a connection
+    /// which stays open.
+    fn read_one_response(client: &mut TcpStream) -> Vec<u8> {
+        let mut response = Vec::new();
+        let mut byte = [0];
+        while !response.ends_with(b"\r\n\r\n") {
+            client.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let length: usize = String::from_utf8_lossy(&response)
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0; length];
+        client.read_exact(&mut body).unwrap();
+        response.extend(body);
+        response
+    }
+
+    #[test]
+    fn test_base_path() {
+        let mut server = Server::new();
+        server
+            .add_named_route("home", HttpMethod::GET, "/home", |req| {
+                req.path_as_str().into()
+            })
+            .add_route(HttpMethod::GET, "/", |_| "index".into())
+            .set_base_path("/app/");
+
+        let response = send_request(&server, b"GET /app/home?x=1 HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"/home");
+        let response = send_request(&server, b"GET /app HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"index");
+        for outside in ["/home", "/application/home"] {
+            let request = format!("GET {} HTTP/1.1\r\n\r\n", outside);
+            let response = send_request(&server, request.as_bytes());
+            assert!(split_response(&response)
+                .0
+                .starts_with("HTTP/1.1 404 Not Found"));
+        }
+        assert_eq!(
+            server.url_for("home", &HashMap::new()),
+            Some("/app/home".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let page = "<p>lorem ipsum dolor sit amet</p>\n".repeat(200);
+        let mut server = Server::new();
+        {
+            let page = page.clone();
+            server.add_route(HttpMethod::GET, "/page", move |_| {
+                Response::from(page.as_str()).with_validators("v1", None)
+            });
+        }
+        server
+            .add_route(HttpMethod::GET, "/short", |_| "<p>short</p>".into())
+            .add_route(HttpMethod::GET, "/image", |_| {
+                let mut response = Response::default();
+                response.set_header(HttpHeaderName::ContentType, "image/png");
+                response.set_body_bytes(&[0; 4096]);
+                response
+            })
+            .enable_compression();
+
+        let response = send_request(
+            &server,
+            b"GET /page HTTP/1.1\r\nAccept-Encoding: deflate, gzip\r\n\r\n",
+        );
+        let (head, body) = split_response(&response);
+        assert!(head.lines().any(|line| line == "content-encoding: gzip"));
+        assert!(head.lines().any(|line| line == "vary: accept-encoding"));
+        let length = format!("content-length: {}", body.len());
+        assert!(head.lines().any(|line| line == length));
+        assert!(body.len() < page.len() / 4);
+        assert_eq!(gzip::tests::gunzip(body), page.as_bytes());
+        // both representations can't share a strong validator
+        assert!(head.lines().any(|line| line == "etag: W/\"v1\""));
+        let response = send_request(
+            &server,
+            b"GET /page HTTP/1.1\r\nAccept-Encoding: gzip\r\nIf-None-Match: W/\"v1\"\r\n\r\n",
+        );
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 304 Not Modified"));
+
+        let response = send_request(&server, b"GET /page HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(!head.contains("content-encoding"));
+        assert!(head.lines().any(|line| line == "etag: \"v1\""));
+        assert_eq!(body, page.as_bytes());
+        let response = send_request(
+            &server,
+            b"GET /page HTTP/1.1\r\nAccept-Encoding: gzip;q=0.5, identity;q=0.8\r\n\r\n",
+        );
+        let (head, body) = split_response(&response);
+        assert!(!head.contains("content-encoding"));
+        assert!(head.lines().any(|line| line == "vary: accept-encoding"));
+        assert_eq!(body, page.as_bytes());
+        for path in ["/short", "/image"] {
+            let request = format!("GET {} HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n", path);
+            let response = send_request(&server, request.as_bytes());
+            assert!(!split_response(&response).0.contains("content-encoding"));
+        }
+    }
+
+    #[test]
+    fn test_compression_min_size() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/small", |_| {
+                "<p>hi</p>".repeat(150).into()
+            })
+            .add_route(HttpMethod::GET, "/large", |_| {
+                "<p>hi</p>".repeat(500).into()
+            })
+            .enable_compression_with_min_size(2048);
+
+        let request = b"GET /small HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n";
+        let response = send_request(&server, request);
+        let (head, body) = split_response(&response);
+        assert!(!head.contains("content-encoding"));
+        // longer than the default minimum, but below the configured one
+        assert_eq!(body.len(), 1350);
+        let request = b"GET /large HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n";
+        let response = send_request(&server, request);
+        let (head, body) = split_response(&response);
+        assert!(head.lines().any(|line| line == "content-encoding: gzip"));
+        assert_eq!(
+            gzip::tests::gunzip(body),
+            "<p>hi</p>".repeat(500).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_sse_from_channel() {
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/events", |_| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for message in ["first", "second"] {
+                    thread::sleep(Duration::from_millis(20));
+                    sender.send(message.to_string()).unwrap();
+                }
+            });
+            Response::sse_from_channel(receiver)
+        });
+
+        let response = send_request(&server, b"GET /events HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head
+            .lines()
+            .any(|line| line == "content-type: text/event-stream"));
+        assert!(head
+            .lines()
+            .any(|line| line == "transfer-encoding: chunked"));
+        let events = response::tests::decode_chunked(body);
+        assert_eq!(events, b"data: first\n\ndata: second\n\n");
+    }
+
+    #[test]
+    fn test_on_connection() {
+        let peers = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&peers);
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/", |_| "hello".into())
+            .on_connection(move |stream| {
+                recorded.lock().unwrap().push(stream.peer_addr().unwrap());
+            });
+        for _ in 0..2 {
+            let response = send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+            assert_eq!(split_response(&response).1, b"hello");
+        }
+        let peers = peers.lock().unwrap();
+        assert_eq!(peers.len(), 2);
+        assert!(peers.iter().all(|peer| peer.ip().is_loopback()));
+        assert_ne!(peers[0], peers[1]);
+
+        server.on_connection(|_| panic!("broken hook"));
+        let response = send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"hello");
+    }
+
+    #[test]
+    fn test_invalid_response_becomes_500() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/redirect", |req| {
+                // the decoded query is copied into the header unchecked
+                let next = req.queries()["next"].clone().unwrap_or_default();
+                Response::redirect(&next, false)
+            })
+            .add_route(HttpMethod::GET, "/ok", |_| "ok".into())
+            .set_keep_alive_timeout(DEFAULT_KEEP_ALIVE_TIMEOUT);
+
+        let response = send_request(
+            &server,
+            b"GET /redirect?next=/a%0D%0Aset-cookie:%20x=1 HTTP/1.1\r\n\r\nGET /ok HTTP/1.1\r\n\r\n",
+        );
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(!response.contains("set-cookie"));
+        assert!(!response.contains("location"));
+        assert!(response.contains(GENERIC_ERROR_PAGE));
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[test]
+    fn test_on_request() {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&logs);
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::POST, "/items", |_| "created".into())
+            .set_keep_alive_timeout(DEFAULT_KEEP_ALIVE_TIMEOUT)
+            .on_request(move |log| recorded.lock().unwrap().push(log.clone()));
+        send_request(
+            &server,
+            b"POST /items?x=1 HTTP/1.1\r\ncontent-length: 0\r\n\r\nGET /missing HTTP/1.1\r\n\r\n",
+        );
+        send_request(&server, b"GET\r\n\r\n");
+
+        let logs = logs.lock().unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].method, HttpMethod::POST);
+        assert_eq!(logs[0].path, "/items");
+        assert_eq!(logs[0].status, HttpStatusCode::OK);
+        assert_eq!(logs[1].method, HttpMethod::GET);
+        assert_eq!(logs[1].status, HttpStatusCode::NotFound);
+        assert!(logs[1].duration < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_maintenance_mode() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/", |_| "home".into())
+            .add_route(HttpMethod::GET, "/healthz", |_| "ok".into())
+            .allow_during_maintenance("/healthz")
+            .set_maintenance_mode(true, Duration::from_secs(120));
+        let response = send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(head.lines().any(|line| line == "retry-after: 120"));
+        assert_eq!(body, b"<h1>Service Unavailable</h1>");
+        let response = send_request(&server, b"GET /healthz HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(body, b"ok");
+
+        // switched off through the handle, as for a running server
+        server.handle().set_maintenance_mode(false, Duration::ZERO);
+        let response = send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!head.contains("retry-after"));
+        assert_eq!(body, b"home");
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        use std::time::Instant;
+
+        let mut server = Server::new();
+        server.add_route(HttpMethod::POST, "/echo/{n}", |req| {
+            format!("{}:{}", req.params()["n"], req.body()).into()
+        });
+
+        // the serial loop doesn't keep connections alive by default
+        let response = send_request(&server, b"POST /echo/1 HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response)
+            .0
+            .lines()
+            .any(|line| line == "connection: close"));
+
+        // pipelined requests, with bodies separated by their content-length
+        server.set_keep_alive_timeout(DEFAULT_KEEP_ALIVE_TIMEOUT);
+        let response = send_request(
+            &server,
+            b"POST /echo/1 HTTP/1.1\r\nContent-Length: 2\r\n\r\nabPOST /echo/2 HTTP/1.1\r\nContent-Length: 3\r\n\r\ncde",
+        );
+        let response = String::from_utf8(response).unwrap();
+        let (first, second) = response.split_once("1:ab").unwrap();
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!first.contains("connection"));
+        assert!(second.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(second.ends_with("\r\n\r\n2:cde"));
+
+        // one request after the other, until the client asks for closing
+        server.set_keep_alive_timeout(Duration::from_millis(200));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        thread::scope(|scope| {
+            let serving = scope.spawn(|| server.handle_request(stream));
+            client
+                .write_all(b"POST /echo/1 HTTP/1.1\r\nContent-Length: 1\r\n\r\na")
+                .unwrap();
+            assert!(read_one_response(&mut client).ends_with(b"1:a"));
+            client
+                .write_all(b"POST /echo/2 HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).unwrap();
+            let (head, body) = split_response(&response);
+            assert!(head.lines().any(|line| line == "connection: close"));
+            assert_eq!(body, b"2:");
+            serving.join().unwrap().unwrap();
+        });
+
+        // an idle connection is closed after the keep-alive timeout
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"POST /echo/1 HTTP/1.1\r\n\r\n").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        server.handle_request(stream).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(split_response(&response).1, b"1:");
+
+        // HTTP/1.0 connections are closed, unless asked otherwise
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"POST /echo/1 HTTP/1.0\r\n\r\n").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.handle_request(stream).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(split_response(&response)
+            .0
+            .lines()
+            .any(|line| line == "connection: close"));
+        let response = send_request(
+            &server,
+            b"POST /echo/1 HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        );
+        assert!(split_response(&response)
+            .0
+            .lines()
+            .any(|line| line == "connection: keep-alive"));
+    }
+
+    #[test]
+    fn test_max_connections_per_ip() {
+        use std::time::Instant;
+
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/", |_| "hello".into())
+            .set_max_connections_per_ip(2);
+        let handle = server.handle();
+        let running = thread::spawn(move || server.bind_and_run_with_threads("127.0.0.1:0", 4));
+        let address = loop {
+            if let Some(address) = handle.local_addr() {
+                break address;
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+
+        // kept-alive connections stay open, holding their slots
+        let mut open: Vec<TcpStream> = (0..2)
+            .map(|_| {
+                let mut client = TcpStream::connect(address).unwrap();
+                client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+                assert!(read_one_response(&mut client).ends_with(b"hello"));
+                client
+            })
+            .collect();
+
+        let mut refused = TcpStream::connect(address).unwrap();
+        let mut response = Vec::new();
+        refused.read_to_end(&mut response).unwrap();
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // closing one frees its slot, once the server has noticed
+        drop(open.pop());
+        let start = Instant::now();
+        let response = loop {
+            let mut client = TcpStream::connect(address).unwrap();
+            client
+                .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = Vec::new();
+            let _ = client.read_to_end(&mut response);
+            if response.starts_with(b"HTTP/1.1 200 OK") || start.elapsed() > Duration::from_secs(5)
+            {
+                break response;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(split_response(&response).1, b"hello");
+
+        drop(open);
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_max_idle_connections() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/", |_| "hello".into())
+            .set_max_idle_connections(1);
+        let handle = server.handle();
+        let running = thread::spawn(move || server.bind_and_run_with_threads("127.0.0.1:0", 4));
+        let address = loop {
+            if let Some(address) = handle.local_addr() {
+                break address;
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+
+        // the first connection takes the only idle slot
+        let mut idle = TcpStream::connect(address).unwrap();
+        idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_one_response(&mut idle);
+        assert!(!split_response(&response).0.contains("connection"));
+
+        // so the next one is closed after its response
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        let (head, body) = split_response(&response);
+        assert!(head.lines().any(|line| line == "connection: close"));
+        assert_eq!(body, b"hello");
+
+        // the idle connection is still usable
+        idle.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        idle.read_to_end(&mut response).unwrap();
+        assert_eq!(split_response(&response).1, b"hello");
+
+        handle.shutdown();
+        let stats = running.join().unwrap().unwrap();
+        assert_eq!(stats.requests, 3);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        use std::time::{Duration, Instant};
+
+        for threads in [None, Some(2)] {
+            let mut server = Server::new();
+            server.add_route(HttpMethod::GET, "/", |_| "hello".into());
+            let handle = server.handle();
+            let running = thread::spawn(move || match threads {
+                None => server.bind_and_run("127.0.0.1:0"),
+                Some(threads) => server.bind_and_run_with_threads("127.0.0.1:0", threads),
+            });
+            let start = Instant::now();
+            let address = loop {
+                if let Some(address) = handle.local_addr() {
+                    break address;
+                }
+                assert!(start.elapsed() < Duration::from_secs(5));
+                thread::sleep(Duration::from_millis(5));
+            };
+
+            let mut client = TcpStream::connect(address).unwrap();
+            client
+                .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).unwrap();
+            assert_eq!(split_response(&response).1, b"hello");
+
+            handle.shutdown();
+            let stats = running.join().unwrap().unwrap();
+            assert_eq!(stats.connections, 1);
+            assert_eq!(stats.requests, 1);
+            assert_eq!(stats.responses_in_class(2), 1);
+            assert_eq!(handle.local_addr(), None);
+        }
+    }
+
+    #[test]
+    fn test_reject_get_body() {
+        let mut server = Server::new();
+        server.add_route(HttpMethod::GET, "/", |_| "index".into());
+        let with_body = b"GET / HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+        let response = send_request(&server, with_body);
+        assert_eq!(split_response(&response).1, b"index");
+
+        server.set_reject_get_body(true);
+        let response = send_request(&server, with_body);
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 400 Bad Request"));
+        let chunked =
+            b"HEAD / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\n";
+        let response = send_request(&server, chunked);
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 400 Bad Request"));
+        let response = send_request(&server, b"GET / HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"index");
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        use std::time::Instant;
+
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/", |_| "index".into())
+            .set_timeouts(Duration::from_millis(200), Duration::from_millis(200));
+
+        // half a request, then the client stalls
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        server.handle_request(stream).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(5));
+
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 408 Request Timeout"));
+
+        // complete requests are unaffected
+        let response = send_request(&server, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"index");
+    }
+
+    #[test]
+    fn test_body_parsers() {
+        #[derive(Debug, PartialEq)]
+        struct Note {
+            title: String,
+        }
+
+        let mut server = Server::new();
+        server
+            .register_body_parser("application/json", |req| {
+                // just enough JSON for the test
+                req.raw_content()
+                    .split_once("\"title\":\"")
+                    .and_then(|(_, rest)| rest.split_once('"'))
+                    .map(|(title, _)| Note {
+                        title: title.to_string(),
+                    })
+                    .ok_or("missing title")
+            })
+            .register_body_parser("application/x-www-form-urlencoded", |req| {
+                req.form()
+                    .get("title")
+                    .map(|title| Note {
+                        title: title.clone(),
+                    })
+                    .ok_or("missing title")
+            })
+            .add_route(HttpMethod::POST, "/notes", |req| {
+                req.parse_body::<Note>()
+                    .map(|note| Response::from(note.title))
+                    .into()
+            });
+
+        let response = send_request(
+            &server,
+            b"POST /notes HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: 16\r\n\r\n{\"title\":\"shop\"}",
+        );
+        assert_eq!(split_response(&response).1, b"shop");
+        let response = send_request(
+            &server,
+            b"POST /notes HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 10\r\n\r\ntitle=shop",
+        );
+        assert_eq!(split_response(&response).1, b"shop");
+
+        let response = send_request(
+            &server,
+            b"POST /notes HTTP/1.1\r\nContent-Type: text/csv\r\nContent-Length: 4\r\n\r\nshop",
+        );
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 415 Unsupported Media Type"));
+        let response = send_request(
+            &server,
+            b"POST /notes HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+        );
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(head
+            .lines()
+            .any(|line| line == "content-type: text/plain; charset=utf-8"));
+        assert_eq!(body, b"invalid body: missing title");
+        let response = send_request(
+            &server,
+            b"POST /notes HTTP/1.1\r\nContent-Type: application/json; Charset=\"UTF-16\"\r\nContent-Length: 4\r\n\r\n{\0}\0",
+        );
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 415 Unsupported Media Type"));
+        assert_eq!(body, b"unsupported charset utf-16");
+
+        // unless parsed leniently, JSON has to be declared as such
+        let mut request = request::utils::parse_request_from_http_request_body(
+            "POST / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n{\"title\":\"hook\"}".to_string(),
+        )
+        .unwrap();
+        request::utils::set_request_body_parsers(&mut request, Arc::clone(&server.body_parsers));
+        assert_eq!(
+            request.parse_body::<Note>(),
+            Err(BodyError::UnsupportedMediaType(Some(
+                "text/plain".to_string()
+            )))
+        );
+        assert_eq!(
+            request.json_lenient::<Note>(),
+            Ok(Note {
+                title: "hook".to_string()
+            })
+        );
+        let request = request::utils::parse_request_from_http_request_body(
+            "POST / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n{}".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.json_lenient::<Note>(), Err(JsonError::NoParser));
+        let mut request = request::utils::parse_request_from_http_request_body(
+            "POST / HTTP/1.1\r\nContent-Type: text/plain; charset=latin1\r\n\r\n{}".to_string(),
+        )
+        .unwrap();
+        request::utils::set_request_body_parsers(&mut request, Arc::clone(&server.body_parsers));
+        assert_eq!(
+            request.json_lenient::<Note>(),
+            Err(JsonError::UnsupportedCharset("latin1".to_string()))
+        );
+
+        // parsers are per type
+        let request =
+            request::utils::parse_request_from_http_request_body("POST / HTTP/1.1".to_string())
+                .unwrap();
+        assert_eq!(
+            request.parse_body::<Note>(),
+            Err(BodyError::UnsupportedMediaType(None))
+        );
+    }
+
+    #[test]
+    fn test_json_body() {
+        #[derive(Debug, PartialEq)]
+        struct User {
+            name: String,
+        }
+
+        let mut server = Server::new();
+        server
+            .register_body_parser("application/json", |req| {
+                let name = req
+                    .body()
+                    .strip_prefix("{\"name\":\"")
+                    .and_then(|rest| rest.strip_suffix("\"}"))
+                    .ok_or("not an object")?;
+                Ok::<_, &str>(User {
+                    name: name.to_string(),
+                })
+            })
+            .add_route(HttpMethod::POST, "/users", |req| match req.json::<User>() {
+                Ok(user) => user.name.into(),
+                Err(err) => err.into(),
+            });
+
+        let response = send_request(
+            &server,
+            b"POST /users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 14\r\n\r\n{\"name\":\"bob\"}",
+        );
+        assert_eq!(split_response(&response).1, b"bob");
+
+        for (request, status, message) in [
+            (
+                &b"POST /users HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 14\r\n\r\n{\"name\":\"bob\"}"[..],
+                "400 Bad Request",
+                "expected application/json, got text/plain",
+            ),
+            (
+                b"POST /users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 5\r\n\r\n{name",
+                "400 Bad Request",
+                "invalid JSON: not an object",
+            ),
+            (
+                b"POST /users HTTP/1.1\r\nContent-Type: application/json; charset=utf-16\r\nContent-Length: 2\r\n\r\n{}",
+                "415 Unsupported Media Type",
+                "unsupported charset utf-16",
+            ),
+        ] {
+            let response = send_request(&server, request);
+            let (head, body) = split_response(&response);
+            assert!(head.starts_with(&format!("HTTP/1.1 {}\r\n", status)), "{}", head);
+            assert_eq!(String::from_utf8_lossy(body), message);
+        }
+
+        // the parser has to be registered for the requested type
+        let mut request = request::utils::parse_request_from_http_request_body(
+            "POST / HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{}".to_string(),
+        )
+        .unwrap();
+        request::utils::set_request_body_parsers(&mut request, Arc::clone(&server.body_parsers));
+        assert_eq!(request.json::<String>(), Err(JsonError::NoParser));
+        assert_eq!(
+            Response::from(JsonError::NoParser).status_code(),
+            HttpStatusCode::InternalServerError
+        );
+    }
+
+    #[test]
+    fn test_middleware() {
+        let mut server = Server::new();
+        server
+            .add_middleware(|req, next| {
+                next.run(req)
+                    .with_header(HttpHeaderName::Server, "raspi-file-server")
+            })
+            .add_middleware(|req, next| match req.header("authorization") {
+                Some(_) => next.run(req),
+                None => Response::with_status(HttpStatusCode::Unauthorized)
+                    .with_header(HttpHeaderName::WwwAuthenticate, "Basic"),
+            })
+            .add_route(HttpMethod::GET, "/users/{id}", |req| {
+                req.params()["id"].as_str().into()
+            });
+
+        let response = send_request(&server, b"GET /users/7 HTTP/1.1\r\n\r\n");
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 401 Unauthorized"));
+        assert!(head.lines().any(|line| line == "www-authenticate: Basic"));
+        assert!(head.lines().any(|line| line == "server: raspi-file-server"));
+        assert!(body.is_empty());
+
+        let response = send_request(
+            &server,
+            b"GET /users/7 HTTP/1.1\r\nAuthorization: Basic cGk6cGk=\r\n\r\n",
+        );
+        let (head, body) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.lines().any(|line| line == "server: raspi-file-server"));
+        assert_eq!(body, b"7");
+
+        let response = send_request(
+            &server,
+            b"GET /missing HTTP/1.1\r\nAuthorization: Basic cGk6cGk=\r\n\r\n",
+        );
+        let (head, _) = split_response(&response);
+        assert!(head.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(head.lines().any(|line| line == "server: raspi-file-server"));
+    }
+
+    #[test]
+    fn test_middleware_for_prefix() {
+        let mut server = Server::new();
+        server
+            .add_middleware_for("/admin/", |req, next| match req.header("authorization") {
+                Some(_) => next.run(req),
+                None => Response::with_status(HttpStatusCode::Unauthorized),
+            })
+            .add_route(HttpMethod::GET, "/admin/x", |_| "admin".into())
+            .add_route(HttpMethod::GET, "/administrator", |_| "other".into())
+            .add_route(HttpMethod::GET, "/public/y", |_| "public".into());
+
+        let response = send_request(&server, b"GET /admin/x HTTP/1.1\r\n\r\n");
+        assert!(split_response(&response)
+            .0
+            .starts_with("HTTP/1.1 401 Unauthorized"));
+        let response = send_request(
+            &server,
+            b"GET /admin/x HTTP/1.1\r\nAuthorization: Basic YTpi\r\n\r\n",
+        );
+        assert_eq!(split_response(&response).1, b"admin");
+        let response = send_request(&server, b"GET /public/y HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"public");
+        let response = send_request(&server, b"GET /administrator HTTP/1.1\r\n\r\n");
+        assert_eq!(split_response(&response).1, b"other");
+    }
+
+    #[test]
+    fn test_splat_route() {
+        let mut server = Server::new();
+        server
+            .add_route(HttpMethod::GET, "/assets/{*rest}", |req| {
+                format!("splat:{}", req.params()["rest"]).into()
+            })
+            .add_route(HttpMethod::GET, "/assets/css/{file}", |req| {
+                format!("css:{}", req.params()["file"]).into()
+            });
+
+        for (request, body) in [
+            (
+                &b"GET /assets/css/site.css HTTP/1.1\r\n\r\n"[..],
+                &b"css:site.css"[..],
+            ),
+            (
+                b"GET /assets/css/vendor/x.css HTTP/1.1\r\n\r\n",
+                b"splat:css/vendor/x.css",
+            ),
+            (
+                b"GET /assets/img/logo.svg HTTP/1.1\r\n\r\n",
+                b"splat:img/logo.svg",
+            ),
+            (b"GET /assets/ HTTP/1.1\r\n\r\n", b"splat:"),
+        ] {
+            let response = send_request(&server, request);
+            assert_eq!(split_response(&response).1, body);
+        }
     }
 }
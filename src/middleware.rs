@@ -0,0 +1,67 @@
+use crate::{Request, Response};
+
+pub type Middleware = Box<dyn Fn(&Request, Next) -> Response + Send + Sync>;
+
+/// The rest of the middleware chain, ending with the handler of the matched
+/// route, see [Server::add_middleware](crate::Server::add_middleware).
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    endpoint: &'a dyn Fn(&Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a [Middleware],
+        endpoint: &'a dyn Fn(&Request) -> Response,
+    ) -> Self {
+        Next {
+            middleware,
+            endpoint,
+        }
+    }
+
+    /// Runs the remaining middleware and the handler, and returns their
+    /// response. Middleware which doesn't call this answers the request
+    /// itself.
+    pub fn run(self, request: &Request) -> Response {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first(request, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::utils::parse_request_from_http_request_body;
+
+    #[test]
+    fn test_chain_order() {
+        let middleware: Vec<Middleware> = vec![
+            Box::new(|req, next| {
+                let response = next.run(req);
+                let body = format!("outer({})", response_body(response));
+                Response::new().with_body(body)
+            }),
+            Box::new(|req, next| {
+                let response = next.run(req);
+                let body = format!("inner({})", response_body(response));
+                Response::new().with_body(body)
+            }),
+        ];
+        let endpoint = |_: &Request| Response::new().with_body("handler");
+        let request = parse_request_from_http_request_body("GET / HTTP/1.1".to_string()).unwrap();
+        let response = Next::new(&middleware, &endpoint).run(&request);
+        assert_eq!(response_body(response), "outer(inner(handler))");
+
+        let response = Next::new(&[], &endpoint).run(&request);
+        assert_eq!(response_body(response), "handler");
+    }
+
+    fn response_body(response: Response) -> String {
+        let bytes = crate::response::response_into_http_response_bytes(response);
+        let text = String::from_utf8(bytes).unwrap();
+        text.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+}
@@ -0,0 +1,63 @@
+/// Guesses the MIME type of a file from its extension (case-insensitive).
+/// Unknown extensions map to `application/octet-stream`.
+pub fn mime_from_extension(extension: &str) -> &'static str {
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Guesses the MIME type of a file from the extension of its path.
+pub fn mime_from_path(path: &std::path::Path) -> &'static str {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or("application/octet-stream", mime_from_extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_mime_from_extension() {
+        let table = [
+            ("html", "text/html"),
+            ("HTM", "text/html"),
+            ("css", "text/css"),
+            ("js", "text/javascript"),
+            ("json", "application/json"),
+            ("png", "image/png"),
+            ("jpg", "image/jpeg"),
+            ("jpeg", "image/jpeg"),
+            ("svg", "image/svg+xml"),
+            ("txt", "text/plain"),
+            ("wasm", "application/wasm"),
+            ("unknown", "application/octet-stream"),
+            ("", "application/octet-stream"),
+        ];
+        for (extension, mime) in table {
+            assert_eq!(mime_from_extension(extension), mime, "{}", extension);
+        }
+        assert_eq!(
+            mime_from_path(Path::new("/www/app.min.js")),
+            "text/javascript"
+        );
+        assert_eq!(
+            mime_from_path(Path::new("/www/README")),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use crate::request::{self, Request};
+use crate::response::{HttpHeaderName, HttpStatusCode, Response};
+use std::io::{self, Cursor, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Request headers which describe the connection to this server, rather than
+/// the request itself, and therefore aren't forwarded. Neither are the
+/// headers named in the `connection` header of the request.
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "host",
+    "connection",
+    "content-length",
+    "transfer-encoding",
+    "keep-alive",
+    "te",
+    "trailer",
+    "upgrade",
+    "proxy-connection",
+];
+
+/// The maximum length of the status line plus headers of an upstream response.
+const MAX_UPSTREAM_HEAD_SIZE: usize = 16 * 1024;
+
+/// How long connecting to the upstream, and each read or write on the
+/// connection to it, may take.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Handler for [Server::proxy](crate::Server::proxy). Failures to reach the
+/// upstream, timeouts or failures to understand its response yield
+/// `502 Bad Gateway`.
+pub fn forward(req: &Request, upstream: &str) -> Response {
+    fetch(req, upstream, UPSTREAM_TIMEOUT)
+        .unwrap_or_else(|_| Response::with_status(HttpStatusCode::BadGateway))
+}
+
+/// Sends the request to `upstream` and reads the head of its response. The
+/// body isn't read here, but streamed from the connection while the response
+/// is written. The request is sent as HTTP/1.0, so the upstream closes the
+/// connection after answering and doesn't use chunked encoding.
+fn fetch(req: &Request, upstream: &str, timeout: Duration) -> io::Result<Response> {
+    let mut stream = connect(upstream, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let body = req.raw_body();
+    let mut head = format!(
+        "{} {} HTTP/1.0\r\nhost: {}\r\n",
+        req.method(),
+        request::utils::path_and_query(req),
+        upstream
+    );
+    let connection_tokens = req.connection_tokens();
+    for (name, value) in req.header_iter() {
+        let name_lower = name.to_ascii_lowercase();
+        if !HOP_BY_HOP_HEADERS.contains(&name_lower.as_str())
+            && !connection_tokens.contains(&name_lower)
+        {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    head.push_str(&format!("content-length: {}\r\n\r\n", body.len()));
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let (head, received) = read_head(&mut stream)?;
+    parse_upstream_response(&head, Cursor::new(received).chain(stream))
+        .ok_or_else(|| io::ErrorKind::InvalidData.into())
+}
+
+/// Reads the response of the upstream up to the blank line ending its head.
+/// Returns the head and the bytes of the body which were read along with it.
+fn read_head(stream: &mut impl Read) -> io::Result<(String, Vec<u8>)> {
+    let mut received = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+            let body = received.split_off(end + 4);
+            let head = String::from_utf8(received)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            return Ok((head, body));
+        }
+        if received.len() > MAX_UPSTREAM_HEAD_SIZE {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        match stream.read(&mut chunk)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => received.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+/// Connects to the first address of `upstream` which accepts within `timeout`.
+fn connect(upstream: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "upstream has no address");
+    for address in upstream.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Parses the head of the upstream response, whose body is then streamed
+/// from `body`: exactly `content-length` bytes of it, or everything up to the
+/// end if there is no `content-length`. All occurrences of a repeated header
+/// like `set-cookie` are kept, `connection` is dropped as it only concerns the
+/// connection to the upstream.
+fn parse_upstream_response<R: Read + 'static>(head: &str, body: R) -> Option<Response> {
+    let mut lines = head.lines();
+
+    let code = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+    let mut response = Response::status(code).ok()?;
+    let mut content_length = None;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        match HttpHeaderName::from(name.trim()) {
+            HttpHeaderName::ContentLength => content_length = value.parse().ok(),
+            HttpHeaderName::TransferEncoding => return None,
+            HttpHeaderName::Connection => {}
+            header_name if response.header(header_name.clone()).is_some() => {
+                response.append_header(header_name, value)
+            }
+            header_name => response.set_header(header_name, value),
+        }
+    }
+
+    if !response.status_code().forbids_body() {
+        match content_length {
+            Some(length) => response.set_sized_stream(body.take(length), length),
+            None => response.set_stream(body),
+        }
+    }
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::utils::parse_request_from_http_request_body;
+    use crate::response::write_response;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Starts an upstream which answers a single request with `response` and
+    /// returns its address, along with a handle yielding the request it got.
+    fn upstream(response: &'static [u8]) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).unwrap();
+            stream.write_all(response).unwrap();
+            String::from_utf8_lossy(&request[..n]).to_string()
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn test_proxy_keeps_all_set_cookie_headers() {
+        let (address, handle) = upstream(
+            b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nset-cookie: b=2\r\ncontent-type: text/plain\r\nX-Upstream: 1\r\nconnection: close\r\ncontent-length: 2\r\n\r\nhi",
+        );
+        let req = parse_request_from_http_request_body(
+            "GET /api?x=1 HTTP/1.1\r\nHost: localhost\r\nAccept: text/plain\r\n\r\n".to_string(),
+        )
+        .unwrap();
+        let response = forward(&req, &address);
+        assert_eq!(
+            response.header_values(HttpHeaderName::SetCookie),
+            ["a=1", "b=2"]
+        );
+
+        let mut http = Vec::new();
+        write_response(response, &mut http).unwrap();
+        let http = String::from_utf8(http).unwrap();
+        assert!(http.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(http.contains("set-cookie: a=1\r\n"));
+        assert!(http.contains("set-cookie: b=2\r\n"));
+        assert!(http.contains("content-type: text/plain\r\n"));
+        assert!(http.contains("x-upstream: 1\r\n"));
+        assert!(!http.contains("connection"));
+        assert!(http.ends_with("content-length: 2\r\n\r\nhi"));
+
+        let forwarded = handle.join().unwrap();
+        assert!(forwarded.starts_with("GET /api?x=1 HTTP/1.0\r\n"));
+        assert!(forwarded.contains(&format!("host: {}\r\n", address)));
+        assert!(forwarded.contains("Accept: text/plain\r\n"));
+        assert!(!forwarded.contains("localhost"));
+    }
+
+    #[test]
+    fn test_proxy_streams_body_without_content_length() {
+        let (address, handle) =
+            upstream(b"HTTP/1.1 200 OK\r\n\r\nstreamed until the upstream closes");
+        let req = parse_request_from_http_request_body("GET / HTTP/1.1".to_string()).unwrap();
+        let response = forward(&req, &address);
+        handle.join().unwrap();
+
+        let mut http = Vec::new();
+        write_response(response, &mut http).unwrap();
+        let head_end = http.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(http[..head_end].to_vec()).unwrap();
+        assert!(head.contains("transfer-encoding: chunked"));
+        assert_eq!(
+            crate::response::tests::decode_chunked(&http[head_end + 4..]),
+            b"streamed until the upstream closes"
+        );
+    }
+
+    #[test]
+    fn test_proxy_drops_hop_by_hop_headers() {
+        let (address, handle) = upstream(b"HTTP/1.1 204 No Content\r\n\r\n");
+        let req = parse_request_from_http_request_body(
+            "GET / HTTP/1.1\r\nConnection: keep-alive, X-Secret\r\nKeep-Alive: timeout=5\r\nTE: trailers\r\nUpgrade: websocket\r\nProxy-Connection: keep-alive\r\nX-Secret: 1\r\nX-Public: 2\r\n\r\n".to_string(),
+        )
+        .unwrap();
+        forward(&req, &address);
+
+        let forwarded = handle.join().unwrap().to_ascii_lowercase();
+        assert!(forwarded.contains("x-public: 2\r\n"));
+        for name in ["connection", "keep-alive", "te:", "upgrade", "x-secret"] {
+            assert!(!forwarded.contains(name), "{} in {}", name, forwarded);
+        }
+    }
+
+    #[test]
+    fn test_proxy_forwards_binary_body() {
+        let (address, handle) = upstream(b"HTTP/1.1 204 No Content\r\n\r\n");
+        let mut request = b"POST /upload HTTP/1.1\r\ncontent-length: 4\r\n\r\n".to_vec();
+        request.extend([0xff, 0x00, 0xfe, 0x80]);
+        let req = request::utils::parse_request_from_bytes(request).unwrap();
+        let response = forward(&req, &address);
+        assert_eq!(response.status_code(), HttpStatusCode::NoContent);
+
+        let forwarded = handle.join().unwrap();
+        assert!(forwarded.contains("content-length: 4\r\n"));
+    }
+
+    #[test]
+    fn test_upstream_timeout() {
+        // the upstream accepts, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let req = parse_request_from_http_request_body("GET / HTTP/1.1".to_string()).unwrap();
+        let result = fetch(&req, &address, Duration::from_millis(100));
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        drop(listener);
+    }
+
+    #[test]
+    fn test_unreachable_upstream() {
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let req = parse_request_from_http_request_body("GET / HTTP/1.1".to_string()).unwrap();
+        let response = forward(&req, &address);
+        assert_eq!(response.status_code(), HttpStatusCode::BadGateway);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::response::{HttpStatusCode, Response};
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read};
+
+/// The maximum length of the request line plus headers.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// The maximum length of a chunk-size line (including chunk extensions) or
+/// of a trailer line in a chunked body.
+pub const MAX_CHUNK_LINE_LENGTH: usize = 1024;
+
+/// Reads HTTP requests from a stream, one at a time.
+///
+/// The head is read up to the blank line, the body according to
+/// `content-length` or `transfer-encoding: chunked`. Bytes read past the end
+/// of a request are kept for the next one.
+pub struct RequestReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> RequestReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Reads the next request. A chunked body is decoded, so the returned
+    /// bytes consist of the head, a blank line and the plain body.
+    ///
+    /// If the stream ends before the blank line, whatever was received is
+    /// returned as the request.
+    pub fn read_request(&mut self, max_body_size: usize) -> Result<Vec<u8>, ReadError> {
+        let head_end = loop {
+            if let Some(end) = find_head_end(&self.buffer) {
+                break end;
+            }
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err(ReadError::HeadTooLarge);
+            }
+            if self.fill()? == 0 {
+                return Ok(std::mem::take(&mut self.buffer));
+            }
+        };
+        let mut request: Vec<u8> = self.buffer.drain(..head_end).collect();
+        let head = String::from_utf8_lossy(&request).to_string();
+
+        let framing = body_framing(&head)?;
+        if framing == Framing::Chunked {
+            let body = self.read_chunked_body(max_body_size)?;
+            trim_blank_line(&mut request);
+            request.extend_from_slice(b"\r\n\r\n");
+            request.extend_from_slice(&body);
+        } else if let Framing::Length(length) = framing {
+            if length > max_body_size {
+                return Err(ReadError::BodyTooLarge);
+            }
+            while self.buffer.len() < length {
+                if self.fill()? == 0 {
+                    return Err(ReadError::Malformed("body is shorter than content-length"));
+                }
+            }
+            request.extend(self.buffer.drain(..length));
+        }
+        Ok(request)
+    }
+
+    /// Waits until the next request starts, i.e. until bytes of it are
+    /// available. Returns false if the stream ends first.
+    pub fn wait_for_request(&mut self) -> io::Result<bool> {
+        if self.buffer.is_empty() {
+            return Ok(self.fill()? > 0);
+        }
+        Ok(true)
+    }
+
+    fn read_chunked_body(&mut self, max_body_size: usize) -> Result<Vec<u8>, ReadError> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| ReadError::Malformed("invalid chunk size"))?;
+            if size > max_body_size - body.len() {
+                return Err(ReadError::BodyTooLarge);
+            }
+            if size == 0 {
+                break;
+            }
+            let chunk_end = size
+                .checked_add(2)
+                .ok_or(ReadError::Malformed("invalid chunk size"))?;
+            while self.buffer.len() < chunk_end {
+                if self.fill()? == 0 {
+                    return Err(ReadError::Malformed("chunked body ended early"));
+                }
+            }
+            body.extend(self.buffer.drain(..size));
+            if self.buffer.drain(..2).as_slice() != b"\r\n" {
+                return Err(ReadError::Malformed("chunk is not terminated by CRLF"));
+            }
+        }
+        // trailers are skipped, up to the blank line ending the body
+        let mut trailer_size = 0;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(body);
+            }
+            trailer_size += line.len();
+            if trailer_size > MAX_HEAD_SIZE {
+                return Err(ReadError::HeadTooLarge);
+            }
+        }
+    }
+
+    /// Reads a line terminated by `\n` (with an optional `\r`), which must
+    /// not exceed [MAX_CHUNK_LINE_LENGTH].
+    fn read_line(&mut self) -> Result<String, ReadError> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                if end > MAX_CHUNK_LINE_LENGTH {
+                    return Err(ReadError::LineTooLong);
+                }
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                return Ok(line.trim_end_matches(['\n', '\r']).to_string());
+            }
+            if self.buffer.len() > MAX_CHUNK_LINE_LENGTH {
+                return Err(ReadError::LineTooLong);
+            }
+            if self.fill()? == 0 {
+                return Err(ReadError::Malformed("chunked body ended early"));
+            }
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0; 8192];
+        loop {
+            match self.inner.read(&mut chunk) {
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    return Ok(n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Returns the index right after the blank line terminating the head.
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    let crlf = buffer
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| i + 4);
+    let lf = buffer.windows(2).position(|w| w == b"\n\n").map(|i| i + 2);
+    match (crlf, lf) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn trim_blank_line(head: &mut Vec<u8>) {
+    while head.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+        head.pop();
+    }
+}
+
+fn header_values<'a>(head: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> {
+    head.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .filter(move |(n, _)| n.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// How the end of the body is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    None,
+    Chunked,
+    Length(usize),
+}
+
+/// Determines the framing of the body from the head. Anything which could be
+/// read differently by another server on the way, like both
+/// `transfer-encoding` and `content-length` or several differing lengths,
+/// is rejected, so the end of the request can't be disputed.
+fn body_framing(head: &str) -> Result<Framing, ReadError> {
+    let codings: Vec<&str> = header_values(head, "transfer-encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    let lengths: Vec<&str> = header_values(head, "content-length")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    if !codings.is_empty() {
+        if !lengths.is_empty() {
+            return Err(ReadError::Malformed(
+                "both transfer-encoding and content-length",
+            ));
+        }
+        // other codings would have to be decoded after chunked
+        return match codings[..] {
+            [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+            _ => Err(ReadError::Malformed("unsupported transfer-encoding")),
+        };
+    }
+    let Some(first) = lengths.first() else {
+        return Ok(Framing::None);
+    };
+    if lengths.iter().any(|length| length != first) {
+        return Err(ReadError::Malformed("conflicting content-length"));
+    }
+    if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ReadError::Malformed("invalid content-length"));
+    }
+    first
+        .parse()
+        .map(Framing::Length)
+        .map_err(|_| ReadError::Malformed("invalid content-length"))
+}
+
+/// Reasons why a request couldn't be read.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// The client didn't send the request within the read timeout.
+    TimedOut,
+    Malformed(&'static str),
+    HeadTooLarge,
+    LineTooLong,
+    BodyTooLarge,
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            // the kind depends on the platform
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ReadError::TimedOut,
+            _ => ReadError::Io(err),
+        }
+    }
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "unable to read request: {}", err),
+            ReadError::TimedOut => f.write_str("timed out reading the request"),
+            ReadError::Malformed(reason) => write!(f, "malformed request: {}", reason),
+            ReadError::HeadTooLarge => f.write_str("request head is too large"),
+            ReadError::LineTooLong => f.write_str("chunk-size line is too long"),
+            ReadError::BodyTooLarge => f.write_str("request body is too large"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<ReadError> for Response {
+    fn from(err: ReadError) -> Self {
+        let mut resp = Response::default();
+        resp.set_html(err.to_string());
+        resp.set_status_code(match err {
+            ReadError::TimedOut => HttpStatusCode::RequestTimeout,
+            ReadError::BodyTooLarge => HttpStatusCode::PayloadTooLarge,
+            _ => HttpStatusCode::BadRequest,
+        });
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &[u8], max_body_size: usize) -> Result<Vec<u8>, ReadError> {
+        RequestReader::new(input).read_request(max_body_size)
+    }
+
+    #[test]
+    fn test_read_with_content_length() {
+        let input = b"PUT / HTTP/1.1\r\ncontent-length: 5\r\n\r\nhelloNEXT";
+        let mut reader = RequestReader::new(&input[..]);
+        assert_eq!(
+            reader.read_request(100).unwrap(),
+            b"PUT / HTTP/1.1\r\ncontent-length: 5\r\n\r\nhello"
+        );
+        assert_eq!(reader.buffer, b"NEXT");
+    }
+
+    #[test]
+    fn test_read_without_body() {
+        assert_eq!(
+            read(b"GET / HTTP/1.1\r\n\r\n", 100).unwrap(),
+            b"GET / HTTP/1.1\r\n\r\n"
+        );
+        assert_eq!(
+            read(b"GET / HTTP/1.1\r\n", 100).unwrap(),
+            b"GET / HTTP/1.1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_read_chunked() {
+        let input = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        assert_eq!(
+            read(input, 100).unwrap(),
+            b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nhello world"
+        );
+    }
+
+    #[test]
+    fn test_chunk_size_line_too_long() {
+        let mut input = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        input.extend(std::iter::repeat_n(b'0', MAX_CHUNK_LINE_LENGTH + 1));
+        assert!(matches!(read(&input, 100), Err(ReadError::LineTooLong)));
+        input.extend_from_slice(b"1\r\nx\r\n0\r\n\r\n");
+        assert!(matches!(read(&input, 100), Err(ReadError::LineTooLong)));
+    }
+
+    #[test]
+    fn test_body_too_large() {
+        let input = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        assert!(matches!(read(input, 10), Err(ReadError::BodyTooLarge)));
+        let input = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n";
+        assert!(matches!(read(input, 10), Err(ReadError::BodyTooLarge)));
+        // without a limit, the largest chunk size mustn't overflow
+        assert!(matches!(
+            read(input, usize::MAX),
+            Err(ReadError::Malformed(_))
+        ));
+        let input = b"PUT / HTTP/1.1\r\ncontent-length: 11\r\n\r\nhello world";
+        assert!(matches!(read(input, 10), Err(ReadError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn test_malformed_bodies() {
+        let input = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n";
+        assert!(matches!(read(input, 100), Err(ReadError::Malformed(_))));
+        let input = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello";
+        assert!(matches!(read(input, 100), Err(ReadError::Malformed(_))));
+        let input = b"PUT / HTTP/1.1\r\ncontent-length: 5\r\n\r\nhel";
+        assert!(matches!(read(input, 100), Err(ReadError::Malformed(_))));
+        let input = b"PUT / HTTP/1.1\r\ncontent-length: five\r\n\r\n";
+        assert!(matches!(read(input, 100), Err(ReadError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_ambiguous_framing() {
+        for head in [
+            "Transfer-Encoding: chunked\r\nContent-Length: 5",
+            "Content-Length: 5\r\nTransfer-Encoding: chunked",
+            "Transfer-Encoding: chunked, gzip",
+            "Transfer-Encoding: gzip, chunked",
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked",
+            "Transfer-Encoding: xchunked",
+            "Content-Length: 5\r\nContent-Length: 6",
+            "Content-Length: 5, 6",
+            "Content-Length: +5",
+            "Content-Length: -5",
+            "Content-Length: 0x5",
+            "Content-Length:",
+        ] {
+            let input = format!("PUT / HTTP/1.1\r\n{}\r\n\r\n5\r\nhello\r\n0\r\n\r\n", head);
+            let result = read(input.as_bytes(), 100);
+            assert!(matches!(result, Err(ReadError::Malformed(_))), "{}", head);
+        }
+
+        // repeating the same length is unambiguous
+        let input = b"PUT / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\nhello";
+        assert!(read(input, 100).unwrap().ends_with(b"\r\n\r\nhello"));
+        let input = b"PUT / HTTP/1.1\r\nTransfer-Encoding: Chunked\r\n\r\n0\r\n\r\n";
+        assert!(read(input, 100).is_ok());
+    }
+}
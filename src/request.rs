@@ -1,15 +1,26 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::response::{HttpStatusCode, Response};
+use crate::body_parser::{BodyError, BodyParsers, JsonError};
+use crate::response::{HttpHeaderName, HttpStatusCode, Response};
 
 /// A (non-exhaustive) list of HTTP method types
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum HttpMethod {
     GET,
+    POST,
     PUT,
     PATCH,
     DELETE,
+    HEAD,
+    OPTIONS,
+    /// Any other method, e.g. `PURGE` for invalidating cached responses.
+    /// Extension methods consist of uppercase letters, digits, `-` and `_`.
+    Other(String),
 }
 
 impl TryFrom<&str> for HttpMethod {
@@ -18,10 +29,30 @@ impl TryFrom<&str> for HttpMethod {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
             "GET" => Ok(HttpMethod::GET),
+            "POST" => Ok(HttpMethod::POST),
             "PUT" => Ok(HttpMethod::PUT),
             "PATCH" => Ok(HttpMethod::PATCH),
             "DELETE" => Ok(HttpMethod::DELETE),
-            _ => Err(RequestParseError),
+            "HEAD" => Ok(HttpMethod::HEAD),
+            "OPTIONS" => Ok(HttpMethod::OPTIONS),
+            other if is_extension_method(other) => Ok(HttpMethod::Other(other.to_string())),
+            _ => Err(RequestParseError::UnknownMethod(value.to_string())),
+        }
+    }
+}
+
+fn is_extension_method(method: &str) -> bool {
+    !method.is_empty()
+        && method
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+impl Display for HttpMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpMethod::Other(method) => f.write_str(method),
+            other => write!(f, "{:?}", other),
         }
     }
 }
@@ -29,16 +60,34 @@ impl TryFrom<&str> for HttpMethod {
 /// An object representing a HTTP request.
 ///
 /// Through the request struct, the raw content of the HTTP
-/// request can be accessed, as well as the full [path](Request::path_as_str),
+/// request can be accessed, as well as the [path](Request::path_as_str),
 /// the [method](Request::method), query parameters with [Request::queries] or
 /// url parameters with [Request::params] (not yet implemented).
 #[derive(Debug, Clone)]
 pub struct Request {
     raw_content: String,
+    /// The path and query as sent, see [Request::full_target].
+    target: String,
+    /// The path without the query and the base path.
     path: String,
+    /// The query as sent, without the leading `?`.
+    query: Option<String>,
     method: HttpMethod,
+    /// The protocol version of the request line, e.g. `HTTP/1.1`.
+    version: String,
     queries: HashMap<String, Option<String>>,
     params: HashMap<String, String>,
+    /// The pattern of the route the request was matched to.
+    matched_route: Option<String>,
+    headers: HashMap<String, String>,
+    format: Option<String>,
+    /// The body as received, only kept if it isn't valid UTF-8 and therefore
+    /// differs from the body in `raw_content`.
+    raw_body: Option<Vec<u8>>,
+    /// A handle to the connection the request was received on.
+    connection: Option<Arc<TcpStream>>,
+    /// The parsers registered with the server, see [Request::parse_body].
+    body_parsers: Option<Arc<BodyParsers>>,
 }
 
 impl Request {
@@ -48,21 +97,89 @@ impl Request {
         &self.raw_content
     }
 
-    /// Returns the original full path with which the request was sent.
+    /// Returns the body exactly as it was received, e.g. for verifying a
+    /// signature over it. Unlike the body in [Request::raw_content], bytes
+    /// which aren't valid UTF-8 are preserved.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn webhook_route(req: &Request) -> Response {
+    ///     let signature = req.header("x-signature").unwrap_or_default();
+    ///     if verify(req.raw_body(), signature) {
+    ///         Response::empty()
+    ///     } else {
+    ///         Response::with_status(HttpStatusCode::BadRequest)
+    ///     }
+    /// }
+    /// # fn verify(_: &[u8], _: &str) -> bool { true }
+    /// ```
+    pub fn raw_body(&self) -> &[u8] {
+        self.raw_body
+            .as_deref()
+            .unwrap_or_else(|| self.body().as_bytes())
+    }
+
+    /// Returns the query string of the path as it was sent, without the
+    /// leading `?`, or None if the path has no query. Nothing is decoded or
+    /// reordered, see [Request::queries] for the parsed parameters.
+    pub fn raw_query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// Returns the path of the request, without the query and the
+    /// [base path](crate::Server::set_base_path) of the server.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// // for a request to `/greet?name=x`, "/greet" is printed
+    /// fn greet_route(req: &Request) -> Response {
+    ///     println!("{}", req.path_as_str());
+    ///     "<h1>Hello</h1>".into()
+    /// }
+    /// ```
     pub fn path_as_str(&self) -> &str {
         &self.path
     }
 
+    /// Returns the request target as it was sent, i.e. the path including
+    /// the base path and the query. Targets in absolute form
+    /// (`http://example.com/a?b=1`) are reduced to the path and query.
+    pub fn full_target(&self) -> &str {
+        &self.target
+    }
+
+    /// Returns the non-empty segments of the path, without the query, split
+    /// the same way the router splits them.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn files_route(req: &Request) -> Response {
+    ///     match req.path_segments().nth(1) {
+    ///         Some(name) => format!("file {}", name).into(),
+    ///         None => Response::with_status(HttpStatusCode::NotFound),
+    ///     }
+    /// }
+    /// ```
+    pub fn path_segments(&self) -> impl Iterator<Item = &str> {
+        utils::path_segments(&self.path)
+    }
+
     /// Returns the [HttpMethod] with which the request was sent.
     pub fn method(&self) -> HttpMethod {
-        self.method
+        self.method.clone()
+    }
+
+    /// Returns the protocol version of the request line, e.g. `HTTP/1.1`.
+    pub fn version(&self) -> &str {
+        &self.version
     }
 
     /// Returns a reference to a [HashMap] containing the encoded query parameters.
     ///
     /// Parameters are encoded in the path of the request. Query parameters
     /// consist of a key and an optional value. The first query parameter is prefixed
-    /// with a `?`, following ones are separated by `&`. See the example below.
+    /// with a `?`, following ones are separated by `&`. Keys and values are
+    /// percent-decoded, with `+` standing for a space. See the example below.
     /// ```
     /// use raspi_file_server::*;
     ///
@@ -97,12 +214,53 @@ impl Request {
         &self.queries
     }
 
+    /// Parses the query parameter `name` into a `T`, e.g. a number. Returns
+    /// `Ok(None)` if the parameter is missing, which maps nicely onto optional
+    /// fields when filling a struct of filters.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// struct Pagination {
+    ///     page: u32,
+    ///     size: Option<u32>,
+    /// }
+    ///
+    /// // GET /items?page=2&size=10
+    /// fn items_route(req: &Request) -> Result<Pagination, QueryError> {
+    ///     Ok(Pagination {
+    ///         page: req.query_required("page")?,
+    ///         size: req.query_as("size")?,
+    ///     })
+    /// }
+    /// ```
+    pub fn query_as<T: FromStr>(&self, name: &str) -> Result<Option<T>, QueryError> {
+        match self.queries.get(name) {
+            None => Ok(None),
+            Some(value) => {
+                let value = value.as_deref().unwrap_or_default();
+                value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| QueryError::Invalid(name.to_string()))
+            }
+        }
+    }
+
+    /// Like [Request::query_as], but a missing parameter is an error as well.
+    pub fn query_required<T: FromStr>(&self, name: &str) -> Result<T, QueryError> {
+        self.query_as(name)?
+            .ok_or_else(|| QueryError::Missing(name.to_string()))
+    }
+
     /// Returns a reference to a [HashMap] containing the encoded url parameters.
     ///
     /// Parameters are encoded as elements of the path of the request, e.g.
     /// the [route](crate::Server::add_route) `/greet/{name}` activated by a
     /// request to `/greet/johnDoe` would get passed a Request object where
     /// `request.params().get("name")` yields a Some("johnDoe") value.
+    ///
+    /// Values are percent-decoded, but unlike in the query, a `+` in the path
+    /// is a literal plus and not a space.
     /// ```
     /// use raspi_file_server::*;
     ///
@@ -126,178 +284,1542 @@ impl Request {
     pub fn params(&self) -> &HashMap<String, String> {
         &self.params
     }
-}
 
-pub mod utils {
-    use super::*;
+    /// Returns the pattern of the route which matched the request, e.g.
+    /// `/users/{id}` for a request to `/users/42`, which is handy for
+    /// aggregating logs or metrics by route. Returns None before routing and
+    /// if no route matched.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// let mut server = Server::new();
+    /// server.add_middleware(|req: &Request, next: Next| {
+    ///     let response = next.run(req);
+    ///     println!("{} {}", req.matched_route().unwrap_or("-"), usize::from(response.status_code()));
+    ///     response
+    /// });
+    /// ```
+    pub fn matched_route(&self) -> Option<&str> {
+        self.matched_route.as_deref()
+    }
 
-    pub fn parse_request_from_http_request_body(
-        content: String,
-    ) -> Result<Request, RequestParseError> {
-        let (method, path) = {
-            let mut words = content.split(' ');
-            words
-                .next()
-                .ok_or(RequestParseError)
-                .and_then(HttpMethod::try_from)
-                .map(|m| (m, words.next().ok_or(RequestParseError)))
-                .and_then(|(m, pr)| Ok((m, pr?)))
-                .map(|(m, p)| (m, p.to_string()))?
-        };
+    /// Returns the format extension which was stripped from the path to match
+    /// the route, e.g. `json` for a request to `/users/1.json` handled by the
+    /// route `/users/{id}`.
+    ///
+    /// Only extensions enabled with
+    /// [Server::set_format_extensions](crate::Server::set_format_extensions)
+    /// are recognized. Returns None if the path matched without stripping.
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
+    /// Returns a reference to a [HashMap] containing the request headers.
+    ///
+    /// Header names are stored in lowercase. If a header was sent multiple
+    /// times, its values are joined with `, `, or `; ` for `cookie`.
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
 
-        let queries = path
-            .split(|c| c == '?' || c == '&')
+    /// Iterates over the headers as `(name, value)` pairs, parsing them from
+    /// the raw content on the fly.
+    ///
+    /// Unlike [Request::headers], names keep the case in which they were sent
+    /// and repeated headers are yielded one by one. This is cheap for handlers
+    /// which only look at one or two headers.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn agent_route(req: &Request) -> Response {
+    ///     req.header_iter()
+    ///         .find(|(name, _)| name.eq_ignore_ascii_case("user-agent"))
+    ///         .map_or("unknown".into(), |(_, agent)| agent.into())
+    /// }
+    /// ```
+    pub fn header_iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        utils::split_head_and_body(&self.raw_content)
+            .0
+            .lines()
             .skip(1)
-            .map(|key_val| {
-                let mut key_val = key_val.split('=').map(ToString::to_string);
-                (key_val.next(), key_val.next())
-            })
-            .filter(|(key, _)| key.is_some())
-            .map(|(key, val)| (key.unwrap(), val))
-            .collect();
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+    }
 
-        Ok(Request {
-            raw_content: content,
-            path,
-            method,
-            queries,
-            params: HashMap::new(),
-        })
+    /// Returns the value of a header, looking up the name case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
     }
 
-    pub fn request_matches_route(request: &Request, route: &str) -> bool {
-        if request.path_as_str() == route {
+    /// Returns the cookies sent in the `cookie` header by name. If a name
+    /// appears twice, the first value wins, like in [Request::cookie].
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let mut cookies = HashMap::new();
+        for (name, value) in self.cookie_pairs() {
+            cookies
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+        cookies
+    }
+
+    /// Returns the value of a single cookie, without collecting all of them.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn theme_route(req: &Request) -> Response {
+    ///     match req.cookie("theme") {
+    ///         Some("dark") => "<body class=\"dark\"></body>".into(),
+    ///         _ => "<body></body>".into(),
+    ///     }
+    /// }
+    /// ```
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookie_pairs()
+            .find(|(cookie, _)| *cookie == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Iterates over the `name=value` pairs of the `cookie` header. Values
+    /// lose their optional quotes. Several `cookie` headers are joined with
+    /// semicolons, so they are read like one.
+    fn cookie_pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.header("cookie")
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim(), value)
+            })
+    }
+
+    /// Returns false if the client has closed the connection, so long-running
+    /// handlers can stop early, e.g. before generating a large response.
+    ///
+    /// The check peeks at the socket without blocking. It only notices a
+    /// client which closed the connection properly; a client which vanished
+    /// (e.g. by losing power) looks alive until TCP gives up on it. Requests
+    /// which weren't received from a connection are always considered alive.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn report_route(req: &Request) -> Response {
+    ///     let mut report = String::new();
+    ///     for day in 1..=365 {
+    ///         if !req.is_connection_alive() {
+    ///             break;
+    ///         }
+    ///         report.push_str(&format!("<p>day {}</p>", day));
+    ///     }
+    ///     report.into()
+    /// }
+    /// ```
+    pub fn is_connection_alive(&self) -> bool {
+        let Some(connection) = &self.connection else {
+            return true;
+        };
+        if connection.set_nonblocking(true).is_err() {
             return true;
         }
+        let alive = match connection.peek(&mut [0]) {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(err) => matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+            ),
+        };
+        // the flag is shared with the stream the response is written to
+        let _ = connection.set_nonblocking(false);
+        alive
+    }
 
-        let mut req_sub_paths = request
-            .path_as_str()
-            .split('/')
-            .filter(|s| !s.is_empty())
-            .filter_map(|s| s.split('?').next());
-        let mut route_sub_paths = route.split('/').filter(|s| !s.is_empty());
+    /// Returns the tokens of the `connection` header in lowercase, e.g.
+    /// `["keep-alive", "upgrade"]` for `Connection: keep-alive, Upgrade`.
+    pub fn connection_tokens(&self) -> Vec<String> {
+        self.header("connection")
+            .unwrap_or_default()
+            .split(',')
+            .map(|token| token.trim().to_ascii_lowercase())
+            .filter(|token| !token.is_empty())
+            .collect()
+    }
 
-        loop {
-            match (req_sub_paths.next(), route_sub_paths.next()) {
-                (None, None) => break,
-                (Some(_), None) | (None, Some(_)) => return false,
-                (Some(re), Some(ro)) => {
-                    if ro == "*" {
-                        return true;
-                    }
-                    if ro.starts_with('{') {
-                        continue;
-                    }
-                    if re != ro {
-                        return false;
-                    }
-                }
+    /// Returns true if the client wants to keep the connection open after
+    /// the response: HTTP/1.1 connections are persistent unless the
+    /// `connection` header lists `close`, HTTP/1.0 connections only if it
+    /// lists `keep-alive`.
+    pub fn is_keep_alive(&self) -> bool {
+        let tokens = self.connection_tokens();
+        if self.version == "HTTP/1.0" {
+            tokens.iter().any(|token| token == "keep-alive")
+        } else {
+            !tokens.iter().any(|token| token == "close")
+        }
+    }
+
+    /// Returns true if the client asks to upgrade the connection to a
+    /// WebSocket, i.e. the `connection` header lists `upgrade` and the
+    /// `upgrade` header names `websocket`.
+    pub fn is_websocket_upgrade(&self) -> bool {
+        let upgrade = self.header("upgrade").unwrap_or_default();
+        self.connection_tokens()
+            .iter()
+            .any(|token| token == "upgrade")
+            && upgrade
+                .split(',')
+                .any(|protocol| protocol.trim().eq_ignore_ascii_case("websocket"))
+    }
+
+    /// Returns the quality value (between 0 and 1) with which the client's
+    /// `accept-encoding` header accepts the content coding `coding`, e.g.
+    /// `gzip`. Codings which are neither listed nor covered by `*` yield 0,
+    /// except for `identity`, i.e. no coding, which is acceptable unless
+    /// excluded with a quality of 0.
+    pub(crate) fn encoding_quality(&self, coding: &str) -> f32 {
+        let mut wildcard = None;
+        for entry in self
+            .header("accept-encoding")
+            .unwrap_or_default()
+            .split(',')
+        {
+            let mut parts = entry.split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            if name.eq_ignore_ascii_case(coding) {
+                return quality;
+            }
+            if name == "*" {
+                wildcard = Some(quality);
             }
         }
+        match wildcard {
+            Some(quality) => quality,
+            None if coding.eq_ignore_ascii_case("identity") => 1.0,
+            None => 0.0,
+        }
+    }
 
-        true
+    /// Returns true if the `origin` header, or the `referer` header if there
+    /// is no origin, names `expected_host`, compared ignoring ASCII case. A
+    /// port in the header is ignored unless `expected_host` has one as well.
+    /// Requests without either header, or with `origin: null`, are not same
+    /// origin.
+    ///
+    /// Browsers send these headers with cross-site requests, so comparing
+    /// them protects state-changing routes against CSRF.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn transfer_route(req: &Request) -> Response {
+    ///     if !req.is_same_origin("bank.example.com") {
+    ///         return Response::with_status(HttpStatusCode::Forbidden);
+    ///     }
+    ///     "<h1>Done</h1>".into()
+    /// }
+    /// ```
+    pub fn is_same_origin(&self, expected_host: &str) -> bool {
+        let Some(source) = self.header("origin").or_else(|| self.header("referer")) else {
+            return false;
+        };
+        let Some(host) = utils::split_absolute_form(source.trim().to_string()).1 else {
+            return false;
+        };
+        let host = if expected_host.contains(':') {
+            host.as_str()
+        } else {
+            host.rsplit_once(':')
+                .map_or(host.as_str(), |(name, _)| name)
+        };
+        host.eq_ignore_ascii_case(expected_host)
     }
 
-    pub fn set_request_params_according_to_match(request: &mut Request, route: &str) {
-        let request_path_as_string = request.path_as_str().to_string();
-        let req_sub_paths = request_path_as_string
-            .split('/')
-            .filter(|s| !s.is_empty())
-            .filter_map(|s| s.split('?').next());
-        let route_sub_paths = route.split('/').filter(|s| !s.is_empty());
+    /// Returns true if the request is conditional, i.e. carries one of the
+    /// headers `if-none-match`, `if-modified-since`, `if-match` or `if-range`.
+    ///
+    /// Handlers can use this to skip computing validators like an ETag when
+    /// the client has nothing to compare them against.
+    pub fn is_conditional(&self) -> bool {
+        ["if-none-match", "if-modified-since", "if-match", "if-range"]
+            .iter()
+            .any(|name| self.headers.contains_key(*name))
+    }
 
-        req_sub_paths
-            .zip(route_sub_paths)
-            .filter_map(|(req, rou)| {
-                if rou.starts_with('{') {
-                    rou.strip_prefix('{')
-                        .and_then(|s| s.strip_suffix('}'))
-                        .map(|s| (req, s))
-                } else {
-                    None
-                }
-            })
-            .for_each(|(val, key)| {
-                request.params.insert(key.to_string(), val.to_string());
-            });
+    /// Returns true if the `content-type` is `application/json`.
+    pub fn is_json(&self) -> bool {
+        self.content_type_is("application/json")
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct RequestParseError;
+    /// Returns true if the `content-type` is `application/x-www-form-urlencoded`.
+    pub fn is_form(&self) -> bool {
+        self.content_type_is("application/x-www-form-urlencoded")
+    }
 
-impl From<RequestParseError> for Response {
-    fn from(_: RequestParseError) -> Self {
-        let mut resp = Response::default();
-        resp.set_html("RequestParseError");
-        resp.set_status_code(HttpStatusCode::BadRequest);
-        resp
+    /// Returns true if the `content-type` is a `multipart/*` type, e.g.
+    /// `multipart/form-data`.
+    pub fn is_multipart(&self) -> bool {
+        self.media_type()
+            .is_some_and(|media_type| media_type.starts_with("multipart/"))
     }
-}
 
-impl Display for RequestParseError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("RequestParseError")
+    /// Returns true if the media type of the `content-type` header equals
+    /// `media_type`. Parameters like `charset` are ignored, and so is the case.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn import_route(req: &Request) -> Response {
+    ///     if req.content_type_is("text/csv") {
+    ///         "imported".into()
+    ///     } else {
+    ///         Response::with_status(HttpStatusCode::BadRequest)
+    ///     }
+    /// }
+    /// ```
+    pub fn content_type_is(&self, media_type: &str) -> bool {
+        self.media_type()
+            .is_some_and(|actual| actual.eq_ignore_ascii_case(media_type.trim()))
     }
-}
 
-impl std::error::Error for RequestParseError {}
+    /// Parses the body into a `T` with the parser registered for the media
+    /// type of the request and `T`, see
+    /// [Server::register_body_parser](crate::Server::register_body_parser).
+    /// This way, a handler can accept several formats without branching on
+    /// the `content-type`. Bodies declaring a charset other than UTF-8 are
+    /// rejected before reaching the parser. The error converts into a response.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// struct Note {
+    ///     title: String,
+    /// }
+    ///
+    /// fn create_note_route(req: &Request) -> Response {
+    ///     match req.parse_body::<Note>() {
+    ///         Ok(note) => format!("<h1>{}</h1>", note.title).into(),
+    ///         Err(err) => err.into(),
+    ///     }
+    /// }
+    /// ```
+    pub fn parse_body<T: 'static>(&self) -> Result<T, BodyError> {
+        if let Some(charset) = self.unsupported_charset() {
+            return Err(BodyError::UnsupportedCharset(charset));
+        }
+        match &self.body_parsers {
+            Some(parsers) => parsers.parse(self.media_type(), self),
+            None => Err(BodyError::UnsupportedMediaType(self.media_type())),
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Parses a JSON body into a `T` with the parser registered for
+    /// `application/json` and `T`, see
+    /// [Server::register_body_parser](crate::Server::register_body_parser).
+    /// The request has to declare `content-type: application/json`, and no
+    /// charset other than UTF-8. The error converts into a response.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// struct User {
+    ///     name: String,
+    /// }
+    ///
+    /// fn create_user_route(req: &Request) -> Response {
+    ///     match req.json::<User>() {
+    ///         Ok(user) => format!("created {}", user.name).into(),
+    ///         Err(err) => err.into(),
+    ///     }
+    /// }
+    /// ```
+    pub fn json<T: 'static>(&self) -> Result<T, JsonError> {
+        if !self.is_json() {
+            return Err(JsonError::NotJson(self.media_type()));
+        }
+        self.parse_json()
+    }
 
-    fn create_mock_request(method: HttpMethod, path: &str) -> (Request, String) {
-        let string = format!(
-            r"{:?} {} HTTP/1.1
-User-Agent: Mozilla/4.0 (compatible; MSIE5.01; Windows NT)
-Host: www.loremipsum.com
-Accept-Language: en-us
-Accept-Encoding: gzip, deflate
-Connection: Keep-Alive",
-            method, path
-        );
-        (
-            utils::parse_request_from_http_request_body(string.clone()).unwrap(),
-            string,
-        )
+    fn parse_json<T: 'static>(&self) -> Result<T, JsonError> {
+        if let Some(charset) = self.unsupported_charset() {
+            return Err(JsonError::UnsupportedCharset(charset));
+        }
+        let parsers = self.body_parsers.as_ref().ok_or(JsonError::NoParser)?;
+        match parsers.parse(Some("application/json".to_string()), self) {
+            Ok(value) => Ok(value),
+            Err(BodyError::Invalid(reason)) => Err(JsonError::Invalid(reason)),
+            Err(_) => Err(JsonError::NoParser),
+        }
     }
 
-    #[test]
-    fn test_parsing_request() {
-        let (request, request_str) =
-            create_mock_request(HttpMethod::GET, "/path?query2&query1=val");
-        assert_eq!(request.raw_content, request_str);
-        assert_eq!(request.method, HttpMethod::GET);
-        assert_eq!(request.queries.len(), 2);
-        assert_eq!(
-            *request.queries.get("query1").unwrap(),
-            Some("val".to_string())
-        );
-        assert_eq!(*request.queries.get("query2").unwrap(), None);
+    /// Parses the body like [Request::json], but whatever media type the
+    /// `content-type` declares. This suits permissive endpoints like webhooks,
+    /// whose clients may send JSON as `text/plain` or without a
+    /// `content-type`; [Request::json] and [Request::parse_body] stay strict.
+    /// A charset other than UTF-8 is still rejected.
+    ///
+    /// Like [Request::json], this needs a parser for `application/json` and
+    /// `T` registered through
+    /// [Server::register_body_parser](crate::Server::register_body_parser),
+    /// otherwise [JsonError::NoParser] is returned.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// struct Event {
+    ///     kind: String,
+    /// }
+    ///
+    /// fn webhook_route(req: &Request) -> Response {
+    ///     match req.json_lenient::<Event>() {
+    ///         Ok(event) => format!("got {}", event.kind).into(),
+    ///         Err(err) => err.into(),
+    ///     }
+    /// }
+    /// ```
+    pub fn json_lenient<T: 'static>(&self) -> Result<T, JsonError> {
+        self.parse_json()
     }
 
-    #[test]
-    fn test_request_matches() {
-        let (request, _) = create_mock_request(HttpMethod::GET, "/test/path");
-        assert!(utils::request_matches_route(&request, "*"));
-        assert!(utils::request_matches_route(&request, "/test/*"));
-        assert!(utils::request_matches_route(&request, "/test/path"));
-        assert!(utils::request_matches_route(&request, "/test/path/"));
-        assert!(!utils::request_matches_route(&request, "/some-other-path"));
+    /// Returns the lowercased media type of the `content-type` header,
+    /// without any parameters like `charset`.
+    fn media_type(&self) -> Option<String> {
+        self.header("content-type").map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+    }
 
-        let (request, _) = create_mock_request(HttpMethod::GET, "/test/path?some_param=value");
-        assert!(utils::request_matches_route(&request, "*"));
-        assert!(utils::request_matches_route(&request, "/test/path"));
+    /// Returns the charset of the `content-type` if it is one the body can't
+    /// be decoded with, i.e. not UTF-8 or its subset US-ASCII.
+    fn unsupported_charset(&self) -> Option<String> {
+        self.charset()
+            .filter(|charset| !matches!(charset.as_str(), "utf-8" | "utf8" | "us-ascii"))
+    }
 
-        let (request, _) = create_mock_request(HttpMethod::GET, "/greet/john");
-        assert!(utils::request_matches_route(&request, "*"));
-        assert!(utils::request_matches_route(&request, "/greet/{name}/"));
-        assert!(!utils::request_matches_route(&request, "/greet"));
-        assert!(!utils::request_matches_route(&request, "/some-other-path"));
+    /// Returns the lowercased `charset` parameter of the `content-type`
+    /// header, without quotes.
+    fn charset(&self) -> Option<String> {
+        self.header("content-type")?
+            .split(';')
+            .skip(1)
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+            .map(|(_, value)| value.trim().trim_matches('"').to_ascii_lowercase())
     }
 
-    #[test]
+    /// Returns the fields of an `application/x-www-form-urlencoded` body.
+    /// Keys and values are percent-decoded like [query parameters](Request::queries),
+    /// with `+` standing for a space. The map is empty if the request declares
+    /// another `content-type`; a body without one is parsed as a form.
+    ///
+    /// If a key occurs multiple times, the last value wins. Keys written in
+    /// array syntax (`items[]=a&items[]=b`) are kept as they are, use
+    /// [Request::form_array] to collect all of their values.
+    pub fn form(&self) -> HashMap<String, String> {
+        self.form_pairs().collect()
+    }
+
+    /// Returns all values of a form field submitted in array syntax, in the
+    /// order in which they were sent.
+    ///
+    /// A body of `items[]=a&items[]=b` yields `["a", "b"]` for
+    /// `form_array("items")`. Plain `items=c` fields are collected as well.
+    pub fn form_array(&self, name: &str) -> Vec<String> {
+        self.form_pairs()
+            .filter(|(key, _)| key.strip_suffix("[]").unwrap_or(key) == name)
+            .map(|(_, val)| val)
+            .collect()
+    }
+
+    /// Looks up a form field, ignoring the (ASCII) case of its name.
+    ///
+    /// If several fields match, the last one wins, just like in [Request::form].
+    pub fn form_field_ignore_case(&self, name: &str) -> Option<String> {
+        self.form_pairs()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, val)| val)
+            .last()
+    }
+
+    /// Returns the fields of either a form body or a JSON object body, as
+    /// told by the `content-type`, so a handler can accept both in one code
+    /// path. See [Request::form] for forms.
+    ///
+    /// The values of a JSON object have to be strings, numbers, booleans or
+    /// null: strings are unescaped, numbers and booleans kept as written and
+    /// null fields left out. Objects with nested objects or arrays aren't
+    /// flattened, the map is empty for them, as it is for invalid JSON.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn signup_route(req: &Request) -> Response {
+    ///     match req.params_from_body().get("name") {
+    ///         Some(name) => format!("<h1>Welcome, {}</h1>", name).into(),
+    ///         None => Response::with_status(HttpStatusCode::BadRequest),
+    ///     }
+    /// }
+    /// ```
+    pub fn params_from_body(&self) -> HashMap<String, String> {
+        if self.media_type().as_deref() == Some("application/json") {
+            crate::json::parse_flat_object(self.body()).unwrap_or_default()
+        } else {
+            self.form()
+        }
+    }
+
+    fn form_pairs(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        let is_form = self
+            .media_type()
+            .is_none_or(|media_type| media_type == "application/x-www-form-urlencoded");
+        let body = if is_form { self.body() } else { "" };
+        body.split('&')
+            .filter(|key_val| !key_val.is_empty())
+            .map(|key_val| key_val.split_once('=').unwrap_or((key_val, "")))
+            .map(|(key, val)| {
+                (
+                    utils::percent_decode(key, true),
+                    utils::percent_decode(val, true),
+                )
+            })
+    }
+
+    /// Returns the body as text, i.e. everything after the blank line
+    /// terminating the headers, or an empty string if there is no body.
+    /// Bytes which aren't valid UTF-8 are replaced, see [Request::raw_body]
+    /// for the exact bytes.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn note_route(req: &Request) -> Response {
+    ///     format!("{} characters", req.body_str().chars().count()).into()
+    /// }
+    /// ```
+    pub fn body_str(&self) -> &str {
+        self.body()
+    }
+
+    /// Returns everything after the blank line terminating the headers.
+    pub(crate) fn body(&self) -> &str {
+        utils::split_head_and_body(&self.raw_content).1
+    }
+}
+
+pub mod utils {
+    use super::*;
+
+    pub fn parse_request_from_http_request_body(
+        content: String,
+    ) -> Result<Request, RequestParseError> {
+        let (method, path, version) = {
+            let request_line = content.lines().next().unwrap_or_default();
+            let words: Vec<&str> = request_line.split(' ').collect();
+            let [method, path, version] = words[..] else {
+                return Err(RequestParseError::MalformedRequestLine);
+            };
+            let method = HttpMethod::try_from(method)?;
+            if path.is_empty() {
+                return Err(RequestParseError::MalformedRequestLine);
+            }
+            if !is_http_version(version) {
+                return Err(RequestParseError::InvalidVersion(version.to_string()));
+            }
+            (method, path.to_string(), version.to_string())
+        };
+        let (path, target_host) = split_absolute_form(path);
+
+        // only the first `?` starts the query, later ones belong to values
+        let (path_only, query) = match path.split_once('?') {
+            Some((path_only, query)) => (path_only.to_string(), Some(query.to_string())),
+            None => (path.clone(), None),
+        };
+        let queries = query
+            .iter()
+            .flat_map(|query| query.split('&'))
+            // only the first `=` ends the key, e.g. of base64 padded values
+            .map(|key_val| match key_val.split_once('=') {
+                Some((key, val)) => (percent_decode(key, true), Some(percent_decode(val, true))),
+                None => (percent_decode(key_val, true), None),
+            })
+            .collect();
+
+        let mut request = Request {
+            raw_content: content,
+            target: path,
+            path: path_only,
+            query,
+            method,
+            version,
+            queries,
+            params: HashMap::new(),
+            headers: HashMap::new(),
+            format: None,
+            raw_body: None,
+            connection: None,
+            body_parsers: None,
+            matched_route: None,
+        };
+        let mut headers: HashMap<String, String> = HashMap::new();
+        request.header_iter().for_each(|(name, value)| {
+            let name = name.to_ascii_lowercase();
+            // cookie values may contain commas, so cookies are joined like
+            // in a single header
+            let separator = if name == "cookie" { "; " } else { ", " };
+            headers
+                .entry(name)
+                .and_modify(|existing| {
+                    existing.push_str(separator);
+                    existing.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        });
+        // the authority of an absolute-form target takes precedence
+        if let Some(host) = target_host {
+            headers.insert("host".to_string(), host);
+        }
+        request.headers = headers;
+        Ok(request)
+    }
+
+    /// Returns true for versions like `HTTP/1.1`.
+    fn is_http_version(version: &str) -> bool {
+        let digits = version.strip_prefix("HTTP/").map(str::as_bytes);
+        matches!(digits, Some([major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit())
+    }
+
+    /// Splits a request target in absolute form, as sent to proxies (e.g.
+    /// `http://example.com/a?b=1`), into the path with the query (`/a?b=1`)
+    /// and the host (`example.com`). Other targets are returned unchanged.
+    pub fn split_absolute_form(target: String) -> (String, Option<String>) {
+        let scheme_len = ["http://", "https://"]
+            .iter()
+            .find(|scheme| {
+                target
+                    .get(..scheme.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+            })
+            .map(|scheme| scheme.len());
+        let Some(scheme_len) = scheme_len else {
+            return (target, None);
+        };
+        let rest = &target[scheme_len..];
+        let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let authority = &rest[..authority_end];
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let path = match &rest[authority_end..] {
+            "" => "/".to_string(),
+            path if path.starts_with('?') => format!("/{}", path),
+            path => path.to_string(),
+        };
+        (path, Some(host.to_string()))
+    }
+
+    /// Encodes all bytes of `raw` but unreserved characters (letters, digits
+    /// and `-._~`) as `%XX`, so it can be used as a path segment.
+    pub fn percent_encode(raw: &str) -> String {
+        raw.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{:02X}", b),
+            })
+            .collect()
+    }
+
+    /// Decodes `%XX` escapes, and `+` as a space if `plus_as_space` is set (as
+    /// in query strings). Malformed escapes like `%ZZ` are kept as they are,
+    /// invalid UTF-8 is replaced.
+    pub fn percent_decode(encoded: &str, plus_as_space: bool) -> String {
+        let bytes = encoded.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let escaped = (bytes[i] == b'%')
+                .then(|| bytes.get(i + 1..i + 3))
+                .flatten()
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            match (bytes[i], escaped) {
+                (_, Some(byte)) => {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+                (b'+', None) if plus_as_space => decoded.push(b' '),
+                (byte, None) => decoded.push(byte),
+            }
+            i += 1;
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
+    }
+
+    /// Parses a request from the bytes received from the client. Invalid UTF-8
+    /// is replaced in [Request::raw_content], but kept in [Request::raw_body].
+    pub fn parse_request_from_bytes(content: Vec<u8>) -> Result<Request, RequestParseError> {
+        match String::from_utf8(content) {
+            Ok(content) => parse_request_from_http_request_body(content),
+            Err(err) => {
+                let content = err.into_bytes();
+                let mut request =
+                    parse_request_from_http_request_body(String::from_utf8_lossy(&content).into())?;
+                let body_start = [
+                    find(&content, b"\r\n\r\n").map(|i| i + 4),
+                    find(&content, b"\n\n").map(|i| i + 2),
+                ]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(content.len());
+                request.raw_body = Some(content[body_start..].to_vec());
+                Ok(request)
+            }
+        }
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|w| w == needle)
+    }
+
+    /// Splits raw request content into the head (request line and headers)
+    /// and the body. If there is no blank line, the body is empty.
+    pub fn split_head_and_body(content: &str) -> (&str, &str) {
+        [
+            content.find("\r\n\r\n").map(|i| (i, 4)),
+            content.find("\n\n").map(|i| (i, 2)),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|(i, _)| *i)
+        .map(|(i, len)| (&content[..i], &content[i + len..]))
+        .unwrap_or((content, ""))
+    }
+
+    #[cfg(test)]
+    pub fn request_matches_route(request: &Request, route: &str) -> bool {
+        path_matches_route(request.path_as_str(), route)
+    }
+
+    /// Returns the non-empty segments of `path`.
+    pub fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+        path.split('/').filter(|s| !s.is_empty())
+    }
+
+    /// How leniently [path_matches_route_with] compares paths.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct MatchOptions {
+        /// Compares literal segments ignoring ASCII case.
+        pub case_insensitive: bool,
+        /// Requires the path to end with a slash exactly if the route does.
+        pub strict_trailing_slash: bool,
+    }
+
+    #[cfg(test)]
+    pub fn path_matches_route(path: &str, route: &str) -> bool {
+        path_matches_route_with(path, route, MatchOptions::default())
+    }
+
+    /// Compares `path` with `route` segment by segment. Literal segments have
+    /// to be equal, a `{param}` or `*` matches any single segment, and only a
+    /// trailing `*` or `{*splat}` matches a different number of segments.
+    /// `options` relax or tighten the comparison.
+    pub fn path_matches_route_with(path: &str, route: &str, options: MatchOptions) -> bool {
+        if options.strict_trailing_slash {
+            let ends_with_wildcard = route
+                .rsplit('/')
+                .find(|s| !s.is_empty())
+                .is_some_and(|last| last == "*" || splat_name(last).is_some());
+            if !ends_with_wildcard && path.ends_with('/') != route.ends_with('/') {
+                return false;
+            }
+        }
+        let mut req_sub_paths = path_segments(path);
+        let mut route_sub_paths = route.split('/').filter(|s| !s.is_empty()).peekable();
+
+        loop {
+            match (req_sub_paths.next(), route_sub_paths.next()) {
+                (None, None) => return true,
+                // a splat also matches an empty remainder
+                (None, Some(ro)) => return splat_name(ro).is_some(),
+                (Some(_), None) => return false,
+                (Some(re), Some(ro)) => {
+                    let is_wildcard = ro == "*" || splat_name(ro).is_some();
+                    if is_wildcard && route_sub_paths.peek().is_none() {
+                        return true;
+                    }
+                    let is_param = ro.starts_with('{') && ro.ends_with('}');
+                    let literal_matches = if options.case_insensitive {
+                        re.eq_ignore_ascii_case(ro)
+                    } else {
+                        re == ro
+                    };
+                    if !is_wildcard && !is_param && !literal_matches {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_request_params_according_to_match(request: &mut Request, route: &str) {
+        let path = request.path_as_str().to_string();
+        set_request_params_from_path(request, &path, route);
+    }
+
+    /// Like [set_request_params_according_to_match], but takes the params from
+    /// `path` instead of the path of the request.
+    pub fn set_request_params_from_path(request: &mut Request, path: &str, route: &str) {
+        let req_sub_paths: Vec<&str> = path_segments(path).collect();
+        let route_sub_paths: Vec<&str> = route.split('/').filter(|s| !s.is_empty()).collect();
+        request.matched_route = Some(route.to_string());
+
+        if let Some((last, _)) = route_sub_paths.split_last() {
+            if let Some(name) = splat_name(last) {
+                let start = (route_sub_paths.len() - 1).min(req_sub_paths.len());
+                let rest = req_sub_paths[start..]
+                    .iter()
+                    .map(|segment| percent_decode(segment, false))
+                    .collect::<Vec<_>>()
+                    .join("/");
+                request.params.insert(name.to_string(), rest);
+            }
+        }
+        req_sub_paths
+            .into_iter()
+            .zip(route_sub_paths)
+            .filter(|(_, rou)| splat_name(rou).is_none())
+            .filter_map(|(req, rou)| {
+                if rou.starts_with('{') {
+                    rou.strip_prefix('{')
+                        .and_then(|s| s.strip_suffix('}'))
+                        .map(|s| (req, s))
+                } else {
+                    None
+                }
+            })
+            .for_each(|(val, key)| {
+                // a `+` only stands for a space in the query
+                request
+                    .params
+                    .insert(key.to_string(), percent_decode(val, false));
+            });
+    }
+
+    /// Returns the name of a `{*name}` segment, which captures the rest of
+    /// the path.
+    pub fn splat_name(segment: &str) -> Option<&str> {
+        segment.strip_prefix("{*").and_then(|s| s.strip_suffix('}'))
+    }
+
+    /// Ranks how specific a route is, for choosing between several routes
+    /// matching a path: segment by segment, a literal beats a `{param}`,
+    /// which beats a wildcard. Compared lexicographically, a longer route
+    /// wins over its prefix.
+    pub fn route_specificity(route: &str) -> Vec<u8> {
+        route
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|segment| {
+                if segment == "*" || splat_name(segment).is_some() {
+                    0
+                } else if segment.starts_with('{') {
+                    1
+                } else {
+                    2
+                }
+            })
+            .collect()
+    }
+
+    /// Splits a known format extension off the last segment of `path`. Returns
+    /// the path without the extension and the extension, e.g.
+    /// `("/users/1", "json")` for `/users/1.json`.
+    pub fn strip_format_extension(path: &str, extensions: &[String]) -> Option<(String, String)> {
+        let (stripped, extension) = path.rsplit_once('.')?;
+        if extension.contains('/') || stripped.ends_with('/') {
+            return None;
+        }
+        extensions
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(extension))
+            .map(|known| (stripped.to_string(), known.clone()))
+    }
+
+    /// Removes the `base` prefix from the path of the request. Returns false
+    /// if the path isn't below `base`.
+    pub fn strip_base_path(request: &mut Request, base: &str) -> bool {
+        let rest = match request.path.strip_prefix(base) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+            _ => return false,
+        };
+        request.path = if rest.is_empty() { "/" } else { rest }.to_string();
+        true
+    }
+
+    /// Returns the path of the request followed by its query, if any.
+    pub fn path_and_query(request: &Request) -> String {
+        match request.raw_query() {
+            Some(query) => format!("{}?{}", request.path_as_str(), query),
+            None => request.path_as_str().to_string(),
+        }
+    }
+
+    pub fn set_request_format(request: &mut Request, format: String) {
+        request.format = Some(format);
+    }
+
+    pub fn set_request_connection(request: &mut Request, connection: TcpStream) {
+        request.connection = Some(Arc::new(connection));
+    }
+
+    pub fn set_request_body_parsers(request: &mut Request, parsers: Arc<BodyParsers>) {
+        request.body_parsers = Some(parsers);
+    }
+}
+
+/// Why a request couldn't be parsed. Converts into a `400 Bad Request`
+/// response naming the reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestParseError {
+    /// The request line isn't a method, a target and a version separated by
+    /// single spaces.
+    MalformedRequestLine,
+    /// The method is neither a known one nor a valid extension method.
+    UnknownMethod(String),
+    /// The version isn't of the form `HTTP/1.1`.
+    InvalidVersion(String),
+}
+
+impl From<RequestParseError> for Response {
+    fn from(err: RequestParseError) -> Self {
+        // the reason quotes the request, so it isn't sent as HTML
+        Response::with_status(HttpStatusCode::BadRequest)
+            .with_header(HttpHeaderName::ContentType, "text/plain; charset=utf-8")
+            .with_body(err)
+    }
+}
+
+impl Display for RequestParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestParseError::MalformedRequestLine => f.write_str("malformed request line"),
+            RequestParseError::UnknownMethod(method) => write!(f, "unknown method {:?}", method),
+            RequestParseError::InvalidVersion(version) => {
+                write!(f, "invalid HTTP version {:?}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestParseError {}
+
+/// The error for a query parameter which is missing or can't be parsed, see
+/// [Request::query_as]. Converts into a `400 Bad Request` response.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    Missing(String),
+    Invalid(String),
+}
+
+impl From<QueryError> for Response {
+    fn from(err: QueryError) -> Self {
+        // not HTML, so nothing in the message is rendered
+        Response::with_status(HttpStatusCode::BadRequest)
+            .with_header(HttpHeaderName::ContentType, "text/plain; charset=utf-8")
+            .with_body(err)
+    }
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Missing(name) => write!(f, "missing query parameter {}", name),
+            QueryError::Invalid(name) => write!(f, "invalid query parameter {}", name),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_mock_request_with_headers(
+        method: HttpMethod,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Request {
+        let headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect();
+        let string = format!(
+            "{} {} HTTP/1.1\r\nHost: www.loremipsum.com\r\n{}content-length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        );
+        utils::parse_request_from_http_request_body(string).unwrap()
+    }
+
+    fn create_mock_request_with_body(method: HttpMethod, path: &str, body: &str) -> Request {
+        create_mock_request_with_headers(method, path, &[], body)
+    }
+
+    fn create_mock_request(method: HttpMethod, path: &str) -> (Request, String) {
+        let string = format!(
+            r"{} {} HTTP/1.1
+User-Agent: Mozilla/4.0 (compatible; MSIE5.01; Windows NT)
+Host: www.loremipsum.com
+Accept-Language: en-us
+Accept-Encoding: gzip, deflate
+Connection: Keep-Alive",
+            method, path
+        );
+        (
+            utils::parse_request_from_http_request_body(string.clone()).unwrap(),
+            string,
+        )
+    }
+
+    #[test]
+    fn test_parsing_request() {
+        let (request, request_str) =
+            create_mock_request(HttpMethod::GET, "/path?query2&query1=val");
+        assert_eq!(request.raw_content, request_str);
+        assert_eq!(request.method, HttpMethod::GET);
+        assert_eq!(request.queries.len(), 2);
+        assert_eq!(
+            *request.queries.get("query1").unwrap(),
+            Some("val".to_string())
+        );
+        assert_eq!(*request.queries.get("query2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_parsing_post_request() {
+        let request =
+            utils::parse_request_from_http_request_body("POST /x HTTP/1.1".to_string()).unwrap();
+        assert_eq!(request.method(), HttpMethod::POST);
+        assert_eq!(request.path_as_str(), "/x");
+        assert_eq!(HttpMethod::try_from("POST"), Ok(HttpMethod::POST));
+        assert_eq!(
+            HttpMethod::try_from("post"),
+            Err(RequestParseError::UnknownMethod("post".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parsing_version() {
+        let request =
+            utils::parse_request_from_http_request_body("GET / HTTP/1.1\r\n\r\n".to_string())
+                .unwrap();
+        assert_eq!(request.version(), "HTTP/1.1");
+        let request =
+            utils::parse_request_from_http_request_body("GET / HTTP/1.0".to_string()).unwrap();
+        assert_eq!(request.version(), "HTTP/1.0");
+
+        for line in ["GET /", "GET / HTTP/1.1 x", "GET  / HTTP/1.1", ""] {
+            let result = utils::parse_request_from_http_request_body(line.to_string());
+            assert_eq!(
+                result.err(),
+                Some(RequestParseError::MalformedRequestLine),
+                "{}",
+                line
+            );
+        }
+        for version in ["HTTP/x", "http/1.1", "HTTP/1.10"] {
+            let line = format!("GET / {}", version);
+            let result = utils::parse_request_from_http_request_body(line);
+            assert_eq!(
+                result.err(),
+                Some(RequestParseError::InvalidVersion(version.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_parsing_extension_method() {
+        let request =
+            utils::parse_request_from_http_request_body("PURGE /x HTTP/1.1".to_string()).unwrap();
+        assert_eq!(request.method(), HttpMethod::Other("PURGE".to_string()));
+        assert_eq!(request.method().to_string(), "PURGE");
+        assert_eq!(HttpMethod::GET.to_string(), "GET");
+        assert_eq!(
+            HttpMethod::try_from("M-SEARCH"),
+            Ok(HttpMethod::Other("M-SEARCH".to_string()))
+        );
+        assert!(HttpMethod::try_from("").is_err());
+        assert_eq!(
+            HttpMethod::try_from("GE T"),
+            Err(RequestParseError::UnknownMethod("GE T".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_error_reason() {
+        let err =
+            utils::parse_request_from_http_request_body("get / HTTP/1.1".to_string()).unwrap_err();
+        assert_eq!(err, RequestParseError::UnknownMethod("get".to_string()));
+        assert_eq!(err.to_string(), r#"unknown method "get""#);
+        let response = Response::from(err);
+        assert_eq!(response.status_code(), HttpStatusCode::BadRequest);
+        assert_eq!(
+            response.header(HttpHeaderName::ContentType),
+            Some("text/plain; charset=utf-8")
+        );
+        let mut written = Vec::new();
+        crate::response::write_response(response, &mut written).unwrap();
+        assert!(String::from_utf8(written)
+            .unwrap()
+            .ends_with("\r\n\r\nunknown method \"get\""));
+
+        let err =
+            utils::parse_request_from_http_request_body("GET / HTTP/11".to_string()).unwrap_err();
+        assert_eq!(err.to_string(), r#"invalid HTTP version "HTTP/11""#);
+        let err = utils::parse_request_from_http_request_body("GET /".to_string()).unwrap_err();
+        assert_eq!(err.to_string(), "malformed request line");
+    }
+
+    #[test]
+    fn test_parsing_absolute_form() {
+        let request = utils::parse_request_from_http_request_body(
+            "GET http://example.com/a?b=1 HTTP/1.1\r\nHost: proxy.local\r\n\r\n".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.path_as_str(), "/a");
+        assert_eq!(request.full_target(), "/a?b=1");
+        assert_eq!(request.raw_query(), Some("b=1"));
+        assert_eq!(request.queries().get("b"), Some(&Some("1".to_string())));
+        assert_eq!(request.header("host"), Some("example.com"));
+
+        let request = utils::parse_request_from_http_request_body(
+            "GET HTTPS://user@example.com:8443 HTTP/1.1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.path_as_str(), "/");
+        assert_eq!(request.header("host"), Some("example.com:8443"));
+
+        let request =
+            utils::parse_request_from_http_request_body("GET /http://x HTTP/1.1".to_string())
+                .unwrap();
+        assert_eq!(request.path_as_str(), "/http://x");
+        assert_eq!(request.header("host"), None);
+    }
+
+    #[test]
+    fn test_cookies() {
+        let request = utils::parse_request_from_http_request_body(
+            "GET / HTTP/1.1\r\nCookie: a=1; b=2; quoted=\"x y\"\r\nCookie: a=3\r\n\r\n".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.cookie("b"), Some("2"));
+        assert_eq!(request.cookie("a"), Some("1"));
+        assert_eq!(request.cookie("quoted"), Some("x y"));
+        assert_eq!(request.cookie("c"), None);
+        assert_eq!(request.cookies().len(), 3);
+        assert_eq!(request.cookies()["a"], "1");
+
+        let request = utils::parse_request_from_http_request_body(
+            "GET / HTTP/1.1\r\nCookie: token=ab=cd;  spaced = v \r\n\r\n".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.cookie("token"), Some("ab=cd"));
+        assert_eq!(request.cookie("spaced"), Some("v"));
+        assert_eq!(
+            request.cookies(),
+            HashMap::from([
+                ("token".to_string(), "ab=cd".to_string()),
+                ("spaced".to_string(), "v".to_string()),
+            ])
+        );
+
+        let request = utils::parse_request_from_http_request_body(
+            "GET / HTTP/1.1\r\nCookie: list=a,b; next=1\r\nCookie: last=x,y\r\n\r\n".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.cookie("list"), Some("a,b"));
+        assert_eq!(request.cookie("next"), Some("1"));
+        assert_eq!(request.cookie("last"), Some("x,y"));
+        assert_eq!(request.header("cookie"), Some("list=a,b; next=1; last=x,y"));
+
+        let request =
+            utils::parse_request_from_http_request_body("GET / HTTP/1.1".to_string()).unwrap();
+        assert_eq!(request.cookie("a"), None);
+        assert!(request.cookies().is_empty());
+    }
+
+    #[test]
+    fn test_parsing_headers() {
+        let (request, _) = create_mock_request(HttpMethod::GET, "/");
+        assert_eq!(request.headers().len(), 5);
+        assert_eq!(request.header("host"), Some("www.loremipsum.com"));
+        assert_eq!(request.header("Accept-Language"), Some("en-us"));
+        assert_eq!(request.header("ACCEPT-ENCODING"), Some("gzip, deflate"));
+        assert_eq!(request.header("missing"), None);
+
+        let request = create_mock_request_with_headers(
+            HttpMethod::GET,
+            "/",
+            &[("Accept", "text/html"), ("accept", "application/json")],
+            "",
+        );
+        assert_eq!(
+            request.header("accept"),
+            Some("text/html, application/json")
+        );
+    }
+
+    #[test]
+    fn test_is_conditional() {
+        let request = create_mock_request_with_headers(
+            HttpMethod::GET,
+            "/",
+            &[("If-None-Match", "\"abc\"")],
+            "",
+        );
+        assert!(request.is_conditional());
+        let request =
+            create_mock_request_with_headers(HttpMethod::GET, "/", &[("if-range", "\"abc\"")], "");
+        assert!(request.is_conditional());
+        let (request, _) = create_mock_request(HttpMethod::GET, "/");
+        assert!(!request.is_conditional());
+    }
+
+    #[test]
+    fn test_is_same_origin() {
+        let request = |headers: &[(&str, &str)]| {
+            create_mock_request_with_headers(HttpMethod::POST, "/transfer", headers, "")
+        };
+        let same = request(&[("Origin", "https://example.com")]);
+        assert!(same.is_same_origin("example.com"));
+        assert!(same.is_same_origin("EXAMPLE.com"));
+        assert!(!same.is_same_origin("example.com:8443"));
+        assert!(!request(&[("Origin", "https://evil.com")]).is_same_origin("example.com"));
+        assert!(
+            !request(&[("Origin", "https://example.com.evil.com")]).is_same_origin("example.com")
+        );
+
+        let port = request(&[("Origin", "http://example.com:8080")]);
+        assert!(port.is_same_origin("example.com"));
+        assert!(port.is_same_origin("example.com:8080"));
+        let referer = request(&[("Referer", "https://example.com/form?x=1")]);
+        assert!(referer.is_same_origin("example.com"));
+        // the origin takes precedence over the referer
+        let both = request(&[("Origin", "null"), ("Referer", "https://example.com/")]);
+        assert!(!both.is_same_origin("example.com"));
+        assert!(!request(&[]).is_same_origin("example.com"));
+    }
+
+    #[test]
+    fn test_header_iter() {
+        let (request, _) = create_mock_request(HttpMethod::GET, "/");
+        let headers: Vec<_> = request.header_iter().collect();
+        assert_eq!(headers.len(), 5);
+        assert_eq!(
+            headers[0],
+            (
+                "User-Agent",
+                "Mozilla/4.0 (compatible; MSIE5.01; Windows NT)"
+            )
+        );
+        assert_eq!(
+            request
+                .header_iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("accept-language")),
+            Some(("Accept-Language", "en-us"))
+        );
+
+        let request = create_mock_request_with_body(HttpMethod::PUT, "/", "not: a header");
+        assert!(request.header_iter().all(|(name, _)| name != "not"));
+    }
+
+    #[test]
+    fn test_content_type_predicates() {
+        let request = create_mock_request_with_headers(
+            HttpMethod::PUT,
+            "/",
+            &[("Content-Type", "application/json; charset=utf-8")],
+            "{}",
+        );
+        assert!(request.is_json());
+        assert!(!request.is_form());
+        assert!(!request.is_multipart());
+
+        let request = create_mock_request_with_headers(
+            HttpMethod::PUT,
+            "/",
+            &[("content-type", "Application/X-WWW-Form-Urlencoded")],
+            "a=b",
+        );
+        assert!(request.is_form());
+        assert!(!request.is_json());
+
+        let request = create_mock_request_with_headers(
+            HttpMethod::PUT,
+            "/",
+            &[("content-type", "multipart/form-data; boundary=xyz")],
+            "",
+        );
+        assert!(request.is_multipart());
+
+        let (request, _) = create_mock_request(HttpMethod::GET, "/");
+        assert!(!request.is_json() && !request.is_form() && !request.is_multipart());
+    }
+
+    #[test]
+    fn test_content_type_is() {
+        let request = create_mock_request_with_headers(
+            HttpMethod::PUT,
+            "/",
+            &[("content-type", "text/csv; charset=utf-8")],
+            "a,b",
+        );
+        assert!(request.content_type_is("text/csv"));
+        assert!(request.content_type_is("Text/CSV"));
+        assert!(!request.content_type_is("text/csv; charset=utf-8"));
+        assert!(!request.content_type_is("text/plain"));
+        assert!(!request.content_type_is("text"));
+
+        let (request, _) = create_mock_request(HttpMethod::GET, "/");
+        assert!(!request.content_type_is("text/csv"));
+    }
+
+    #[test]
+    fn test_request_matches() {
+        let (request, _) = create_mock_request(HttpMethod::GET, "/test/path");
+        assert!(utils::request_matches_route(&request, "*"));
+        assert!(utils::request_matches_route(&request, "/test/*"));
+        assert!(utils::request_matches_route(&request, "/test/path"));
+        assert!(utils::request_matches_route(&request, "/test/path/"));
+        assert!(!utils::request_matches_route(&request, "/some-other-path"));
+
+        let (request, _) = create_mock_request(HttpMethod::GET, "/test/path?some_param=value");
+        assert!(utils::request_matches_route(&request, "*"));
+        assert!(utils::request_matches_route(&request, "/test/path"));
+
+        let (request, _) = create_mock_request(HttpMethod::GET, "/greet/john");
+        assert!(utils::request_matches_route(&request, "*"));
+        assert!(utils::request_matches_route(&request, "/greet/{name}/"));
+        assert!(!utils::request_matches_route(&request, "/greet"));
+        assert!(!utils::request_matches_route(&request, "/some-other-path"));
+    }
+
+    #[test]
+    fn test_body_str() {
+        let request = create_mock_request_with_body(HttpMethod::POST, "/", "hello=world");
+        assert_eq!(request.body_str(), "hello=world");
+        let request =
+            utils::parse_request_from_http_request_body("GET / HTTP/1.1\r\nHost: a".to_string())
+                .unwrap();
+        assert_eq!(request.body_str(), "");
+    }
+
+    #[test]
+    fn test_plus_in_path_and_query() {
+        let mut request = utils::parse_request_from_http_request_body(
+            "GET /a+b/c%2Bd%20e?x=a+b HTTP/1.1".to_string(),
+        )
+        .unwrap();
+        utils::set_request_params_according_to_match(&mut request, "/{first}/{second}");
+        assert_eq!(request.params()["first"], "a+b");
+        assert_eq!(request.params()["second"], "c+d e");
+        assert_eq!(request.queries()["x"].as_deref(), Some("a b"));
+
+        utils::set_request_params_according_to_match(&mut request, "/{*rest}");
+        assert_eq!(request.params()["rest"], "a+b/c+d e");
+    }
+
+    #[test]
+    fn test_path_without_query() {
+        let request =
+            utils::parse_request_from_http_request_body("GET /greet?name=x HTTP/1.1".to_string())
+                .unwrap();
+        assert_eq!(request.path_as_str(), "/greet");
+        assert_eq!(request.full_target(), "/greet?name=x");
+        assert_eq!(request.raw_query(), Some("name=x"));
+        assert_eq!(request.queries()["name"].as_deref(), Some("x"));
+
+        let mut request = utils::parse_request_from_http_request_body(
+            "GET /app/greet?next=/app HTTP/1.1".to_string(),
+        )
+        .unwrap();
+        assert!(utils::strip_base_path(&mut request, "/app"));
+        assert_eq!(request.path_as_str(), "/greet");
+        assert_eq!(request.full_target(), "/app/greet?next=/app");
+    }
+
+    #[test]
+    fn test_question_mark_in_query_value() {
+        let request = utils::parse_request_from_http_request_body(
+            "GET /search?q=a?b&sort=asc HTTP/1.1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.queries().len(), 2);
+        assert_eq!(request.queries()["q"].as_deref(), Some("a?b"));
+        assert_eq!(request.queries()["sort"].as_deref(), Some("asc"));
+
+        let request =
+            utils::parse_request_from_http_request_body("GET /a&b HTTP/1.1".to_string()).unwrap();
+        assert!(request.queries().is_empty());
+    }
+
+    #[test]
+    fn test_equals_sign_in_query_value() {
+        let request = utils::parse_request_from_http_request_body(
+            "GET /verify?token=abc==&expr=a=b%3Dc&flag HTTP/1.1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(request.queries()["token"].as_deref(), Some("abc=="));
+        assert_eq!(request.queries()["expr"].as_deref(), Some("a=b=c"));
+        assert_eq!(request.queries()["flag"], None);
+    }
+
+    #[test]
+    fn test_matched_route() {
+        let mut request =
+            utils::parse_request_from_http_request_body("GET /users/42 HTTP/1.1".to_string())
+                .unwrap();
+        assert_eq!(request.matched_route(), None);
+        utils::set_request_params_according_to_match(&mut request, "/users/{id}");
+        assert_eq!(request.matched_route(), Some("/users/{id}"));
+    }
+
+    #[test]
+    fn test_path_segments() {
+        let request =
+            utils::parse_request_from_http_request_body("GET //a/b/c/?x=1/2 HTTP/1.1".to_string())
+                .unwrap();
+        assert_eq!(request.path_segments().collect::<Vec<_>>(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_route_segments_must_match() {
+        assert!(!utils::path_matches_route("/test/pathExtra", "/test/path"));
+        assert!(!utils::path_matches_route("/test/pat", "/test/path"));
+        assert!(!utils::path_matches_route("/a/b", "/a/b/c"));
+        assert!(!utils::path_matches_route("/a/b/c", "/a/b"));
+        assert!(!utils::path_matches_route("/a/b/c", "/a/{id}"));
+        assert!(!utils::path_matches_route("/a", "/a/{id}"));
+        assert!(utils::path_matches_route("/a/b/", "/a/{id}"));
+        for (target, route) in [("/a/b?next=/c/d", "/a/b"), ("/?x=1", "/")] {
+            let request =
+                utils::parse_request_from_http_request_body(format!("GET {} HTTP/1.1", target))
+                    .unwrap();
+            assert!(utils::request_matches_route(&request, route), "{}", target);
+        }
+        assert!(utils::path_matches_route("/a/x/c", "/a/*/c"));
+        assert!(!utils::path_matches_route("/a/x/y/c", "/a/*/c"));
+        assert!(utils::path_matches_route("/a/x/y/c", "/a/*"));
+        assert!(!utils::path_matches_route("/About", "/about"));
+    }
+
+    #[test]
+    fn test_match_options() {
+        let strict = utils::MatchOptions {
+            strict_trailing_slash: true,
+            ..Default::default()
+        };
+        assert!(!utils::path_matches_route_with("/x/", "/x", strict));
+        assert!(!utils::path_matches_route_with("/x", "/x/", strict));
+        assert!(utils::path_matches_route_with("/x/", "/x/", strict));
+        assert!(utils::path_matches_route_with("/", "/", strict));
+        assert!(utils::path_matches_route_with("/x/a/", "/x/*", strict));
+        assert!(utils::path_matches_route_with("/x/", "/x/{*rest}", strict));
+
+        let case_insensitive = utils::MatchOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert!(utils::path_matches_route_with(
+            "/About/",
+            "/about",
+            case_insensitive
+        ));
+        assert!(utils::path_matches_route_with(
+            "/USERS/Ann",
+            "/users/{name}",
+            case_insensitive
+        ));
+        assert!(!utils::path_matches_route_with(
+            "/Abouts",
+            "/about",
+            case_insensitive
+        ));
+    }
+
+    #[test]
+    fn test_splat_routes() {
+        let route = "/assets/{*rest}";
+        for (path, rest) in [
+            ("/assets/css/site.css", "css/site.css"),
+            ("/assets/app.js?v=2", "app.js"),
+            ("/assets/", ""),
+            ("/assets", ""),
+        ] {
+            assert!(utils::path_matches_route(path, route), "{}", path);
+            let (mut request, _) = create_mock_request(HttpMethod::GET, path);
+            utils::set_request_params_according_to_match(&mut request, route);
+            assert_eq!(request.params().get("rest").map(String::as_str), Some(rest));
+        }
+        assert!(!utils::path_matches_route("/other/css/site.css", route));
+
+        let (mut request, _) = create_mock_request(HttpMethod::GET, "/u/7/files/a/b");
+        utils::set_request_params_according_to_match(&mut request, "/u/{id}/files/{*path}");
+        assert_eq!(request.params()["id"], "7");
+        assert_eq!(request.params()["path"], "a/b");
+        assert_eq!(request.params().len(), 2);
+
+        assert!(utils::route_specificity("/assets/css/{file}") > utils::route_specificity(route));
+        assert!(utils::route_specificity("/assets/me") > utils::route_specificity("/assets/{id}"));
+        assert!(utils::route_specificity("/assets/{id}") > utils::route_specificity("*"));
+    }
+
+    #[test]
+    fn test_strip_format_extension() {
+        let extensions = vec!["json".to_string(), "html".to_string()];
+        assert_eq!(
+            utils::strip_format_extension("/users/1.json", &extensions),
+            Some(("/users/1".to_string(), "json".to_string()))
+        );
+        assert_eq!(
+            utils::strip_format_extension("/users/1.HTML", &extensions),
+            Some(("/users/1".to_string(), "html".to_string()))
+        );
+        assert_eq!(
+            utils::strip_format_extension("/users/1.xml", &extensions),
+            None
+        );
+        assert_eq!(utils::strip_format_extension("/users/1", &extensions), None);
+        assert_eq!(
+            utils::strip_format_extension("/v1.json/users", &extensions),
+            None
+        );
+        assert_eq!(
+            utils::strip_format_extension("/users/.json", &extensions),
+            None
+        );
+        assert_eq!(utils::strip_format_extension("/users/1.json", &[]), None);
+    }
+
+    #[test]
     fn test_setting_params_according_to_match() {
         let (mut request, _) = create_mock_request(HttpMethod::GET, "/test/path/lorem");
         utils::set_request_params_according_to_match(&mut request, "/{one}/path/{two}");
@@ -306,6 +1828,203 @@ Connection: Keep-Alive",
 
         let (mut request, _) = create_mock_request(HttpMethod::GET, "/test/");
         utils::set_request_params_according_to_match(&mut request, "/test/{name}/one");
-        assert!(request.params.get("name").is_none());
+        assert!(!request.params.contains_key("name"));
+    }
+
+    #[test]
+    fn test_form() {
+        let request =
+            create_mock_request_with_body(HttpMethod::PUT, "/form", "name=john&age=30&age=31");
+        let form = request.form();
+        assert_eq!(form.len(), 2);
+        assert_eq!(form.get("name").unwrap(), "john");
+        assert_eq!(form.get("age").unwrap(), "31");
+
+        let (request, _) = create_mock_request(HttpMethod::GET, "/form");
+        assert!(request.form().is_empty());
+
+        let request = utils::parse_request_from_http_request_body(
+            "POST /form HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=utf-8\r\n\r\nname=John+Doe&age=30&city=K%C3%B6ln".to_string(),
+        )
+        .unwrap();
+        let form = request.form();
+        assert_eq!(form.get("name").unwrap(), "John Doe");
+        assert_eq!(form.get("age").unwrap(), "30");
+        assert_eq!(form.get("city").unwrap(), "Köln");
+
+        let request = utils::parse_request_from_http_request_body(
+            "POST /form HTTP/1.1\r\nContent-Type: application/json\r\n\r\nname=john".to_string(),
+        )
+        .unwrap();
+        assert!(request.form().is_empty());
+    }
+
+    #[test]
+    fn test_params_from_body() {
+        let name = |request: &Request| request.params_from_body().get("name").cloned();
+        let json = create_mock_request_with_headers(
+            HttpMethod::POST,
+            "/signup",
+            &[("Content-Type", "application/json; charset=utf-8")],
+            r#"{"name":"x","age":30}"#,
+        );
+        assert_eq!(name(&json).as_deref(), Some("x"));
+        assert_eq!(json.params_from_body()["age"], "30");
+        let form = create_mock_request_with_headers(
+            HttpMethod::POST,
+            "/signup",
+            &[("Content-Type", "application/x-www-form-urlencoded")],
+            "name=x",
+        );
+        assert_eq!(name(&form).as_deref(), Some("x"));
+
+        let nested = create_mock_request_with_headers(
+            HttpMethod::POST,
+            "/signup",
+            &[("Content-Type", "application/json")],
+            r#"{"name":"x","tags":["a"]}"#,
+        );
+        assert!(nested.params_from_body().is_empty());
+    }
+
+    #[test]
+    fn test_form_array() {
+        let request =
+            create_mock_request_with_body(HttpMethod::PUT, "/form", "items[]=a&other=x&items[]=b");
+        assert_eq!(request.form_array("items"), vec!["a", "b"]);
+        assert_eq!(request.form_array("other"), vec!["x"]);
+        assert!(request.form_array("missing").is_empty());
+    }
+
+    #[test]
+    fn test_form_field_ignore_case() {
+        let request = create_mock_request_with_body(HttpMethod::PUT, "/form", "UserName=john");
+        assert_eq!(request.form_field_ignore_case("username").unwrap(), "john");
+        assert_eq!(request.form_field_ignore_case("USERNAME").unwrap(), "john");
+        assert!(request.form_field_ignore_case("user").is_none());
+    }
+
+    #[test]
+    fn test_query_as() {
+        let (request, _) = create_mock_request(HttpMethod::GET, "/items?page=2&size=10&sort");
+        assert_eq!(request.query_required::<u32>("page"), Ok(2));
+        assert_eq!(request.query_as::<u32>("size"), Ok(Some(10)));
+        assert_eq!(request.query_as::<u32>("offset"), Ok(None));
+        assert_eq!(request.query_as::<String>("sort"), Ok(Some(String::new())));
+        assert_eq!(
+            request.query_required::<u32>("offset"),
+            Err(QueryError::Missing("offset".to_string()))
+        );
+        assert_eq!(
+            request.query_as::<u32>("sort"),
+            Err(QueryError::Invalid("sort".to_string()))
+        );
+
+        let response = Response::from(QueryError::Missing("<b>".to_string()));
+        assert_eq!(response.status_code(), HttpStatusCode::BadRequest);
+        assert_eq!(
+            response.header(HttpHeaderName::ContentType),
+            Some("text/plain; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn test_raw_body_and_query() {
+        let body = " {\"a\": 1}\r\n\t ";
+        let request = create_mock_request_with_body(HttpMethod::POST, "/hook?b=%20x&a=1", body);
+        assert_eq!(request.raw_body(), body.as_bytes());
+        assert_eq!(request.raw_query(), Some("b=%20x&a=1"));
+        let (request, _) = create_mock_request(HttpMethod::GET, "/hook");
+        assert_eq!(request.raw_query(), None);
+        assert_eq!(request.raw_body(), b"");
+
+        let mut content = b"POST /hook HTTP/1.1\r\ncontent-length: 4\r\n\r\n".to_vec();
+        content.extend_from_slice(&[0xff, b'\n', 0x00, 0xfe]);
+        let request = utils::parse_request_from_bytes(content).unwrap();
+        assert_eq!(request.raw_body(), [0xff, b'\n', 0x00, 0xfe]);
+        assert_eq!(request.header("content-length"), Some("4"));
+        assert!(request.raw_content().contains('\u{fffd}'));
+    }
+
+    #[test]
+    fn test_query_decoding() {
+        let (request, _) = create_mock_request(
+            HttpMethod::GET,
+            "/search?name=John%20Doe&q=a+b&amp=x%26y&path=%2Fetc%2F&bad=%ZZ%2&caf%C3%A9=1",
+        );
+        let query = |name: &str| request.queries().get(name).cloned().flatten();
+        assert_eq!(query("name").as_deref(), Some("John Doe"));
+        assert_eq!(query("q").as_deref(), Some("a b"));
+        assert_eq!(query("amp").as_deref(), Some("x&y"));
+        assert_eq!(query("path").as_deref(), Some("/etc/"));
+        assert_eq!(query("bad").as_deref(), Some("%ZZ%2"));
+        assert_eq!(query("café").as_deref(), Some("1"));
+        assert_eq!(request.queries().len(), 6);
+        assert!(utils::path_matches_route(request.path_as_str(), "/search"));
+
+        assert_eq!(utils::percent_decode("a+b%2B", false), "a+b+");
+        assert_eq!(utils::percent_decode("%", true), "%");
+        assert_eq!(utils::percent_decode("%e9", true), "\u{fffd}");
+    }
+
+    #[test]
+    fn test_encoding_quality() {
+        let request = |accept: &str| {
+            create_mock_request_with_headers(
+                HttpMethod::GET,
+                "/",
+                &[("Accept-Encoding", accept)],
+                "",
+            )
+        };
+        let accept = request("gzip, br;q=0.8, deflate;q=0");
+        assert_eq!(accept.encoding_quality("gzip"), 1.0);
+        assert_eq!(accept.encoding_quality("BR"), 0.8);
+        assert_eq!(accept.encoding_quality("deflate"), 0.0);
+        assert_eq!(accept.encoding_quality("zstd"), 0.0);
+        assert_eq!(accept.encoding_quality("identity"), 1.0);
+        let accept = request("br;q=0.5, *;q=0.3");
+        assert_eq!(accept.encoding_quality("gzip"), 0.3);
+        assert_eq!(accept.encoding_quality("br"), 0.5);
+        assert_eq!(accept.encoding_quality("identity"), 0.3);
+        let accept = request("gzip;q=0.5, identity;q=0.8");
+        assert_eq!(accept.encoding_quality("identity"), 0.8);
+        assert_eq!(request("gzip, *;q=0").encoding_quality("identity"), 0.0);
+        let (request, _) = create_mock_request(HttpMethod::GET, "/");
+        assert_eq!(request.encoding_quality("gzip"), 1.0);
+        let request = create_mock_request_with_body(HttpMethod::GET, "/", "");
+        assert_eq!(request.encoding_quality("gzip"), 0.0);
+    }
+
+    #[test]
+    fn test_connection_tokens() {
+        let request = |connection: &str| {
+            create_mock_request_with_headers(
+                HttpMethod::GET,
+                "/chat",
+                &[("Connection", connection), ("Upgrade", "WebSocket")],
+                "",
+            )
+        };
+        let request_both = request("keep-alive, Upgrade");
+        assert_eq!(request_both.connection_tokens(), ["keep-alive", "upgrade"]);
+        assert!(request_both.is_keep_alive());
+        assert!(request_both.is_websocket_upgrade());
+
+        let request_close = request("Close");
+        assert!(!request_close.is_keep_alive());
+        assert!(!request_close.is_websocket_upgrade());
+        let request_keep_alive = request("keep-alive");
+        assert!(!request_keep_alive.is_websocket_upgrade());
+
+        let (request, _) = create_mock_request(HttpMethod::GET, "/");
+        assert!(request.is_keep_alive());
+        let http_1_0 = |headers: &str| {
+            let content = format!("GET / HTTP/1.0\r\n{}\r\n", headers);
+            utils::parse_request_from_http_request_body(content).unwrap()
+        };
+        assert!(!http_1_0("").is_keep_alive());
+        assert!(http_1_0("Connection: Keep-Alive\r\n").is_keep_alive());
+        assert!(http_1_0("").connection_tokens().is_empty());
     }
 }
@@ -1,64 +1,842 @@
 use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::time::SystemTime;
+
+use crate::cookie::{self, CookieOptions};
+use crate::httpdate::format_http_date;
+use crate::mime::mime_from_path;
+use crate::{files, sse, Request};
 
 /// A (non-exhaustive) list of HTTP status codes according to [MDN](https://developer.mozilla.org/de/docs/Web/HTTP/Status)
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum HttpStatusCode {
-    OK,                  // 200
-    BadRequest,          // 400
-    NotFound,            // 404
-    InternalServerError, // 500
+    #[default]
+    OK, // 200
+    Created,              // 201
+    NoContent,            // 204
+    PartialContent,       // 206
+    MovedPermanently,     // 301
+    Found,                // 302
+    NotModified,          // 304
+    BadRequest,           // 400
+    Unauthorized,         // 401
+    Forbidden,            // 403
+    NotFound,             // 404
+    MethodNotAllowed,     // 405
+    RequestTimeout,       // 408
+    UnsupportedMediaType, // 415
+    PayloadTooLarge,      // 413
+    RangeNotSatisfiable,  // 416
+    UpgradeRequired,      // 426
+    TooManyRequests,      // 429
+    InternalServerError,  // 500
+    BadGateway,           // 502
+    ServiceUnavailable,   // 503
+    /// Any other status code with its reason phrase, see
+    /// [Response::set_raw_status].
+    Custom(u16, &'static str),
 }
 
 impl From<HttpStatusCode> for usize {
     fn from(code: HttpStatusCode) -> Self {
         match code {
             HttpStatusCode::OK => 200,
+            HttpStatusCode::Created => 201,
+            HttpStatusCode::NoContent => 204,
+            HttpStatusCode::PartialContent => 206,
+            HttpStatusCode::MovedPermanently => 301,
+            HttpStatusCode::Found => 302,
+            HttpStatusCode::NotModified => 304,
             HttpStatusCode::BadRequest => 400,
+            HttpStatusCode::Unauthorized => 401,
+            HttpStatusCode::Forbidden => 403,
             HttpStatusCode::NotFound => 404,
+            HttpStatusCode::MethodNotAllowed => 405,
+            HttpStatusCode::RequestTimeout => 408,
+            HttpStatusCode::UnsupportedMediaType => 415,
+            HttpStatusCode::PayloadTooLarge => 413,
+            HttpStatusCode::RangeNotSatisfiable => 416,
+            HttpStatusCode::UpgradeRequired => 426,
+            HttpStatusCode::TooManyRequests => 429,
             HttpStatusCode::InternalServerError => 500,
+            HttpStatusCode::BadGateway => 502,
+            HttpStatusCode::ServiceUnavailable => 503,
+            HttpStatusCode::Custom(code, _) => code as usize,
+        }
+    }
+}
+
+impl TryFrom<u16> for HttpStatusCode {
+    type Error = UnknownStatus;
+
+    /// Maps a numeric code to one of the named variants. Codes without a
+    /// named variant yield an error, never [HttpStatusCode::Custom].
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        match code {
+            200 => Ok(HttpStatusCode::OK),
+            201 => Ok(HttpStatusCode::Created),
+            204 => Ok(HttpStatusCode::NoContent),
+            206 => Ok(HttpStatusCode::PartialContent),
+            301 => Ok(HttpStatusCode::MovedPermanently),
+            302 => Ok(HttpStatusCode::Found),
+            304 => Ok(HttpStatusCode::NotModified),
+            400 => Ok(HttpStatusCode::BadRequest),
+            401 => Ok(HttpStatusCode::Unauthorized),
+            403 => Ok(HttpStatusCode::Forbidden),
+            404 => Ok(HttpStatusCode::NotFound),
+            405 => Ok(HttpStatusCode::MethodNotAllowed),
+            408 => Ok(HttpStatusCode::RequestTimeout),
+            415 => Ok(HttpStatusCode::UnsupportedMediaType),
+            413 => Ok(HttpStatusCode::PayloadTooLarge),
+            416 => Ok(HttpStatusCode::RangeNotSatisfiable),
+            426 => Ok(HttpStatusCode::UpgradeRequired),
+            429 => Ok(HttpStatusCode::TooManyRequests),
+            500 => Ok(HttpStatusCode::InternalServerError),
+            502 => Ok(HttpStatusCode::BadGateway),
+            503 => Ok(HttpStatusCode::ServiceUnavailable),
+            _ => Err(UnknownStatus(code)),
+        }
+    }
+}
+
+impl HttpStatusCode {
+    /// Returns true for the codes whose responses never carry a body, and
+    /// therefore no `content-length` either.
+    pub(crate) fn forbids_body(&self) -> bool {
+        matches!(
+            self,
+            HttpStatusCode::NoContent | HttpStatusCode::NotModified
+        )
+    }
+
+    /// Returns the reason phrase sent in the status line, e.g. `Not Found`.
+    /// ```
+    /// use raspi_file_server::HttpStatusCode;
+    ///
+    /// assert_eq!(HttpStatusCode::MethodNotAllowed.reason_phrase(), "Method Not Allowed");
+    /// assert_eq!(HttpStatusCode::Custom(418, "I'm a teapot").reason_phrase(), "I'm a teapot");
+    /// ```
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            HttpStatusCode::OK => "OK",
+            HttpStatusCode::Created => "Created",
+            HttpStatusCode::NoContent => "No Content",
+            HttpStatusCode::PartialContent => "Partial Content",
+            HttpStatusCode::MovedPermanently => "Moved Permanently",
+            HttpStatusCode::Found => "Found",
+            HttpStatusCode::NotModified => "Not Modified",
+            HttpStatusCode::BadRequest => "Bad Request",
+            HttpStatusCode::Unauthorized => "Unauthorized",
+            HttpStatusCode::Forbidden => "Forbidden",
+            HttpStatusCode::NotFound => "Not Found",
+            HttpStatusCode::MethodNotAllowed => "Method Not Allowed",
+            HttpStatusCode::RequestTimeout => "Request Timeout",
+            HttpStatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            HttpStatusCode::PayloadTooLarge => "Content Too Large",
+            HttpStatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatusCode::UpgradeRequired => "Upgrade Required",
+            HttpStatusCode::TooManyRequests => "Too Many Requests",
+            HttpStatusCode::InternalServerError => "Internal Server Error",
+            HttpStatusCode::BadGateway => "Bad Gateway",
+            HttpStatusCode::ServiceUnavailable => "Service Unavailable",
+            HttpStatusCode::Custom(_, reason) => reason,
         }
     }
 }
 
-impl Default for HttpStatusCode {
-    fn default() -> Self {
-        HttpStatusCode::OK
+/// Returns the standard reason phrase of codes without a named
+/// [HttpStatusCode] variant, or an empty one for unusual codes.
+fn standard_reason_phrase(code: u16) -> &'static str {
+    match code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        202 => "Accepted",
+        303 => "See Other",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        406 => "Not Acceptable",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        414 => "URI Too Long",
+        418 => "I'm a teapot",
+        422 => "Unprocessable Content",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
     }
 }
 
-/// A (non-exhaustive) list of HTTP headers.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// The error for a numeric status code which isn't known, or which isn't a
+/// valid HTTP status code at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnknownStatus(pub u16);
+
+impl Display for UnknownStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown HTTP status code {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownStatus {}
+
+/// Why a [Response] can't be sent as it is, see [Response::validate].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseError {
+    /// A custom header name which is empty or contains characters which
+    /// aren't allowed in header names.
+    InvalidHeaderName(String),
+    /// The value of the named header contains a line break or another
+    /// control character, which could inject further header lines.
+    InvalidHeaderValue(String),
+    /// A `content-length` was set which differs from the length of the body,
+    /// or a body whose length isn't known up front.
+    ContentLengthMismatch {
+        declared: String,
+        actual: Option<u64>,
+    },
+}
+
+impl Display for ResponseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseError::InvalidHeaderName(name) => write!(f, "invalid header name {:?}", name),
+            ResponseError::InvalidHeaderValue(name) => {
+                write!(f, "invalid value for header {}", name)
+            }
+            ResponseError::ContentLengthMismatch { declared, actual } => match actual {
+                Some(actual) => write!(
+                    f,
+                    "content-length {} set for a body of {} bytes",
+                    declared, actual
+                ),
+                None => write!(f, "content-length {} set for a streamed body", declared),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+/// A (non-exhaustive) list of HTTP headers. Other headers can be set
+/// through [HttpHeaderName::Custom].
+///
+/// `ContentLength` and `TransferEncoding` describe the framing of the body,
+/// so the serializer writes them itself based on the kind of body. Values set
+/// for them on a [Response] are not sent.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum HttpHeaderName {
     ContentType,
+    ContentDisposition,
+    ContentEncoding,
+    ETag,
+    LastModified,
+    CacheControl,
+    Expires,
+    Pragma,
+    Vary,
+    ContentRange,
+    AcceptRanges,
+    ContentLength,
+    TransferEncoding,
+    Trailer,
+    Connection,
+    Upgrade,
+    SetCookie,
+    Location,
+    Allow,
+    RetryAfter,
+    Server,
+    WwwAuthenticate,
+    AccessControlAllowOrigin,
+    AccessControlAllowMethods,
+    AccessControlAllowHeaders,
+    /// Any other header, e.g. `x-request-id`. The name is sent as given and,
+    /// unlike header names in general, compared case-sensitively, so it's best
+    /// to stick to lowercase.
+    Custom(String),
 }
 
-impl From<HttpHeaderName> for &str {
-    fn from(name: HttpHeaderName) -> Self {
-        match name {
+/// The named variants of [HttpHeaderName], for looking up names.
+const KNOWN_HEADER_NAMES: [HttpHeaderName; 25] = [
+    HttpHeaderName::ContentType,
+    HttpHeaderName::ContentDisposition,
+    HttpHeaderName::ContentEncoding,
+    HttpHeaderName::ETag,
+    HttpHeaderName::LastModified,
+    HttpHeaderName::CacheControl,
+    HttpHeaderName::Expires,
+    HttpHeaderName::Pragma,
+    HttpHeaderName::Vary,
+    HttpHeaderName::ContentRange,
+    HttpHeaderName::AcceptRanges,
+    HttpHeaderName::ContentLength,
+    HttpHeaderName::TransferEncoding,
+    HttpHeaderName::Trailer,
+    HttpHeaderName::Connection,
+    HttpHeaderName::Upgrade,
+    HttpHeaderName::SetCookie,
+    HttpHeaderName::Location,
+    HttpHeaderName::Allow,
+    HttpHeaderName::RetryAfter,
+    HttpHeaderName::Server,
+    HttpHeaderName::WwwAuthenticate,
+    HttpHeaderName::AccessControlAllowOrigin,
+    HttpHeaderName::AccessControlAllowMethods,
+    HttpHeaderName::AccessControlAllowHeaders,
+];
+
+impl HttpHeaderName {
+    /// Returns the name as it is sent, which is lowercase for all but custom
+    /// headers.
+    /// ```
+    /// use raspi_file_server::HttpHeaderName;
+    ///
+    /// assert_eq!(HttpHeaderName::ContentType.as_str(), "content-type");
+    /// assert_eq!(HttpHeaderName::Custom("x-id".to_string()).as_str(), "x-id");
+    /// ```
+    pub fn as_str(&self) -> &str {
+        match self {
             HttpHeaderName::ContentType => "content-type",
+            HttpHeaderName::ContentDisposition => "content-disposition",
+            HttpHeaderName::ContentEncoding => "content-encoding",
+            HttpHeaderName::ETag => "etag",
+            HttpHeaderName::LastModified => "last-modified",
+            HttpHeaderName::CacheControl => "cache-control",
+            HttpHeaderName::Expires => "expires",
+            HttpHeaderName::Pragma => "pragma",
+            HttpHeaderName::Vary => "vary",
+            HttpHeaderName::ContentRange => "content-range",
+            HttpHeaderName::AcceptRanges => "accept-ranges",
+            HttpHeaderName::ContentLength => "content-length",
+            HttpHeaderName::TransferEncoding => "transfer-encoding",
+            HttpHeaderName::Trailer => "trailer",
+            HttpHeaderName::Connection => "connection",
+            HttpHeaderName::Upgrade => "upgrade",
+            HttpHeaderName::SetCookie => "set-cookie",
+            HttpHeaderName::Location => "location",
+            HttpHeaderName::Allow => "allow",
+            HttpHeaderName::RetryAfter => "retry-after",
+            HttpHeaderName::Server => "server",
+            HttpHeaderName::WwwAuthenticate => "www-authenticate",
+            HttpHeaderName::AccessControlAllowOrigin => "access-control-allow-origin",
+            HttpHeaderName::AccessControlAllowMethods => "access-control-allow-methods",
+            HttpHeaderName::AccessControlAllowHeaders => "access-control-allow-headers",
+            HttpHeaderName::Custom(name) => name,
         }
     }
 }
 
+impl From<&str> for HttpHeaderName {
+    /// Looks up a header name case-insensitively. Names without a variant
+    /// become a lowercased [HttpHeaderName::Custom].
+    fn from(name: &str) -> Self {
+        KNOWN_HEADER_NAMES
+            .iter()
+            .find(|header_name| name.eq_ignore_ascii_case(header_name.as_str()))
+            .cloned()
+            .unwrap_or_else(|| HttpHeaderName::Custom(name.to_ascii_lowercase()))
+    }
+}
+
 /// An object representing a HTTP response.
 ///
 /// By default, the body is empty, no headers are set and the status code
 /// is 200 OK. Using various methods, these can be changed. For example,
 /// [Response::set_body] changes only the body. In contrast, [Response::set_json]
 /// changes the body _and_ sets the header `content-type: application/json`.
+///
+/// The body may hold any bytes, not only text (see [Response::set_body_bytes]).
+/// Instead of an owned body, a response can also carry static bytes (see
+/// [Response::from_static]) or a stream, which is sent to the client in chunks
+/// while it is being read (see [Response::from_reader]).
 #[derive(Default)]
 pub struct Response {
     status_code: HttpStatusCode,
-    body: String,
+    body: Vec<u8>,
+    headers: HashMap<HttpHeaderName, String>,
+    /// Headers which may be sent multiple times, see [Response::append_header].
+    appended_headers: Vec<(HttpHeaderName, String)>,
+    raw_body: Option<RawBody>,
+    /// Only the head is sent, as the answer to a HEAD request.
+    head_only: bool,
+    /// The handler has nothing to serve, see [Response::not_found].
+    fall_through: bool,
+    /// Announced trailer names and the function computing the trailers, see
+    /// [Response::with_trailers].
+    trailers: Option<(Vec<HttpHeaderName>, TrailerFn)>,
+}
+
+type TrailerFn = Box<dyn FnOnce() -> Vec<(HttpHeaderName, String)>>;
+
+/// Headers whose values are replaced with `<redacted>` in the [Debug] output
+/// of a [Response], so credentials don't end up in logs.
+const REDACTED_HEADERS: [&str; 4] = [
+    "set-cookie",
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+];
+
+impl Debug for Response {
+    /// Shows the status, the headers sorted by name with the values of
+    /// sensitive ones like `set-cookie` redacted, and the size of the body.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut headers: Vec<(&str, &str)> = self
+            .headers
+            .iter()
+            .chain(
+                self.appended_headers
+                    .iter()
+                    .map(|(name, value)| (name, value)),
+            )
+            .map(|(name, value)| {
+                let name = name.as_str();
+                let redacted = REDACTED_HEADERS
+                    .iter()
+                    .any(|r| name.eq_ignore_ascii_case(r));
+                (
+                    name,
+                    if redacted {
+                        "<redacted>"
+                    } else {
+                        value.as_str()
+                    },
+                )
+            })
+            .collect();
+        headers.sort();
+        let mut debug = f.debug_struct("Response");
+        debug
+            .field("status_code", &self.status_code)
+            .field("headers", &headers);
+        match &self.raw_body {
+            None => debug.field("body", &format_args!("{} bytes", self.body.len())),
+            Some(RawBody::Static(bytes)) => {
+                debug.field("body", &format_args!("{} static bytes", bytes.len()))
+            }
+            Some(RawBody::Sized(_, length)) => {
+                debug.field("body", &format_args!("stream of {} bytes", length))
+            }
+            Some(RawBody::Stream(_)) => debug.field("body", &format_args!("stream")),
+        };
+        debug.finish()
+    }
+}
+
+/// A copy of a [Response] whose body is held in memory. Unlike a response,
+/// it can be shared between threads and turned into a response repeatedly.
+#[derive(Clone)]
+pub(crate) struct StoredResponse {
+    status_code: HttpStatusCode,
+    body: Vec<u8>,
     headers: HashMap<HttpHeaderName, String>,
+    appended_headers: Vec<(HttpHeaderName, String)>,
+    static_body: Option<&'static [u8]>,
+}
+
+impl From<StoredResponse> for Response {
+    fn from(stored: StoredResponse) -> Self {
+        Response {
+            status_code: stored.status_code,
+            body: stored.body,
+            headers: stored.headers,
+            appended_headers: stored.appended_headers,
+            raw_body: stored.static_body.map(RawBody::Static),
+            head_only: false,
+            fall_through: false,
+            trailers: None,
+        }
+    }
+}
+
+/// A body which isn't owned by the response.
+enum RawBody {
+    Static(&'static [u8]),
+    /// A stream whose length is known up front, e.g. a file.
+    Sized(Box<dyn Read>, u64),
+    Stream(Box<dyn Read>),
 }
 
 impl Response {
+    /// Creates an empty response: status 200 OK, no headers and an empty body.
+    ///
+    /// This is the same as [Response::default], but makes the intent explicit.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty response, meant as the start of a chain of the
+    /// consuming `with_*` methods.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn missing_route(_: &Request) -> Response {
+    ///     Response::new()
+    ///         .with_status_code(HttpStatusCode::NotFound)
+    ///         .with_html("<h1>nope</h1>")
+    /// }
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty `200 OK` response.
+    pub fn ok() -> Self {
+        Self::with_status(HttpStatusCode::OK)
+    }
+
+    /// Creates an empty response with the given status code.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn delete_route(_: &Request) -> Response {
+    ///     Response::with_status(HttpStatusCode::NotFound)
+    /// }
+    /// ```
+    pub fn with_status(code: HttpStatusCode) -> Self {
+        let mut response = Self::default();
+        response.set_status_code(code);
+        response
+    }
+
+    /// Creates a response from a fallibly generated body: an `Ok` body is
+    /// sent like a `String`, an error with `err_status` and its message as
+    /// plain text, since error messages often quote client input.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn report_route(_: &Request) -> Response {
+    ///     Response::try_body(render_report(), HttpStatusCode::InternalServerError)
+    /// }
+    /// # fn render_report() -> Result<String, std::fmt::Error> { Ok(String::new()) }
+    /// ```
+    pub fn try_body<E: Display>(result: Result<String, E>, err_status: HttpStatusCode) -> Self {
+        match result {
+            Ok(body) => body.into(),
+            Err(err) => Self::with_status(err_status)
+                .with_header(HttpHeaderName::ContentType, "text/plain; charset=utf-8")
+                .with_body(err.to_string()),
+        }
+    }
+
+    /// Creates a redirect to `url`, which is sent as the `location` header.
+    /// The status is `301 Moved Permanently` if `permanent` is true, which
+    /// clients may cache, and `302 Found` otherwise.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn admin_route(req: &Request) -> Response {
+    ///     if req.header("authorization").is_none() {
+    ///         return Response::redirect("/login", false);
+    ///     }
+    ///     "<h1>Admin</h1>".into()
+    /// }
+    /// ```
+    pub fn redirect(url: &str, permanent: bool) -> Self {
+        let status = if permanent {
+            HttpStatusCode::MovedPermanently
+        } else {
+            HttpStatusCode::Found
+        };
+        Self::with_status(status).with_header(HttpHeaderName::Location, url)
+    }
+
+    /// Creates a `426 Upgrade Required` response, telling the client to
+    /// switch to one of `protocols` through the `upgrade` header.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn socket_route(_: &Request) -> Response {
+    ///     Response::upgrade_required(&["websocket"])
+    /// }
+    /// ```
+    pub fn upgrade_required(protocols: &[&str]) -> Self {
+        Self::with_status(HttpStatusCode::UpgradeRequired)
+            .with_header(HttpHeaderName::Upgrade, protocols.join(", "))
+            .with_header(HttpHeaderName::Connection, "upgrade")
+    }
+
+    /// Creates a response which streams its body from `reader`.
+    ///
+    /// This is meant for bodies whose size isn't known up front, e.g. when
+    /// copying from an upstream connection or a file which is still growing.
+    /// The body is sent with `transfer-encoding: chunked` while it is read.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn log_route(_: &Request) -> Response {
+    ///     match std::fs::File::open("/var/log/syslog") {
+    ///         Ok(file) => Response::from_reader("text/plain", file),
+    ///         Err(_) => Response::with_status(HttpStatusCode::NotFound),
+    ///     }
+    /// }
+    /// ```
+    pub fn from_reader<S: ToString, R: Read + 'static>(content_type: S, reader: R) -> Self {
+        let mut response = Self::default();
+        response.set_header(HttpHeaderName::ContentType, content_type);
+        response.set_stream(reader);
+        response
+    }
+
+    /// Creates a response which streams its body from `reader`, like
+    /// [Response::from_reader] but without a `content-type`, which can be
+    /// added with [Response::with_header] if needed.
+    ///
+    /// The body is sent with `transfer-encoding: chunked`, so a large file is
+    /// never held in memory as a whole.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn backup_route(_: &Request) -> Response {
+    ///     match std::fs::File::open("/srv/backup.tar") {
+    ///         Ok(file) => Response::stream(file)
+    ///             .with_header(HttpHeaderName::ContentType, "application/x-tar"),
+    ///         Err(_) => Response::with_status(HttpStatusCode::NotFound),
+    ///     }
+    /// }
+    /// ```
+    pub fn stream<R: Read + 'static>(reader: R) -> Self {
+        let mut response = Self::default();
+        response.set_stream(reader);
+        response
+    }
+
+    /// Sends trailer headers after a streamed body, e.g. a checksum which is
+    /// only known once the body has been read. The `names` are announced in
+    /// the `trailer` header up front; `trailers` is called after the stream
+    /// has ended.
+    ///
+    /// Trailers can only follow a body sent with `transfer-encoding: chunked`
+    /// (see [Response::from_reader]); for other bodies they are dropped.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn report_route(_: &Request) -> Response {
+    ///     let report = std::io::Cursor::new(b"all systems nominal".to_vec());
+    ///     let checksum = HttpHeaderName::Custom("x-checksum".to_string());
+    ///     Response::from_reader("text/plain", report)
+    ///         .with_trailers([checksum.clone()], move || vec![(checksum, "1f3a".to_string())])
+    /// }
+    /// ```
+    pub fn with_trailers<I, F>(mut self, names: I, trailers: F) -> Self
+    where
+        I: IntoIterator<Item = HttpHeaderName>,
+        F: FnOnce() -> Vec<(HttpHeaderName, String)> + 'static,
+    {
+        self.trailers = Some((names.into_iter().collect(), Box::new(trailers)));
+        self
+    }
+
+    /// Creates a response serving bytes which are embedded into the binary,
+    /// without copying them. The `content-length` is the length of `bytes`.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// static LOGO: &[u8] = &[0x89, b'P', b'N', b'G']; // or include_bytes!("logo.png")
+    ///
+    /// fn logo_route(_: &Request) -> Response {
+    ///     Response::from_static("image/png", LOGO)
+    /// }
+    /// ```
+    pub fn from_static(content_type: &str, bytes: &'static [u8]) -> Self {
+        let mut response = Self::default();
+        response.set_header(HttpHeaderName::ContentType, content_type);
+        response.raw_body = Some(RawBody::Static(bytes));
+        response
+    }
+
+    /// Creates an empty response from a numeric status code.
+    ///
+    /// Codes with a named [HttpStatusCode] variant map to it, other codes in the
+    /// valid range of 100 to 599 fall back to a raw status (see
+    /// [Response::set_raw_status]) with their standard reason phrase, or none
+    /// if the code is unusual. Codes outside of that range yield
+    /// [UnknownStatus].
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn create_route(_: &Request) -> Response {
+    ///     Response::status(201).unwrap()
+    /// }
+    /// ```
+    pub fn status(code: u16) -> Result<Self, UnknownStatus> {
+        match HttpStatusCode::try_from(code) {
+            Ok(status_code) => Ok(Self::with_status(status_code)),
+            Err(_) if (100..=599).contains(&code) => {
+                let mut response = Self::default();
+                response.set_raw_status(code, standard_reason_phrase(code));
+                Ok(response)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sets the validators `etag` and, if given, `last-modified` of the
+    /// response. Quotes are added to `etag` unless it's already quoted (or a
+    /// weak tag like `W/"abc"`).
+    ///
+    /// The server compares them to the `if-none-match` and `if-modified-since`
+    /// headers of a GET or HEAD request and answers with `304 Not Modified`
+    /// (without a body) if the client's copy is still fresh. Handlers
+    /// therefore don't need to implement conditional requests themselves.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn versioned_route(_: &Request) -> Response {
+    ///     Response::from("<h1>Version 3</h1>").with_validators("v3", None)
+    /// }
+    /// ```
+    pub fn with_validators(mut self, etag: &str, last_modified: Option<SystemTime>) -> Self {
+        if etag.starts_with('"') || etag.starts_with("W/\"") {
+            self.set_header(HttpHeaderName::ETag, etag);
+        } else {
+            self.set_header(HttpHeaderName::ETag, format!("\"{}\"", etag));
+        }
+        if let Some(last_modified) = last_modified {
+            self.set_header(
+                HttpHeaderName::LastModified,
+                format_http_date(last_modified),
+            );
+        }
+        self
+    }
+
+    /// Like [Response::set_status_code], but consumes and returns the response.
+    pub fn with_status_code(mut self, code: HttpStatusCode) -> Self {
+        self.set_status_code(code);
+        self
+    }
+
+    /// Like [Response::set_header], but consumes and returns the response.
+    pub fn with_header<S: ToString>(
+        mut self,
+        header_name: HttpHeaderName,
+        header_value: S,
+    ) -> Self {
+        self.set_header(header_name, header_value);
+        self
+    }
+
+    /// Like [Response::set_body], but consumes and returns the response.
+    pub fn with_body<S: ToString>(mut self, body: S) -> Self {
+        self.set_body(body);
+        self
+    }
+
+    /// Like [Response::set_json], but consumes and returns the response.
+    pub fn with_json<S: ToString>(mut self, json: S) -> Self {
+        self.set_json(json);
+        self
+    }
+
+    /// Like [Response::set_html], but consumes and returns the response.
+    pub fn with_html<S: ToString>(mut self, html: S) -> Self {
+        self.set_html(html);
+        self
+    }
+
     /// Sets the HTTP status code
     pub fn set_status_code(&mut self, code: HttpStatusCode) {
         self.status_code = code;
     }
 
+    /// Replaces the status code with the result of `f`, e.g. in code which
+    /// post-processes responses.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn no_content_if_empty(response: Response) -> Response {
+    ///     let empty = !response.has_body();
+    ///     response.map_status(|status| match status {
+    ///         HttpStatusCode::OK if empty => HttpStatusCode::NoContent,
+    ///         other => other,
+    ///     })
+    /// }
+    /// ```
+    pub fn map_status<F: FnOnce(HttpStatusCode) -> HttpStatusCode>(mut self, f: F) -> Self {
+        self.status_code = f(self.status_code);
+        self
+    }
+
+    /// Sets a status code which has no named [HttpStatusCode] variant,
+    /// together with its reason phrase. The status line is sent as
+    /// `HTTP/1.1 <code> <reason>`.
+    pub fn set_raw_status(&mut self, code: u16, reason: &'static str) {
+        self.status_code = HttpStatusCode::Custom(code, reason);
+    }
+
+    /// Returns the status code of the response.
+    pub fn status_code(&self) -> HttpStatusCode {
+        self.status_code
+    }
+
+    /// Returns true if the response has a non-empty text body, or a static or
+    /// streamed body.
+    pub fn has_body(&self) -> bool {
+        !self.body.is_empty() || self.raw_body.is_some()
+    }
+
+    /// Checks that the response can be sent as it is: header names and values
+    /// mustn't contain line breaks or other characters which would corrupt
+    /// the head, and a `content-length` set by hand has to match the body.
+    /// The server checks each response before writing it and sends a
+    /// `500 Internal Server Error` instead of an invalid one.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// let mut response = Response::from("<h1>Hi</h1>");
+    /// assert_eq!(response.validate(), Ok(()));
+    /// response.set_header(HttpHeaderName::Location, "/a\r\nset-cookie: x=1");
+    /// assert!(response.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), ResponseError> {
+        let names = self
+            .headers
+            .keys()
+            .chain(self.appended_headers.iter().map(|(name, _)| name))
+            .chain(self.trailers.iter().flat_map(|(names, _)| names));
+        for name in names {
+            if !is_header_name(name.as_str()) {
+                return Err(ResponseError::InvalidHeaderName(name.as_str().to_string()));
+            }
+        }
+        let values = self.headers.iter().chain(
+            self.appended_headers
+                .iter()
+                .map(|(name, value)| (name, value)),
+        );
+        for (name, value) in values {
+            if !is_header_value(value) {
+                return Err(ResponseError::InvalidHeaderValue(name.as_str().to_string()));
+            }
+        }
+        // the body of HEAD responses and 304s is left out anyway
+        if let Some(declared) = self.header(HttpHeaderName::ContentLength) {
+            let actual = self.body_length();
+            let skipped = self.head_only || self.status_code.forbids_body();
+            if !skipped && declared.trim().parse::<u64>().ok() != actual {
+                return Err(ResponseError::ContentLengthMismatch {
+                    declared: declared.to_string(),
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a header, if it is set.
+    pub fn header(&self, header_name: HttpHeaderName) -> Option<&str> {
+        self.headers.get(&header_name).map(String::as_str)
+    }
+
     /// Sets a specific header.
     ///
     /// If a header with the same [HttpHeaderName] is already set, it will get overwritten.
@@ -66,15 +844,149 @@ impl Response {
         self.headers.insert(header_name, header_value.to_string());
     }
 
+    /// Adds a header line without replacing the values already set for the
+    /// header. Each value is sent as a separate line, which is how headers like
+    /// `set-cookie` have to be repeated.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// let mut response = Response::default();
+    /// response.append_header(HttpHeaderName::SetCookie, "a=1");
+    /// response.append_header(HttpHeaderName::SetCookie, "b=2");
+    /// assert_eq!(response.header_values(HttpHeaderName::SetCookie), ["a=1", "b=2"]);
+    /// ```
+    pub fn append_header<S: ToString>(&mut self, header_name: HttpHeaderName, header_value: S) {
+        self.appended_headers
+            .push((header_name, header_value.to_string()));
+    }
+
+    /// Returns all values of a header, the one set with [Response::set_header]
+    /// first, followed by those added with [Response::append_header].
+    pub fn header_values(&self, header_name: HttpHeaderName) -> Vec<&str> {
+        self.headers
+            .get(&header_name)
+            .into_iter()
+            .chain(
+                self.appended_headers
+                    .iter()
+                    .filter(|(name, _)| *name == header_name)
+                    .map(|(_, value)| value),
+            )
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Sets the body to the content of the file at `path`, which is streamed
+    /// to the client, and the `content-type` guessed from its extension (see
+    /// [mime_from_extension](crate::mime::mime_from_extension)).
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn manual_route(_: &Request) -> Response {
+    ///     let mut response = Response::default();
+    ///     match response.set_file("/home/pi/manual.pdf") {
+    ///         Ok(()) => response,
+    ///         Err(_) => Response::with_status(HttpStatusCode::NotFound),
+    ///     }
+    /// }
+    /// ```
+    pub fn set_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        self.set_header(HttpHeaderName::ContentType, mime_from_path(path));
+        self.set_sized_stream(file, length);
+        Ok(())
+    }
+
+    /// Creates a response serving the file at `path` the way `request` asks
+    /// for it: with a `content-type`, validators (`etag`, `last-modified`)
+    /// and support for conditional requests and single byte ranges. The
+    /// content is streamed to the client.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn manual_route(req: &Request) -> Response {
+    ///     Response::pipe_from_file("/home/pi/manual.pdf", req)
+    ///         .unwrap_or_else(|_| Response::with_status(HttpStatusCode::NotFound))
+    /// }
+    /// ```
+    pub fn pipe_from_file<P: AsRef<Path>>(path: P, request: &Request) -> io::Result<Self> {
+        files::pipe_file(path.as_ref(), request)
+    }
+
+    /// Forbids clients and proxies to store the response, e.g. for pages
+    /// behind a login, through `cache-control` and, for HTTP/1.0 caches,
+    /// `pragma`.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn account_route(_: &Request) -> Response {
+    ///     let mut response: Response = "<h1>Your account</h1>".into();
+    ///     response.no_cache();
+    ///     response
+    /// }
+    /// ```
+    pub fn no_cache(&mut self) {
+        self.set_header(
+            HttpHeaderName::CacheControl,
+            "no-store, no-cache, must-revalidate",
+        );
+        self.set_header(HttpHeaderName::Pragma, "no-cache");
+    }
+
+    /// Sets a cookie through a `set-cookie` header. Each call adds its own
+    /// header line, so a response can set several cookies. Characters like
+    /// `;` or spaces in the name, value or path are percent-encoded, so a value
+    /// built from user input can't add cookie attributes.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn login_route(_: &Request) -> Response {
+    ///     let mut response = Response::empty();
+    ///     let options = CookieOptions {
+    ///         http_only: true,
+    ///         same_site: Some(SameSite::Strict),
+    ///         ..Default::default()
+    ///     };
+    ///     response.set_cookie("session", "f3a9", options);
+    ///     response.set_cookie("theme", "dark", CookieOptions::default());
+    ///     response
+    /// }
+    /// ```
+    pub fn set_cookie(&mut self, name: &str, value: &str, options: CookieOptions) {
+        self.append_header(
+            HttpHeaderName::SetCookie,
+            cookie::format_set_cookie(name, value, &options),
+        );
+    }
+
     /// Sets the body and only the body of the response.
     pub fn set_body<S: ToString>(&mut self, body: S) {
-        self.body = body.to_string();
+        self.set_body_bytes(body.to_string().as_bytes());
+    }
+
+    /// Sets the body to arbitrary bytes, which are sent unchanged. Unlike
+    /// text, they may contain invalid UTF-8, e.g. an image.
+    /// ```
+    /// use raspi_file_server::*;
+    ///
+    /// fn pixel_route(_: &Request) -> Response {
+    ///     let mut response = Response::default();
+    ///     response.set_header(HttpHeaderName::ContentType, "image/gif");
+    ///     response.set_body_bytes(&[0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0xFF, 0x00]);
+    ///     response
+    /// }
+    /// ```
+    pub fn set_body_bytes(&mut self, body: &[u8]) {
+        self.body = body.to_vec();
+        self.raw_body = None;
     }
 
     /// Sets the body of the response and the header `content-type: application/json`.
     pub fn set_json<S: ToString>(&mut self, json: S) {
         self.set_header(HttpHeaderName::ContentType, "application/json");
-        self.body = json.to_string();
+        self.set_body(json);
     }
 
     /// Sets the body of the response and the header `content-type: text/html`.
@@ -82,31 +994,257 @@ impl Response {
     /// This method is also used by the implementation of [From<&str>] for Response.
     pub fn set_html<S: ToString>(&mut self, html: S) {
         self.set_header(HttpHeaderName::ContentType, "text/html");
-        self.body = html.to_string();
+        self.set_body(html);
+    }
+
+    /// Creates a response streaming server-sent events: each message received
+    /// from `receiver` is sent as a `data:` event as soon as it arrives,
+    /// until all senders are dropped. A message with several lines becomes
+    /// one event with several `data:` lines.
+    /// ```
+    /// use raspi_file_server::*;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// fn clock_route(_: &Request) -> Response {
+    ///     let (sender, receiver) = mpsc::channel();
+    ///     thread::spawn(move || {
+    ///         for tick in 0.. {
+    ///             // fails once the client is gone
+    ///             if sender.send(format!("tick {}", tick)).is_err() {
+    ///                 break;
+    ///             }
+    ///             thread::sleep(Duration::from_secs(1));
+    ///         }
+    ///     });
+    ///     Response::sse_from_channel(receiver)
+    /// }
+    /// ```
+    pub fn sse_from_channel(receiver: Receiver<String>) -> Self {
+        let mut response = Self::default();
+        response.set_header(HttpHeaderName::ContentType, "text/event-stream");
+        response.set_header(HttpHeaderName::CacheControl, "no-cache");
+        response.set_stream(sse::ChannelEvents::new(receiver));
+        response
+    }
+
+    /// Replaces the body by a stream, which gets sent with
+    /// `transfer-encoding: chunked` as it is read.
+    pub(crate) fn set_stream<R: Read + 'static>(&mut self, reader: R) {
+        self.body.clear();
+        self.raw_body = Some(RawBody::Stream(Box::new(reader)));
+    }
+
+    /// Replaces the body by a stream of exactly `length` bytes, which gets sent
+    /// with a `content-length` as it is read.
+    pub(crate) fn set_sized_stream<R: Read + 'static>(&mut self, reader: R, length: u64) {
+        self.body.clear();
+        self.raw_body = Some(RawBody::Sized(Box::new(reader), length));
+    }
+
+    /// Copies the response for storing it, see [StoredResponse]. Returns None
+    /// if the body is a stream, which can only be read once.
+    pub(crate) fn to_stored(&self) -> Option<StoredResponse> {
+        let static_body = match self.raw_body {
+            None => None,
+            Some(RawBody::Static(bytes)) => Some(bytes),
+            Some(_) => return None,
+        };
+        Some(StoredResponse {
+            status_code: self.status_code,
+            body: self.body.clone(),
+            headers: self.headers.clone(),
+            appended_headers: self.appended_headers.clone(),
+            static_body,
+        })
+    }
+
+    /// Creates a `404 Not Found` response which the server replaces with the
+    /// response of its not-found handler, for built-in handlers like the one
+    /// of [Server::serve_dir](crate::Server::serve_dir).
+    pub(crate) fn not_found() -> Self {
+        let mut response = Self::with_status(HttpStatusCode::NotFound);
+        response.fall_through = true;
+        response
+    }
+
+    pub(crate) fn is_fall_through(&self) -> bool {
+        self.fall_through
+    }
+
+    /// Marks the response as the answer to a HEAD request: the head, including
+    /// the `content-length` of the body, is sent, but the body isn't.
+    pub(crate) fn set_head_only(&mut self) {
+        self.head_only = true;
+    }
+
+    /// Returns the body if it is held in memory, or None for a stream.
+    pub(crate) fn buffered_body(&self) -> Option<&[u8]> {
+        match &self.raw_body {
+            None => Some(&self.body),
+            Some(RawBody::Static(bytes)) => Some(bytes),
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the number of body bytes which are sent, or None for a stream
+    /// of unknown length.
+    pub(crate) fn body_length(&self) -> Option<u64> {
+        match &self.raw_body {
+            _ if self.head_only || self.status_code.forbids_body() => Some(0),
+            None => Some(self.body.len() as u64),
+            Some(RawBody::Static(bytes)) => Some(bytes.len() as u64),
+            Some(RawBody::Sized(_, length)) => Some(*length),
+            Some(RawBody::Stream(_)) => None,
+        }
+    }
+
+    /// Returns the header describing the framing of the body, or None if the
+    /// status doesn't allow a body.
+    fn framing_header(&self) -> Option<String> {
+        match &self.raw_body {
+            _ if self.status_code.forbids_body() => None,
+            None => Some(format!("content-length: {}", self.body.len())),
+            Some(RawBody::Static(bytes)) => Some(format!("content-length: {}", bytes.len())),
+            Some(RawBody::Sized(_, length)) => Some(format!("content-length: {}", length)),
+            Some(RawBody::Stream(_)) => Some("transfer-encoding: chunked".to_string()),
+        }
+    }
+
+    /// Turns the response into a `304 Not Modified`, which keeps the headers
+    /// but doesn't carry a body.
+    pub(crate) fn set_not_modified(&mut self) {
+        self.status_code = HttpStatusCode::NotModified;
+        self.body.clear();
+        self.raw_body = None;
     }
 
     fn headers_to_string(&self) -> String {
         self.headers
             .iter()
-            .map(|(hn, value)| format!("{}: {}", <HttpHeaderName as Into<&str>>::into(*hn), value))
-            .collect::<Vec<String>>()
-            .join("\n")
+            .chain(self.appended_headers.iter().map(|(hn, value)| (hn, value)))
+            .filter(|(hn, _)| {
+                !matches!(
+                    hn,
+                    HttpHeaderName::ContentLength
+                        | HttpHeaderName::TransferEncoding
+                        | HttpHeaderName::Trailer
+                )
+            })
+            .map(|(hn, value)| format!("{}: {}\r\n", hn.as_str(), value))
+            .collect()
+    }
+}
+
+/// Converts a Response to the bytes which can be written to the response
+/// [TcpStream](std::net::TcpStream): the head as ASCII, then the raw body.
+pub fn response_into_http_response_bytes(response: Response) -> Vec<u8> {
+    let mut bytes = head_to_string(&response, response.framing_header().as_deref()).into_bytes();
+    if !response.head_only {
+        bytes.extend_from_slice(&response.body);
     }
+    bytes
 }
 
-/// Converts a Response to a String which can be written to the response
-/// [TcpStream](std::net::TcpStream).
-pub fn response_into_http_response_string(response: Response) -> String {
+/// Formats status line and headers, followed by the header describing the
+/// framing of the body (if the response can have a body) and the blank line.
+/// Every header ends its own line, so a response without headers has none.
+fn head_to_string(response: &Response, framing_header: Option<&str>) -> String {
     format!(
-        "HTTP/1.1 {} {:?}\n{}\ncontent-length: {}\n\n{}",
+        "HTTP/1.1 {} {}\r\n{}{}\r\n",
         <HttpStatusCode as Into<usize>>::into(response.status_code),
-        response.status_code,
+        response.status_code.reason_phrase(),
         response.headers_to_string(),
-        response.body.len(),
-        response.body
+        framing_header
+            .map(|header| format!("{}\r\n", header))
+            .unwrap_or_default()
     )
 }
 
+/// Writes a Response to a [Write] like the response
+/// [TcpStream](std::net::TcpStream). Streamed responses are copied from their
+/// reader using chunked transfer encoding.
+pub fn write_response<W: Write>(mut response: Response, writer: &mut W) -> io::Result<()> {
+    if response.head_only {
+        let head = head_to_string(&response, response.framing_header().as_deref());
+        return writer.write_all(head.as_bytes());
+    }
+    let mut stream = match response.raw_body.take() {
+        None => return writer.write_all(&response_into_http_response_bytes(response)),
+        Some(RawBody::Static(bytes)) => {
+            let framing_header = format!("content-length: {}", bytes.len());
+            writer.write_all(head_to_string(&response, Some(&framing_header)).as_bytes())?;
+            return writer.write_all(bytes);
+        }
+        Some(RawBody::Sized(stream, length)) => {
+            let framing_header = format!("content-length: {}", length);
+            writer.write_all(head_to_string(&response, Some(&framing_header)).as_bytes())?;
+            let copied = io::copy(&mut stream.take(length), writer)?;
+            if copied < length {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            return Ok(());
+        }
+        Some(RawBody::Stream(stream)) => stream,
+    };
+    // content-length and chunked encoding are mutually exclusive
+    debug_assert!(
+        response.header(HttpHeaderName::ContentLength).is_none(),
+        "a streamed response must not set content-length"
+    );
+    let trailers = response.trailers.take();
+    let framing_header = match &trailers {
+        Some((names, _)) if !names.is_empty() => format!(
+            "transfer-encoding: chunked\r\ntrailer: {}",
+            names
+                .iter()
+                .map(HttpHeaderName::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        _ => "transfer-encoding: chunked".to_string(),
+    };
+    writer.write_all(head_to_string(&response, Some(&framing_header)).as_bytes())?;
+    let mut buffer = [0; 8192];
+    loop {
+        let bytes_read = match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        write!(writer, "{:x}\r\n", bytes_read)?;
+        writer.write_all(&buffer[..bytes_read])?;
+        writer.write_all(b"\r\n")?;
+    }
+    writer.write_all(b"0\r\n")?;
+    if let Some((_, trailers)) = trailers {
+        // trailers are computed after the response was validated, so invalid
+        // ones are dropped rather than written into the stream
+        for (name, value) in trailers() {
+            if is_header_name(name.as_str()) && is_header_value(&value) {
+                write!(writer, "{}: {}\r\n", name.as_str(), value)?;
+            }
+        }
+    }
+    writer.write_all(b"\r\n")
+}
+
+/// Returns true if `name` is a token, as header names have to be.
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Returns true if `value` has no control characters other than tab, which
+/// could end the header line early.
+fn is_header_value(value: &str) -> bool {
+    !value.bytes().any(|b| (b < b' ' && b != b'\t') || b == 0x7f)
+}
+
 impl From<&str> for Response {
     fn from(s: &str) -> Self {
         let mut r = Response::default();
@@ -135,14 +1273,34 @@ where
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
+    /// Serializes a response with a text body for comparing it.
+    fn http_string(response: Response) -> String {
+        String::from_utf8(response_into_http_response_bytes(response)).unwrap()
+    }
+
     #[test]
     fn test_default_response() {
         let response = Response::default();
         assert_eq!(response.status_code, HttpStatusCode::OK);
-        assert_eq!(response.body, "");
+        assert_eq!(response.body, b"");
+        assert_eq!(response.headers.len(), 0);
+    }
+
+    #[test]
+    fn test_empty_constructors() {
+        let response = Response::empty();
+        assert_eq!(response.status_code, HttpStatusCode::OK);
+        assert_eq!(response.body, b"");
+        assert_eq!(response.headers.len(), 0);
+        let response = Response::ok();
+        assert_eq!(response.status_code, HttpStatusCode::OK);
+        assert_eq!(response.body, b"");
+        let response = Response::with_status(HttpStatusCode::NotFound);
+        assert_eq!(response.status_code, HttpStatusCode::NotFound);
+        assert_eq!(response.body, b"");
         assert_eq!(response.headers.len(), 0);
     }
 
@@ -155,6 +1313,54 @@ mod tests {
         assert_eq!(response.status_code, HttpStatusCode::BadRequest);
     }
 
+    #[test]
+    fn test_status_from_number() {
+        let response = Response::status(201).unwrap();
+        assert_eq!(response.status_code, HttpStatusCode::Created);
+        let response = Response::status(404).unwrap();
+        assert_eq!(response.status_code, HttpStatusCode::NotFound);
+        let response = Response::status(299).unwrap();
+        assert_eq!(response.status_code, HttpStatusCode::Custom(299, ""));
+        assert!(http_string(response).starts_with("HTTP/1.1 299 \r\n"));
+        assert_eq!(Response::status(99).err(), Some(UnknownStatus(99)));
+        assert_eq!(Response::status(600).err(), Some(UnknownStatus(600)));
+        assert_eq!(HttpStatusCode::try_from(299), Err(UnknownStatus(299)));
+        let response = Response::status(409).unwrap();
+        assert_eq!(
+            response.status_code,
+            HttpStatusCode::Custom(409, "Conflict")
+        );
+    }
+
+    #[test]
+    fn test_reason_phrases() {
+        let response = Response::with_status(HttpStatusCode::NotFound);
+        assert!(http_string(response).starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = Response::with_status(HttpStatusCode::TooManyRequests);
+        assert!(http_string(response).starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        let response = Response::status(418).unwrap();
+        assert!(http_string(response).starts_with("HTTP/1.1 418 I'm a teapot\r\n"));
+        for code in [
+            200, 201, 204, 206, 301, 302, 304, 400, 401, 403, 404, 405, 408, 415, 416, 429, 500,
+            502, 503,
+        ] {
+            let status = HttpStatusCode::try_from(code).unwrap();
+            assert_eq!(usize::from(status), code as usize);
+            assert!(!status.reason_phrase().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_set_raw_status() {
+        let mut response = Response::default();
+        response.set_raw_status(418, "I'm a teapot");
+        assert_eq!(
+            <HttpStatusCode as Into<usize>>::into(response.status_code),
+            418
+        );
+        assert!(http_string(response).starts_with("HTTP/1.1 418 I'm a teapot\r\n"));
+    }
+
     #[test]
     fn test_set_header() {
         let mut response = Response::default();
@@ -173,7 +1379,7 @@ mod tests {
     fn test_set_body() {
         let mut response = Response::default();
         response.set_body("body");
-        assert_eq!(response.body, "body");
+        assert_eq!(response.body, b"body");
         assert_eq!(response.headers.len(), 0);
     }
 
@@ -181,14 +1387,14 @@ mod tests {
     fn test_set_json_and_html() {
         let mut response = Response::default();
         response.set_json("json");
-        assert_eq!(response.body, "json");
+        assert_eq!(response.body, b"json");
         assert_eq!(response.headers.len(), 1);
         assert_eq!(
             response.headers.get(&HttpHeaderName::ContentType).unwrap(),
             "application/json"
         );
         response.set_html("html");
-        assert_eq!(response.body, "html");
+        assert_eq!(response.body, b"html");
         assert_eq!(response.headers.len(), 1);
         assert_eq!(
             response.headers.get(&HttpHeaderName::ContentType).unwrap(),
@@ -197,21 +1403,618 @@ mod tests {
     }
 
     #[test]
-    fn test_into_http_response_string() {
+    fn test_into_http_response_bytes() {
         let mut response = Response::default();
         response.set_html("test");
-        let should_be = "HTTP/1.1 200 OK\ncontent-type: text/html\ncontent-length: 4\n\ntest";
-        assert_eq!(response_into_http_response_string(response), should_be);
+        let should_be =
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 4\r\n\r\ntest";
+        assert_eq!(http_string(response), should_be);
+    }
+
+    #[test]
+    fn test_into_http_response_bytes_without_headers() {
+        let mut response = Response::default();
+        response.set_body("test");
+        assert_eq!(
+            http_string(response),
+            "HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\ntest"
+        );
+        let response = Response::with_status(HttpStatusCode::NotModified);
+        assert_eq!(http_string(response), "HTTP/1.1 304 Not Modified\r\n\r\n");
+    }
+
+    #[test]
+    fn test_content_length_counts_utf8_bytes() {
+        let body = "héllo 🎉";
+        assert_eq!(body.chars().count(), 7);
+        let mut response = Response::default();
+        response.set_body(body);
+        let serialized = http_string(response);
+        assert_eq!(
+            serialized,
+            format!("HTTP/1.1 200 OK\r\ncontent-length: 11\r\n\r\n{}", body)
+        );
+    }
+
+    #[test]
+    fn test_binary_body() {
+        let bytes = [0x89, b'P', 0xFF, 0x00, b'\r', b'\n'];
+        let mut response = Response::default();
+        response.set_body_bytes(&bytes);
+        assert!(response.has_body());
+        let serialized = response_into_http_response_bytes(response);
+        let head = b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\n";
+        assert_eq!(&serialized[..head.len()], head);
+        assert_eq!(&serialized[head.len()..], bytes);
+
+        let mut response = Response::default();
+        response.set_body_bytes(&bytes);
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        assert_eq!(&written[head.len()..], bytes);
+    }
+
+    #[test]
+    fn test_redirect() {
+        let response = Response::redirect("/login", false);
+        assert_eq!(response.status_code(), HttpStatusCode::Found);
+        assert_eq!(
+            http_string(response),
+            "HTTP/1.1 302 Found\r\nlocation: /login\r\ncontent-length: 0\r\n\r\n"
+        );
+        let response = Response::redirect("https://example.com/new", true);
+        let serialized = http_string(response);
+        assert!(serialized.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(serialized.contains("\r\nlocation: https://example.com/new\r\n"));
+    }
+
+    #[test]
+    fn test_debug_redacts_sensitive_headers() {
+        let mut response = Response::from("<h1>Hi</h1>");
+        response.set_cookie("session", "s3cr3t", CookieOptions::default());
+        response.set_header(
+            HttpHeaderName::Custom("authorization".to_string()),
+            "Bearer t0ken",
+        );
+        let debug = format!("{:?}", response);
+        assert!(!debug.contains("s3cr3t"), "{}", debug);
+        assert!(!debug.contains("t0ken"), "{}", debug);
+        assert!(
+            debug.contains(r#"("set-cookie", "<redacted>")"#),
+            "{}",
+            debug
+        );
+        assert!(
+            debug.contains(r#"("content-type", "text/html")"#),
+            "{}",
+            debug
+        );
+        assert!(debug.contains("status_code: OK"), "{}", debug);
+        assert!(debug.contains("body: 11 bytes"), "{}", debug);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut response = Response::from("body");
+        response.append_header(HttpHeaderName::SetCookie, "a=1");
+        response.set_header(HttpHeaderName::Custom("x-tab".to_string()), "a\tb");
+        assert_eq!(response.validate(), Ok(()));
+
+        let mut injected = Response::from("body");
+        injected.append_header(HttpHeaderName::SetCookie, "a=1\r\nx-evil: 1");
+        assert_eq!(
+            injected.validate(),
+            Err(ResponseError::InvalidHeaderValue("set-cookie".to_string()))
+        );
+        let mut name = Response::from("body");
+        name.set_header(HttpHeaderName::Custom("x bad".to_string()), "1");
+        assert_eq!(
+            name.validate(),
+            Err(ResponseError::InvalidHeaderName("x bad".to_string()))
+        );
+
+        response.set_header(HttpHeaderName::ContentLength, 4);
+        assert_eq!(response.validate(), Ok(()));
+        response.set_header(HttpHeaderName::ContentLength, 5);
+        let mismatch = response.validate().unwrap_err();
+        assert_eq!(
+            mismatch.to_string(),
+            "content-length 5 set for a body of 4 bytes"
+        );
+        response.set_not_modified();
+        assert_eq!(response.validate(), Ok(()));
+        let mut stream = Response::from_reader("text/plain", &b"chunked"[..]);
+        stream.set_header(HttpHeaderName::ContentLength, 7);
+        assert!(matches!(
+            stream.validate(),
+            Err(ResponseError::ContentLengthMismatch { actual: None, .. })
+        ));
+    }
+
+    #[test]
+    fn test_upgrade_required() {
+        let response = Response::upgrade_required(&["websocket", "h2c"]);
+        let serialized = http_string(response);
+        assert!(serialized.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(serialized.contains("\r\nupgrade: websocket, h2c\r\n"));
+        assert!(serialized.contains("\r\nconnection: upgrade\r\n"));
+        assert_eq!(
+            HttpStatusCode::try_from(426),
+            Ok(HttpStatusCode::UpgradeRequired)
+        );
+        assert_eq!(HttpHeaderName::from("Upgrade"), HttpHeaderName::Upgrade);
+    }
+
+    #[test]
+    fn test_head_only() {
+        let mut response: Response = "test".into();
+        response.set_head_only();
+        assert_eq!(
+            http_string(response),
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 4\r\n\r\n"
+        );
+        let mut response = Response::from_reader("text/plain", &b"stream"[..]);
+        response.set_head_only();
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        assert!(written.ends_with(b"transfer-encoding: chunked\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_no_lone_line_feeds() {
+        let mut response = Response::with_status(HttpStatusCode::NotFound);
+        response.set_html("not found");
+        response.set_header(HttpHeaderName::CacheControl, "no-cache");
+        response.append_header(HttpHeaderName::SetCookie, "a=1");
+        let serialized = http_string(response);
+        let bytes = serialized.as_bytes();
+        for (i, byte) in bytes.iter().enumerate() {
+            if *byte == b'\n' {
+                assert!(
+                    i > 0 && bytes[i - 1] == b'\r',
+                    "lone LF at {} in {:?}",
+                    i,
+                    serialized
+                );
+            }
+        }
+        assert!(serialized.ends_with("\r\n\r\nnot found"));
+    }
+
+    /// Decodes a chunked body, returning the concatenated data.
+    pub(crate) fn decode_chunked(mut body: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        loop {
+            let line_end = body.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size_line = std::str::from_utf8(&body[..line_end]).unwrap();
+            let size = usize::from_str_radix(size_line, 16).unwrap();
+            body = &body[line_end + 2..];
+            if size == 0 {
+                return data;
+            }
+            data.extend_from_slice(&body[..size]);
+            assert_eq!(&body[size..size + 2], b"\r\n");
+            body = &body[size + 2..];
+        }
+    }
+
+    #[test]
+    fn test_write_streamed_response() {
+        let content: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        let mut response = Response::default();
+        response.set_header(HttpHeaderName::ContentType, "application/octet-stream");
+        response.set_stream(std::io::Cursor::new(content.clone()));
+
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        let head_end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("transfer-encoding: chunked"));
+        assert!(head.contains("content-type: application/octet-stream"));
+        assert_eq!(decode_chunked(&written[head_end + 4..]), content);
+    }
+
+    #[test]
+    fn test_write_trailers() {
+        use std::sync::{Arc, Mutex};
+
+        /// Sums up the bytes read through it.
+        struct SummingReader<R>(R, Arc<Mutex<u32>>);
+
+        impl<R: Read> Read for SummingReader<R> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.read(buf)?;
+                *self.1.lock().unwrap() += buf[..n].iter().map(|&b| b as u32).sum::<u32>();
+                Ok(n)
+            }
+        }
+
+        let sum = Arc::new(Mutex::new(0));
+        let reader = SummingReader(std::io::Cursor::new(b"abc".to_vec()), Arc::clone(&sum));
+        let checksum = HttpHeaderName::Custom("x-checksum".to_string());
+        let response = Response::from_reader("text/plain", reader)
+            .with_trailers([checksum.clone()], move || {
+                vec![(checksum, sum.lock().unwrap().to_string())]
+            });
+
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        let head_end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+        assert!(head.contains("\r\ntransfer-encoding: chunked\r\ntrailer: x-checksum"));
+        let body = &written[head_end + 4..];
+        assert_eq!(decode_chunked(body), b"abc");
+        assert!(body.ends_with(b"\r\n0\r\nx-checksum: 294\r\n\r\n"));
+
+        // invalid trailers can't inject lines after the body
+        let checksum = HttpHeaderName::Custom("x-checksum".to_string());
+        let response = Response::from_reader("text/plain", &b"abc"[..]).with_trailers(
+            [checksum.clone()],
+            move || {
+                vec![
+                    (checksum, "1\r\nset-cookie: a=b".to_string()),
+                    (HttpHeaderName::Custom("x y".to_string()), "1".to_string()),
+                    (HttpHeaderName::ETag, "\"ok\"".to_string()),
+                ]
+            },
+        );
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.ends_with("\r\n0\r\netag: \"ok\"\r\n\r\n"));
+        assert!(!written.contains("set-cookie"));
+
+        // trailers need a chunked body
+        let response = Response::from("abc").with_trailers([HttpHeaderName::ETag], || {
+            vec![(HttpHeaderName::ETag, "\"x\"".to_string())]
+        });
+        let serialized = http_string(response);
+        assert!(!serialized.contains("trailer"));
+        assert!(serialized.ends_with("\r\n\r\nabc"));
+    }
+
+    /// A reader of unknown length, which hands out its data in small pieces.
+    struct TrickleReader {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for TrickleReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = (self.data.len() - self.pos).min(buf.len()).min(7);
+            buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let data = b"streamed from a reader of unknown length".to_vec();
+        let response = Response::from_reader(
+            "text/plain",
+            TrickleReader {
+                data: data.clone(),
+                pos: 0,
+            },
+        );
+        assert_eq!(
+            response.headers.get(&HttpHeaderName::ContentType).unwrap(),
+            "text/plain"
+        );
+
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        let head_end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+        assert!(head.contains("transfer-encoding: chunked"));
+        assert_eq!(decode_chunked(&written[head_end + 4..]), data);
+    }
+
+    #[test]
+    fn test_stream() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let response = Response::stream(TrickleReader {
+            data: data.clone(),
+            pos: 0,
+        });
+        assert!(response.headers.is_empty());
+
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        let head_end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+        assert!(head.contains("transfer-encoding: chunked"));
+        assert!(!head.contains("content-length"));
+        let body = &written[head_end + 4..];
+        assert!(body.ends_with(b"\r\n0\r\n\r\n"));
+        assert_eq!(decode_chunked(body), data);
+    }
+
+    #[test]
+    fn test_from_static() {
+        static IMAGE: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff];
+        let response = Response::from_static("image/png", IMAGE);
+        assert_eq!(
+            response.headers.get(&HttpHeaderName::ContentType).unwrap(),
+            "image/png"
+        );
+
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        let head_end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+        assert!(head.contains("content-length: 10"));
+        assert_eq!(&written[head_end + 4..], IMAGE);
+
+        let mut response = Response::from_static("image/png", IMAGE);
+        response.set_body("replaced");
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        assert!(written.ends_with(b"content-length: 8\r\n\r\nreplaced"));
+    }
+
+    #[test]
+    fn test_no_cache() {
+        let mut response = Response::from("secret");
+        response.set_header(HttpHeaderName::CacheControl, "max-age=60");
+        response.no_cache();
+        assert_eq!(
+            response.header(HttpHeaderName::CacheControl),
+            Some("no-store, no-cache, must-revalidate")
+        );
+        assert_eq!(response.header(HttpHeaderName::Pragma), Some("no-cache"));
+        assert_eq!(http_string(response).matches("cache-control").count(), 1);
+    }
+
+    #[test]
+    fn test_with_validators() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+        let response = Response::default().with_validators("abc", Some(time));
+        assert_eq!(response.header(HttpHeaderName::ETag), Some("\"abc\""));
+        assert_eq!(
+            response.header(HttpHeaderName::LastModified),
+            Some("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+        let response = Response::default().with_validators("W/\"abc\"", None);
+        assert_eq!(response.header(HttpHeaderName::ETag), Some("W/\"abc\""));
+        assert_eq!(response.header(HttpHeaderName::LastModified), None);
+    }
+
+    #[test]
+    fn test_not_modified_has_no_body() {
+        let mut response = Response::from("body").with_validators("abc", None);
+        response.set_not_modified();
+        let serialized = http_string(response);
+        assert!(serialized.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(serialized.contains("etag: \"abc\""));
+        assert!(!serialized.contains("content-length"));
+        assert!(serialized.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_write_sized_stream() {
+        let mut response = Response::default();
+        response.set_sized_stream(&b"exactly this, not more"[..], 12);
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        assert!(written.ends_with(b"content-length: 12\r\n\r\nexactly this"));
+
+        let mut response = Response::default();
+        response.set_sized_stream(&b"short"[..], 12);
+        assert!(write_response(response, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_chunked_response_omits_content_length() {
+        let response = Response::from_reader("text/plain", &b"chunked"[..]);
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("transfer-encoding: chunked\r\n"));
+        assert!(!written.contains("content-length"));
+    }
+
+    #[test]
+    fn test_framing_headers_are_not_duplicated() {
+        let mut response: Response = "body".into();
+        response.set_header(HttpHeaderName::ContentLength, 100);
+        response.set_header(HttpHeaderName::TransferEncoding, "chunked");
+        let serialized = http_string(response);
+        assert_eq!(serialized.matches("content-length").count(), 1);
+        assert!(serialized.contains("content-length: 4"));
+        assert!(!serialized.contains("transfer-encoding"));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "must not set content-length")]
+    fn test_chunked_response_with_content_length_is_rejected() {
+        let mut response = Response::from_reader("text/plain", &b"chunked"[..]);
+        response.set_header(HttpHeaderName::ContentLength, 7);
+        let _ = write_response(response, &mut Vec::new());
+    }
+
+    #[test]
+    fn test_write_response_without_stream() {
+        let mut written = Vec::new();
+        write_response("test".into(), &mut written).unwrap();
+        assert_eq!(
+            written,
+            b"HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 4\r\n\r\ntest"
+        );
     }
 
     #[test]
     fn test_response_from_str() {
         let response: Response = "test".into();
-        assert_eq!(response.body, "test");
+        assert_eq!(response.body, b"test");
+        assert_eq!(
+            response.headers.get(&HttpHeaderName::ContentType).unwrap(),
+            "text/html"
+        );
+        assert_eq!(response.status_code, HttpStatusCode::OK);
+    }
+
+    #[test]
+    fn test_appended_headers() {
+        let mut response = Response::default();
+        response.set_header(HttpHeaderName::SetCookie, "a=1");
+        response.append_header(HttpHeaderName::SetCookie, "b=2");
+        assert_eq!(response.header(HttpHeaderName::SetCookie), Some("a=1"));
+        assert_eq!(
+            response.header_values(HttpHeaderName::SetCookie),
+            ["a=1", "b=2"]
+        );
+        let http = http_string(response);
+        assert!(http.contains("set-cookie: a=1\r\n"));
+        assert!(http.contains("set-cookie: b=2\r\n"));
+    }
+
+    #[test]
+    fn test_try_body() {
+        let response = Response::try_body(
+            Ok::<_, String>("done".to_string()),
+            HttpStatusCode::BadRequest,
+        );
+        assert_eq!(response.status_code, HttpStatusCode::OK);
+        assert_eq!(response.body, b"done");
+
+        let response = Response::try_body(Err("no template"), HttpStatusCode::InternalServerError);
+        assert_eq!(response.status_code, HttpStatusCode::InternalServerError);
+        assert_eq!(response.body, b"no template");
+        assert_eq!(
+            response.header(HttpHeaderName::ContentType),
+            Some("text/plain; charset=utf-8")
+        );
+
+        let response = Response::try_body(
+            Err("unknown page <script>alert(1)</script>"),
+            HttpStatusCode::NotFound,
+        );
+        let http = http_string(response);
+        assert!(http.contains("\r\ncontent-type: text/plain; charset=utf-8\r\n"));
+        assert!(http.ends_with("\r\n\r\nunknown page <script>alert(1)</script>"));
+    }
+
+    #[test]
+    fn test_set_cookie() {
+        let mut response = Response::default();
+        let options = CookieOptions {
+            path: Some("/".to_string()),
+            http_only: true,
+            ..Default::default()
+        };
+        response.set_cookie("session", "abc", options);
+        response.set_cookie("theme", "dark", CookieOptions::default());
+        let http = http_string(response);
+        assert!(http.contains("set-cookie: session=abc; Path=/; HttpOnly\r\n"));
+        assert!(http.contains("set-cookie: theme=dark\r\n"));
+    }
+
+    #[test]
+    fn test_builder_chain() {
+        let response = Response::new()
+            .with_status_code(HttpStatusCode::NotFound)
+            .with_header(HttpHeaderName::CacheControl, "no-store")
+            .with_html("nope");
+        assert_eq!(response.status_code, HttpStatusCode::NotFound);
+        assert_eq!(response.body, b"nope");
+        assert_eq!(response.headers.len(), 2);
         assert_eq!(
             response.headers.get(&HttpHeaderName::ContentType).unwrap(),
             "text/html"
         );
+        assert_eq!(
+            response.headers.get(&HttpHeaderName::CacheControl).unwrap(),
+            "no-store"
+        );
+
+        let response = Response::new().with_html("<p></p>").with_json("{}");
+        assert_eq!(
+            response.headers.get(&HttpHeaderName::ContentType).unwrap(),
+            "application/json"
+        );
+        let response = Response::new().with_body("raw");
+        assert_eq!(response.body, b"raw");
+        assert_eq!(response.headers.len(), 0);
         assert_eq!(response.status_code, HttpStatusCode::OK);
     }
+
+    #[test]
+    fn test_map_status() {
+        // stands in for response middleware, which gets the response by value
+        fn no_content_if_empty(response: Response) -> Response {
+            let empty = !response.has_body();
+            response.map_status(|status| match status {
+                HttpStatusCode::OK if empty => HttpStatusCode::NoContent,
+                other => other,
+            })
+        }
+
+        let response = no_content_if_empty(Response::ok());
+        assert_eq!(response.status_code(), HttpStatusCode::NoContent);
+        assert_eq!(http_string(response), "HTTP/1.1 204 No Content\r\n\r\n");
+        let response = no_content_if_empty("content".into());
+        assert_eq!(response.status_code(), HttpStatusCode::OK);
+        let response = no_content_if_empty(Response::with_status(HttpStatusCode::NotFound));
+        assert_eq!(response.status_code(), HttpStatusCode::NotFound);
+
+        let mut response = no_content_if_empty(Response::from_static("text/plain", b""));
+        assert_eq!(response.status_code(), HttpStatusCode::OK);
+        response.set_status_code(HttpStatusCode::Created);
+        assert_eq!(response.status_code(), HttpStatusCode::Created);
+        assert_eq!(HttpStatusCode::try_from(204), Ok(HttpStatusCode::NoContent));
+    }
+
+    #[test]
+    fn test_known_and_custom_headers() {
+        let mut response = Response::default();
+        response.set_header(HttpHeaderName::AccessControlAllowOrigin, "*");
+        response.set_header(HttpHeaderName::Custom("x-request-id".to_string()), "42");
+        response.set_html("test");
+        assert_eq!(
+            response.header(HttpHeaderName::Custom("x-request-id".to_string())),
+            Some("42")
+        );
+        let http = http_string(response);
+        assert!(http.contains("\r\naccess-control-allow-origin: *\r\n"));
+        assert!(http.contains("\r\nx-request-id: 42\r\n"));
+        assert!(http.contains("\r\ncontent-type: text/html\r\n"));
+
+        assert_eq!(
+            HttpHeaderName::from("Set-Cookie"),
+            HttpHeaderName::SetCookie
+        );
+        assert_eq!(
+            HttpHeaderName::from("CONTENT-TYPE"),
+            HttpHeaderName::ContentType
+        );
+        assert_eq!(
+            HttpHeaderName::from("X-Unknown"),
+            HttpHeaderName::Custom("x-unknown".to_string())
+        );
+        for name in KNOWN_HEADER_NAMES {
+            assert_eq!(HttpHeaderName::from(name.as_str()), name);
+        }
+    }
+
+    #[test]
+    fn test_set_file() {
+        let path = std::env::temp_dir().join(format!("rfs-set-file-{}.css", std::process::id()));
+        std::fs::write(&path, "body { color: red }").unwrap();
+        let mut response = Response::default();
+        response.set_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            response.header(HttpHeaderName::ContentType),
+            Some("text/css")
+        );
+        let mut written = Vec::new();
+        write_response(response, &mut written).unwrap();
+        assert!(written.ends_with(b"\r\ncontent-length: 19\r\n\r\nbody { color: red }"));
+
+        assert!(Response::default().set_file(&path).is_err());
+    }
 }
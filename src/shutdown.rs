@@ -0,0 +1,99 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Stops a running [Server](crate::Server) from another thread or switches
+/// its maintenance mode, see [Server::handle](crate::Server::handle).
+/// Handles can be cloned and sent to other threads freely.
+#[derive(Debug, Clone, Default)]
+pub struct ServerHandle {
+    state: Arc<HandleState>,
+}
+
+#[derive(Debug, Default)]
+struct HandleState {
+    stopping: AtomicBool,
+    address: Mutex<Option<SocketAddr>>,
+    /// The `Retry-After` delay while in maintenance mode.
+    maintenance: Mutex<Option<Duration>>,
+}
+
+impl ServerHandle {
+    /// Makes the server stop accepting connections. Connections which are
+    /// being handled are finished first, then `bind_and_run` returns.
+    ///
+    /// Calling this before the server runs makes it stop right away once it
+    /// has bound its address.
+    pub fn shutdown(&self) {
+        self.state.stopping.store(true, Ordering::SeqCst);
+        // a connection of our own wakes up the accept loop
+        if let Some(address) = self.local_addr() {
+            let _ = TcpStream::connect_timeout(&reachable(address), Duration::from_secs(1));
+        }
+    }
+
+    /// Returns the address the server is listening on, or None if it isn't
+    /// running. Useful if it was bound to port 0 to let the OS pick a port.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.state.address.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Switches maintenance mode on or off while the server runs, see
+    /// [Server::set_maintenance_mode](crate::Server::set_maintenance_mode).
+    pub fn set_maintenance_mode(&self, enabled: bool, retry_after: Duration) {
+        *self
+            .state
+            .maintenance
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = enabled.then_some(retry_after);
+    }
+
+    /// Returns the `Retry-After` delay if the server is in maintenance mode.
+    pub(crate) fn maintenance(&self) -> Option<Duration> {
+        *self
+            .state
+            .maintenance
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.state.stopping.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_local_addr(&self, address: Option<SocketAddr>) {
+        *self.state.address.lock().unwrap_or_else(|e| e.into_inner()) = address;
+    }
+}
+
+/// Replaces a wildcard address like `0.0.0.0` by loopback, which can be
+/// connected to.
+fn reachable(mut address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => address.set_ip(Ipv4Addr::LOCALHOST.into()),
+        IpAddr::V6(ip) if ip.is_unspecified() => address.set_ip(Ipv6Addr::LOCALHOST.into()),
+        _ => {}
+    }
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_shutdown_wakes_up_accept() {
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let handle = ServerHandle::default();
+        handle.set_local_addr(listener.local_addr().ok());
+        assert!(!handle.is_stopping());
+
+        handle.shutdown();
+        assert!(handle.is_stopping());
+        // the wake-up connection is waiting to be accepted
+        let (stream, _) = listener.accept().unwrap();
+        assert!(stream.peer_addr().unwrap().ip().is_loopback());
+    }
+}
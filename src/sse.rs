@@ -0,0 +1,71 @@
+use std::io::{self, Read};
+use std::sync::mpsc::Receiver;
+
+/// The body of [Response::sse_from_channel](crate::Response::sse_from_channel):
+/// formats each received message as a server-sent event, blocking until the
+/// next one arrives. The stream ends once all senders are dropped.
+pub struct ChannelEvents {
+    receiver: Receiver<String>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl ChannelEvents {
+    pub fn new(receiver: Receiver<String>) -> Self {
+        Self {
+            receiver,
+            pending: Vec::new(),
+            pending_pos: 0,
+        }
+    }
+}
+
+/// Formats `message` as an event with a `data:` line per line of it.
+pub fn format_event(message: &str) -> String {
+    let mut event = String::new();
+    for line in message.split('\n') {
+        event.push_str("data: ");
+        event.push_str(line.strip_suffix('\r').unwrap_or(line));
+        event.push('\n');
+    }
+    event.push('\n');
+    event
+}
+
+impl Read for ChannelEvents {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending_pos >= self.pending.len() {
+            match self.receiver.recv() {
+                Ok(message) => {
+                    self.pending = format_event(&message).into_bytes();
+                    self.pending_pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let available = &self.pending[self.pending_pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pending_pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_channel_events() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send("one".to_string()).unwrap();
+        sender.send("two\r\nlines".to_string()).unwrap();
+        drop(sender);
+        let mut events = String::new();
+        ChannelEvents::new(receiver)
+            .read_to_string(&mut events)
+            .unwrap();
+        assert_eq!(events, "data: one\n\ndata: two\ndata: lines\n\n");
+    }
+}
@@ -0,0 +1,84 @@
+use crate::response::HttpStatusCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A summary of the work a [Server](crate::Server) has done, see
+/// [Server::stats](crate::Server::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    /// The number of connections which were handled, i.e. not refused.
+    pub connections: u64,
+    /// The number of responses which were sent, including error responses
+    /// for malformed requests.
+    pub requests: u64,
+    /// The number of responses by status class: the first element counts
+    /// `1xx` responses, the last one `5xx` responses.
+    pub responses_by_class: [u64; 5],
+}
+
+impl ServerStats {
+    /// Returns the number of responses in the status class `class`, e.g. 4
+    /// for all `4xx` responses. Classes outside of 1 to 5 have none.
+    pub fn responses_in_class(&self, class: u16) -> u64 {
+        match class {
+            1..=5 => self.responses_by_class[class as usize - 1],
+            _ => 0,
+        }
+    }
+}
+
+/// Counts connections and responses. Shared by the worker threads.
+#[derive(Default)]
+pub struct StatsRecorder {
+    connections: AtomicU64,
+    responses_by_class: [AtomicU64; 5],
+}
+
+impl StatsRecorder {
+    pub fn record_connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_response(&self, status: HttpStatusCode) {
+        let code = usize::from(status);
+        if let Some(counter) = (code / 100)
+            .checked_sub(1)
+            .and_then(|index| self.responses_by_class.get(index))
+        {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> ServerStats {
+        let responses_by_class = self
+            .responses_by_class
+            .each_ref()
+            .map(|counter| counter.load(Ordering::Relaxed));
+        ServerStats {
+            connections: self.connections.load(Ordering::Relaxed),
+            requests: responses_by_class.iter().sum(),
+            responses_by_class,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_recorder() {
+        let recorder = StatsRecorder::default();
+        recorder.record_connection();
+        recorder.record_response(HttpStatusCode::OK);
+        recorder.record_response(HttpStatusCode::NotFound);
+        recorder.record_response(HttpStatusCode::Custom(418, "I'm a teapot"));
+        recorder.record_response(HttpStatusCode::Custom(99, ""));
+        let stats = recorder.snapshot();
+        assert_eq!(stats.connections, 1);
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.responses_by_class, [0, 1, 0, 2, 0]);
+        assert_eq!(stats.responses_in_class(4), 2);
+        assert_eq!(stats.responses_in_class(0), 0);
+        assert_eq!(stats.responses_in_class(6), 0);
+    }
+}
@@ -0,0 +1,44 @@
+//! Helpers for testing applications built on the server. Requires the
+//! `test-support` feature.
+
+use std::net::{SocketAddr, TcpListener};
+
+/// Returns a loopback address with a port which was free a moment ago, for
+/// starting a server in a test without hard-coding a port.
+///
+/// The port is found by binding to port 0 and letting the OS pick one. It's
+/// released again before returning, so it may in theory be taken by someone
+/// else before the server binds to it.
+/// ```
+/// use raspi_file_server::test_support::free_loopback_addr;
+///
+/// let address = free_loopback_addr();
+/// assert!(address.ip().is_loopback());
+/// assert_ne!(address.port(), 0);
+/// ```
+pub fn free_loopback_addr() -> SocketAddr {
+    TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| listener.local_addr())
+        .expect("failed to bind to a free loopback port")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_loopback_addr() {
+        let first = free_loopback_addr();
+        assert!(first.ip().is_loopback());
+        let listener = TcpListener::bind(first).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), first);
+
+        // while the first port is taken, a different one is found
+        let second = free_loopback_addr();
+        assert!(second.ip().is_loopback());
+        assert_ne!(first, second);
+        drop(listener);
+        let listener = TcpListener::bind(second).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), second);
+    }
+}
@@ -0,0 +1,368 @@
+use crate::httpdate::civil_from_days;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const VERSION: u16 = 20;
+// bit 3: sizes and crc follow the data, bit 11: names are UTF-8
+const FLAGS: u16 = 0x0808;
+const METHOD_STORED: u16 = 0;
+
+/// A file which should be added to a [ZipStream].
+pub struct ZipEntry {
+    /// The name inside of the archive, using `/` as separator.
+    pub name: String,
+    /// The location of the file on disk.
+    pub path: PathBuf,
+}
+
+struct CurrentFile {
+    file: File,
+    crc: Crc32,
+    size: u64,
+    header_offset: u64,
+    dos_time: (u16, u16),
+}
+
+/// A ZIP archive which is generated while it is being read.
+///
+/// Files are only opened when the archive reaches them and are copied in
+/// small pieces, so the whole archive never has to be held in memory. Entries
+/// are stored uncompressed and the format is limited to 4 GiB per file and
+/// for the whole archive (no ZIP64).
+pub struct ZipStream {
+    entries: std::vec::IntoIter<ZipEntry>,
+    current: Option<(ZipEntry, CurrentFile)>,
+    central_directory: Vec<u8>,
+    entry_count: u16,
+    pending: Vec<u8>,
+    pending_pos: usize,
+    offset: u64,
+    finished: bool,
+}
+
+impl ZipStream {
+    pub fn new(entries: Vec<ZipEntry>) -> Self {
+        Self {
+            entries: entries.into_iter(),
+            current: None,
+            central_directory: Vec::new(),
+            entry_count: 0,
+            pending: Vec::new(),
+            pending_pos: 0,
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Fills `pending` with the next part of the archive. Returns false once
+    /// everything was produced.
+    fn advance(&mut self) -> io::Result<bool> {
+        self.pending.clear();
+        self.pending_pos = 0;
+
+        if let Some((_, current)) = &mut self.current {
+            let mut buffer = [0; 8192];
+            let bytes_read = current.file.read(&mut buffer)?;
+            if bytes_read > 0 {
+                current.crc.update(&buffer[..bytes_read]);
+                current.size += bytes_read as u64;
+                self.pending.extend_from_slice(&buffer[..bytes_read]);
+            } else {
+                let (entry, current) = self.current.take().unwrap();
+                self.finish_entry(&entry, current)?;
+            }
+        } else if let Some(entry) = self.entries.next() {
+            self.start_entry(entry)?;
+        } else if !self.finished {
+            self.finish_archive()?;
+            self.finished = true;
+        } else {
+            return Ok(false);
+        }
+
+        self.offset += self.pending.len() as u64;
+        Ok(true)
+    }
+
+    fn start_entry(&mut self, entry: ZipEntry) -> io::Result<()> {
+        if self.entry_count == u16::MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "zip archive exceeds 65535 entries",
+            ));
+        }
+        let name_length = name_length(&entry.name)?;
+        let file = File::open(&entry.path)?;
+        let modified = file.metadata()?.modified().unwrap_or(UNIX_EPOCH);
+        let dos_time = dos_date_time(modified);
+        let header = &mut self.pending;
+        put_u32(header, LOCAL_HEADER_SIGNATURE);
+        put_u16(header, VERSION);
+        put_u16(header, FLAGS);
+        put_u16(header, METHOD_STORED);
+        put_u16(header, dos_time.0);
+        put_u16(header, dos_time.1);
+        put_u32(header, 0); // crc, see data descriptor
+        put_u32(header, 0); // compressed size
+        put_u32(header, 0); // uncompressed size
+        put_u16(header, name_length);
+        put_u16(header, 0); // extra field length
+        header.extend_from_slice(entry.name.as_bytes());
+
+        let current = CurrentFile {
+            file,
+            crc: Crc32::new(),
+            size: 0,
+            header_offset: self.offset,
+            dos_time,
+        };
+        self.current = Some((entry, current));
+        Ok(())
+    }
+
+    fn finish_entry(&mut self, entry: &ZipEntry, current: CurrentFile) -> io::Result<()> {
+        let size = to_u32(current.size)?;
+        let header_offset = to_u32(current.header_offset)?;
+        let crc = current.crc.finish();
+
+        put_u32(&mut self.pending, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut self.pending, crc);
+        put_u32(&mut self.pending, size);
+        put_u32(&mut self.pending, size);
+
+        let record = &mut self.central_directory;
+        put_u32(record, CENTRAL_HEADER_SIGNATURE);
+        put_u16(record, VERSION); // version made by
+        put_u16(record, VERSION); // version needed to extract
+        put_u16(record, FLAGS);
+        put_u16(record, METHOD_STORED);
+        put_u16(record, current.dos_time.0);
+        put_u16(record, current.dos_time.1);
+        put_u32(record, crc);
+        put_u32(record, size);
+        put_u32(record, size);
+        put_u16(record, name_length(&entry.name)?);
+        put_u16(record, 0); // extra field length
+        put_u16(record, 0); // comment length
+        put_u16(record, 0); // disk number
+        put_u16(record, 0); // internal attributes
+        put_u32(record, 0); // external attributes
+        put_u32(record, header_offset);
+        record.extend_from_slice(entry.name.as_bytes());
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    fn finish_archive(&mut self) -> io::Result<()> {
+        let directory_offset = to_u32(self.offset)?;
+        let directory_size = to_u32(self.central_directory.len() as u64)?;
+        self.pending.append(&mut self.central_directory);
+        put_u32(&mut self.pending, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u16(&mut self.pending, 0); // number of this disk
+        put_u16(&mut self.pending, 0); // disk with the central directory
+        put_u16(&mut self.pending, self.entry_count);
+        put_u16(&mut self.pending, self.entry_count);
+        put_u32(&mut self.pending, directory_size);
+        put_u32(&mut self.pending, directory_offset);
+        put_u16(&mut self.pending, 0); // comment length
+        Ok(())
+    }
+}
+
+impl Read for ZipStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending_pos >= self.pending.len() {
+            if !self.advance()? {
+                return Ok(0);
+            }
+        }
+        let available = &self.pending[self.pending_pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pending_pos += len;
+        Ok(len)
+    }
+}
+
+/// The CRC-32 checksum (IEEE polynomial), as used by ZIP and gzip.
+pub(crate) struct Crc32 {
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { value: 0xffffffff }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.value ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.value & 1).wrapping_neg();
+                self.value = (self.value >> 1) ^ (0xedb88320 & mask);
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.value
+    }
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn to_u32(value: u64) -> io::Result<u32> {
+    u32::try_from(value)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "zip archive exceeds 4 GiB"))
+}
+
+fn name_length(name: &str) -> io::Result<u16> {
+    u16::try_from(name.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "zip entry name exceeds 65535 bytes",
+        )
+    })
+}
+
+/// Converts a timestamp to the MS-DOS (time, date) pair used by ZIP, in UTC.
+fn dos_date_time(time: SystemTime) -> (u16, u16) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let secs_of_day = secs % 86400;
+    let time =
+        ((secs_of_day / 3600) << 11) | ((secs_of_day % 3600 / 60) << 5) | (secs_of_day % 60 / 2);
+    let date = (((year - 1980) as u64) << 9) | ((month as u64) << 5) | day as u64;
+    (time as u16, date as u16)
+}
+
+/// Reads the entries of an archive through its central directory, returning
+/// the name and content of each file. Only supports what [ZipStream] writes.
+#[cfg(test)]
+pub(crate) fn read_entries(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let u16_at = |at: usize| u16::from_le_bytes([archive[at], archive[at + 1]]) as usize;
+    let u32_at = |at: usize| {
+        u32::from_le_bytes([
+            archive[at],
+            archive[at + 1],
+            archive[at + 2],
+            archive[at + 3],
+        ]) as usize
+    };
+    let end = archive.len() - 22;
+    assert_eq!(u32_at(end), END_OF_CENTRAL_DIRECTORY_SIGNATURE as usize);
+    let count = u16_at(end + 10);
+    let mut at = u32_at(end + 16);
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        assert_eq!(u32_at(at), CENTRAL_HEADER_SIGNATURE as usize);
+        let crc = u32_at(at + 16) as u32;
+        let size = u32_at(at + 24);
+        let name_len = u16_at(at + 28);
+        let local = u32_at(at + 42);
+        let name = String::from_utf8(archive[at + 46..at + 46 + name_len].to_vec()).unwrap();
+        assert_eq!(u32_at(local), LOCAL_HEADER_SIGNATURE as usize);
+        let data_start = local + 30 + u16_at(local + 26);
+        let data = archive[data_start..data_start + size].to_vec();
+        let mut check = Crc32::new();
+        check.update(&data);
+        assert_eq!(check.finish(), crc);
+        entries.push((name, data));
+        at += 46 + name_len;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_zip_stream() {
+        let dir = std::env::temp_dir().join(format!("rfs-zip-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "first file").unwrap();
+        std::fs::write(dir.join("b.bin"), [0u8, 255, 1, 2]).unwrap();
+        std::fs::write(dir.join("empty"), "").unwrap();
+
+        let mut archive = Vec::new();
+        ZipStream::new(vec![
+            ZipEntry {
+                name: "a.txt".into(),
+                path: dir.join("a.txt"),
+            },
+            ZipEntry {
+                name: "sub/b.bin".into(),
+                path: dir.join("b.bin"),
+            },
+            ZipEntry {
+                name: "empty".into(),
+                path: dir.join("empty"),
+            },
+        ])
+        .read_to_end(&mut archive)
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let entries = read_entries(&archive);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], ("a.txt".to_string(), b"first file".to_vec()));
+        assert_eq!(entries[1], ("sub/b.bin".to_string(), vec![0, 255, 1, 2]));
+        assert_eq!(entries[2], ("empty".to_string(), vec![]));
+    }
+
+    #[test]
+    fn test_zip_stream_limits() {
+        let path = std::env::temp_dir().join(format!("rfs-zip-limits-{}", std::process::id()));
+        std::fs::write(&path, "x").unwrap();
+
+        let mut stream = ZipStream::new(vec![ZipEntry {
+            name: "a".repeat(usize::from(u16::MAX) + 1),
+            path: path.clone(),
+        }]);
+        let err = stream.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "zip entry name exceeds 65535 bytes");
+
+        let mut stream = ZipStream::new(vec![ZipEntry {
+            name: "one too many".into(),
+            path: path.clone(),
+        }]);
+        stream.entry_count = u16::MAX;
+        let err = stream.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "zip archive exceeds 65535 entries");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_empty_zip_stream() {
+        let mut archive = Vec::new();
+        ZipStream::new(vec![]).read_to_end(&mut archive).unwrap();
+        assert_eq!(archive.len(), 22);
+        assert!(read_entries(&archive).is_empty());
+    }
+}